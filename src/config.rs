@@ -4,6 +4,8 @@ use std::fs;
 use std::io::{Read, Write};
 use std::path::PathBuf;
 
+use crate::query::EntryOrder;
+
 const CONFIG_FILE: &str = "config.toml";
 
 macro_rules! vec_of_strings {
@@ -21,6 +23,12 @@ pub struct Config {
     pub hgignore: Option<bool>,
     pub dockerignore: Option<bool>,
     pub is_zip_archive: Option<Vec<String>>,
+    #[serde(default)]
+    pub is_tar_archive: Option<Vec<String>>,
+    #[serde(default)]
+    pub is_7z_archive: Option<Vec<String>>,
+    #[serde(default)]
+    pub is_rar_archive: Option<Vec<String>>,
     pub is_archive: Option<Vec<String>>,
     pub is_audio: Option<Vec<String>>,
     pub is_book: Option<Vec<String>>,
@@ -36,6 +44,22 @@ pub struct Config {
     pub everything: Option<bool>,
     #[serde(default)]
     pub plocate: Option<bool>,
+    #[serde(default)]
+    pub entry_order: Option<EntryOrder>,
+    #[serde(default)]
+    pub unique: Option<bool>,
+    #[serde(default)]
+    pub hash_cache: Option<bool>,
+    #[serde(default)]
+    pub progress: Option<bool>,
+    #[serde(default)]
+    pub timeout: Option<u64>,
+    #[serde(default)]
+    pub max_files: Option<u64>,
+    #[serde(default)]
+    pub max_depth_default: Option<u32>,
+    #[serde(default)]
+    pub max_memory: Option<String>,
     #[serde(skip_serializing, default = "get_false")]
     pub debug: bool,
     #[serde(skip)]
@@ -128,6 +152,11 @@ impl Config {
             hgignore: Some(false),
             dockerignore: Some(false),
             is_zip_archive: vec_of_strings![".zip", ".jar", ".war", ".ear"],
+            is_tar_archive: vec_of_strings![
+                ".tar", ".tar.gz", ".tgz", ".tar.bz2", ".tbz", ".tbz2", ".tar.xz", ".txz", ".tar.zst", ".tzst"
+            ],
+            is_7z_archive: vec_of_strings![".7z"],
+            is_rar_archive: vec_of_strings![".rar"],
             is_archive: vec_of_strings![
                 ".7z", ".bz2", ".bzip2", ".gz", ".gzip", ".lz", ".rar", ".tar", ".xz", ".zip"
             ],
@@ -166,6 +195,14 @@ impl Config {
             check_for_updates: Some(false),
             everything: Some(false),
             plocate: Some(false),
+            entry_order: Some(EntryOrder::Unsorted),
+            unique: Some(false),
+            hash_cache: Some(true),
+            progress: Some(false),
+            timeout: Some(0),
+            max_files: Some(0),
+            max_depth_default: Some(0),
+            max_memory: Some(String::new()),
             debug: false,
             save: true,
         }
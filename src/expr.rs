@@ -569,6 +569,20 @@ impl Expr {
         Ok(())
     }
 
+    /// Whether a field or function of the expression reads the content of the file.
+    /// Subqueries are left out, as they read their own files.
+    pub fn reads_content(&self) -> bool {
+        if self.field.is_some_and(|field| field.reads_content())
+            || self.function.as_ref().is_some_and(|function| function.reads_content())
+        {
+            return true;
+        }
+
+        self.left.as_ref().is_some_and(|left| left.reads_content())
+            || self.right.as_ref().is_some_and(|right| right.reads_content())
+            || self.args.iter().flatten().any(|arg| arg.reads_content())
+    }
+
     pub fn contains_colorized(&self) -> bool {
         Self::contains_colorized_field(self)
     }
@@ -0,0 +1,112 @@
+use crate::field::content_handlers::check_extension;
+use crate::field::context::FieldContext;
+use crate::fileinfo::{ArchiveFormat, NESTED_ARCHIVE_SEPARATOR};
+use crate::util::*;
+use crate::util::error::SearchError;
+
+pub fn handle_archive_path(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
+    match ctx.file_info {
+        Some(file_info) => {
+            let mut archive_path = match ctx.entry.path().strip_prefix(ctx.root_path) {
+                Ok(stripped_path) => stripped_path.to_string_lossy().to_string(),
+                Err(_) => ctx.entry.path().to_string_lossy().to_string(),
+            };
+            if let Some((nested_archives, _)) = file_info.name.rsplit_once(NESTED_ARCHIVE_SEPARATOR) {
+                archive_path.push_str(NESTED_ARCHIVE_SEPARATOR);
+                archive_path.push_str(nested_archives);
+            }
+            Ok(Variant::from_string(&archive_path))
+        }
+        _ => Ok(Variant::empty(VariantType::String)),
+    }
+}
+
+pub fn handle_entry_path(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
+    match ctx.file_info {
+        Some(file_info) => {
+            let entry_path = match file_info.name.rsplit_once(NESTED_ARCHIVE_SEPARATOR) {
+                Some((_, entry_path)) => entry_path,
+                None => &file_info.name,
+            };
+            Ok(Variant::from_string(&entry_path.to_string()))
+        }
+        _ => Ok(Variant::empty(VariantType::String)),
+    }
+}
+
+pub fn handle_compressed_size(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
+    if let Some(file_info) = ctx.file_info
+        && let Some(compressed_size) = file_info.compressed_size {
+            return Ok(Variant::from_int(compressed_size as i64));
+        }
+    Ok(Variant::empty(VariantType::Int))
+}
+
+pub fn handle_compression_ratio(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
+    if let Some(file_info) = ctx.file_info
+        && let Some(compressed_size) = file_info.compressed_size
+            && compressed_size > 0 {
+                return Ok(Variant::from_float(file_info.size as f64 / compressed_size as f64));
+            }
+    Ok(Variant::empty(VariantType::Float))
+}
+
+pub fn handle_compression_method(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
+    if let Some(file_info) = ctx.file_info
+        && let Some(ref compression_method) = file_info.compression_method {
+            return Ok(Variant::from_string(compression_method));
+        }
+    Ok(Variant::empty(VariantType::String))
+}
+
+pub fn handle_is_encrypted(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
+    if let Some(file_info) = ctx.file_info
+        && let Some(encrypted) = file_info.encrypted {
+            return Ok(Variant::from_bool(encrypted));
+        }
+    Ok(Variant::empty(VariantType::Bool))
+}
+
+pub fn handle_archive_entry_count(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
+    if let Some(format) = get_archive_format(ctx) {
+        ctx.fms.update_archive_summary(ctx.entry, format);
+        if let Some(summary) = ctx.fms.get_archive_summary() {
+            return Ok(Variant::from_int(summary.entry_count as i64));
+        }
+    }
+    Ok(Variant::empty(VariantType::Int))
+}
+
+pub fn handle_archive_uncompressed_size(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
+    if let Some(format) = get_archive_format(ctx) {
+        ctx.fms.update_archive_summary(ctx.entry, format);
+        if let Some(summary) = ctx.fms.get_archive_summary() {
+            return Ok(Variant::from_int(summary.uncompressed_size as i64));
+        }
+    }
+    Ok(Variant::empty(VariantType::Int))
+}
+
+/// Tells the format of an archive by the extensions the search opens it by.
+fn get_archive_format(ctx: &FieldContext) -> Option<ArchiveFormat> {
+    let name = ctx.entry.file_name().to_string_lossy().to_string();
+    let (config, default_config) = (ctx.config, ctx.default_config);
+
+    #[cfg(feature = "sevenz")]
+    if check_extension(&name, &config.is_7z_archive, &default_config.is_7z_archive) {
+        return Some(ArchiveFormat::SevenZ);
+    }
+
+    #[cfg(feature = "rar")]
+    if check_extension(&name, &config.is_rar_archive, &default_config.is_rar_archive) {
+        return Some(ArchiveFormat::Rar);
+    }
+
+    if check_extension(&name, &config.is_zip_archive, &default_config.is_zip_archive) {
+        Some(ArchiveFormat::Zip)
+    } else if check_extension(&name, &config.is_tar_archive, &default_config.is_tar_archive) {
+        Some(ArchiveFormat::Tar)
+    } else {
+        None
+    }
+}
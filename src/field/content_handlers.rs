@@ -4,7 +4,7 @@ use crate::util::*;
 use crate::util::error::SearchError;
 
 pub fn handle_line_count(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
-    ctx.fms.update_line_count(ctx.entry);
+    ctx.fms.update_line_count(ctx.entry, ctx.file_info, ctx.decompress);
     if let Some(line_count) = ctx.fms.get_line_count() {
         return Ok(Variant::from_int(line_count as i64));
     }
@@ -12,7 +12,7 @@ pub fn handle_line_count(ctx: &mut FieldContext) -> Result<Variant, SearchError>
 }
 
 pub fn handle_word_count(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
-    ctx.fms.update_content_stats(ctx.entry);
+    ctx.fms.update_content_stats(ctx.entry, ctx.file_info, ctx.decompress);
     if let Some(stats) = ctx.fms.get_content_stats()
         && stats.is_text {
             return Ok(Variant::from_int(stats.word_count as i64));
@@ -21,7 +21,7 @@ pub fn handle_word_count(ctx: &mut FieldContext) -> Result<Variant, SearchError>
 }
 
 pub fn handle_char_count(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
-    ctx.fms.update_content_stats(ctx.entry);
+    ctx.fms.update_content_stats(ctx.entry, ctx.file_info, ctx.decompress);
     if let Some(stats) = ctx.fms.get_content_stats()
         && stats.is_text {
             return Ok(Variant::from_int(stats.char_count as i64));
@@ -30,7 +30,7 @@ pub fn handle_char_count(ctx: &mut FieldContext) -> Result<Variant, SearchError>
 }
 
 pub fn handle_encoding(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
-    ctx.fms.update_content_stats(ctx.entry);
+    ctx.fms.update_content_stats(ctx.entry, ctx.file_info, ctx.decompress);
     if let Some(stats) = ctx.fms.get_content_stats()
         && stats.is_text {
             return Ok(Variant::from_string(&stats.encoding));
@@ -39,14 +39,20 @@ pub fn handle_encoding(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
 }
 
 pub fn handle_has_bom(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
+    if ctx.file_info.is_some() || ctx.decompress {
+        ctx.fms.update_content_stats(ctx.entry, ctx.file_info, ctx.decompress);
+    }
     if let Some(stats) = ctx.fms.get_content_stats() {
         return Ok(Variant::from_bool(stats.has_bom));
     }
+    if ctx.file_info.is_some() || ctx.decompress {
+        return Ok(Variant::from_bool(false));
+    }
     Ok(Variant::from_bool(has_bom(ctx.entry)))
 }
 
 pub fn handle_line_ending(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
-    ctx.fms.update_content_stats(ctx.entry);
+    ctx.fms.update_content_stats(ctx.entry, ctx.file_info, ctx.decompress);
     if let Some(stats) = ctx.fms.get_content_stats()
         && stats.is_text {
             return Ok(Variant::from_string(&stats.line_ending));
@@ -55,21 +61,35 @@ pub fn handle_line_ending(ctx: &mut FieldContext) -> Result<Variant, SearchError
 }
 
 pub fn handle_mime(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
-    ctx.fms.update_mime_type(ctx.entry);
+    ctx.fms.update_mime_type(ctx.entry, ctx.file_info);
     if let Some(mime) = ctx.fms.get_mime_type() {
         return Ok(Variant::from_string(&String::from(mime)));
     }
     Ok(Variant::empty(VariantType::String))
 }
 
+pub fn handle_uncompressed_size(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
+    ctx.fms.update_uncompressed_size(ctx.entry);
+    if let Some(size) = ctx.fms.get_uncompressed_size() {
+        return Ok(Variant::from_int(size as i64));
+    }
+    Ok(Variant::empty(VariantType::Int))
+}
+
 pub fn handle_is_binary_or_text(ctx: &mut FieldContext, field: &Field) -> Result<Variant, SearchError> {
-    ctx.fms.update_file_metadata(ctx.entry, ctx.follow_symlinks);
-    if let Some(meta) = ctx.fms.get_file_metadata()
-        && meta.is_dir() {
-            return Ok(Variant::from_bool(false));
+    match ctx.file_info {
+        Some(file_info) if file_info.name.ends_with('/') => return Ok(Variant::from_bool(false)),
+        Some(_) => {}
+        None => {
+            ctx.fms.update_file_metadata(ctx.entry, ctx.follow_symlinks);
+            if let Some(meta) = ctx.fms.get_file_metadata()
+                && meta.is_dir() {
+                    return Ok(Variant::from_bool(false));
+                }
         }
+    }
 
-    ctx.fms.update_mime_type(ctx.entry);
+    ctx.fms.update_mime_type(ctx.entry, ctx.file_info);
     if let Some(mime) = ctx.fms.get_mime_type() {
         let is_text = is_text_mime(mime);
         let result = matches!(field, Field::IsText) == is_text;
@@ -99,7 +119,7 @@ pub fn handle_is_type(ctx: &mut FieldContext, field: &Field) -> Result<Variant,
     Ok(Variant::from_bool(result))
 }
 
-fn check_extension(
+pub(crate) fn check_extension(
     file_name: &str,
     config_ext: &Option<Vec<String>>,
     default_ext: &Option<Vec<String>>,
@@ -119,12 +139,15 @@ mod tests {
 
     use super::*;
 
-    fn test_field(entry: &fs::DirEntry, root_path: &Path, field: &Field) -> Variant {
+    fn test_field(entry: &DirEntry, root_path: &Path, field: &Field) -> Variant {
         let config = Config::default();
         let default_config = Config::default();
         let mut fms = crate::field::context::FileMetadataState::new();
         #[cfg(feature = "git")]
         let mut git_cache = crate::util::git::GitCache::new();
+        let mut dir_size_cache = crate::util::dir_size::DirSizeCache::new();
+        let mut hash_cache = crate::util::hash_cache::HashCache::with_file(None);
+        let mut checksum_files = crate::util::checksums::ChecksumFiles::new();
         #[cfg(all(unix, feature = "users"))]
         let user_cache = uzers::UsersCache::new();
         let none_file_info = None;
@@ -135,7 +158,11 @@ mod tests {
             fms: &mut fms,
             #[cfg(feature = "git")]
             git_cache: &mut git_cache,
+            dir_size_cache: &mut dir_size_cache,
+            hash_cache: &mut hash_cache,
+            checksum_files: &mut checksum_files,
             follow_symlinks: true,
+            decompress: false,
             config: &config,
             default_config: &default_config,
             #[cfg(all(unix, feature = "users"))]
@@ -144,11 +171,12 @@ mod tests {
         crate::field::dispatch::get_field_value(&mut ctx, field).unwrap()
     }
 
-    fn entry_for(dir: &Path, name: &str) -> fs::DirEntry {
+    fn entry_for(dir: &Path, name: &str) -> DirEntry {
         fs::read_dir(dir)
             .unwrap()
             .filter_map(|e| e.ok())
             .find(|e| e.file_name() == name)
+            .map(DirEntry::from)
             .unwrap()
     }
 
@@ -1,24 +1,39 @@
 use std::collections::HashMap;
-use std::fs::{DirEntry, FileType, Metadata};
+use std::fs::Metadata;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+#[cfg(target_os = "linux")]
+use std::os::unix::io::RawFd;
 use std::path::Path;
 
 #[cfg(all(unix, feature = "users"))]
 use uzers::UsersCache;
 
 use crate::config::Config;
-use crate::fileinfo::FileInfo;
+use crate::fileinfo::{
+    buffer_archive_entry, get_archive_summary, read_archive_entry, ArchiveFormat, ArchiveReader, ArchiveSummary, FileInfo,
+};
 use crate::util::*;
 #[cfg(feature = "git")]
 use crate::util::git::GitCache;
 use crate::util::audio::{AudioInfo, get_audio_info};
+use crate::util::dir_size::DirSizeCache;
+use crate::util::checksums::ChecksumFiles;
+use crate::util::fuzzy_hash::{fuzzy_hash_file, fuzzy_hash_reader};
+use crate::util::hash_cache::HashCache;
+use crate::util::hashes::HashAlgorithm;
+use crate::util::decompress::{decompress as decompress_content, get_uncompressed_size};
 use crate::util::dimensions::get_dimensions;
+use crate::util::image_hash::{get_image_hashes, ImageHashes};
 use crate::util::duration::get_duration;
+#[cfg(target_os = "linux")]
+use crate::util::statx::{Statx, StatxError, statx_at};
 
 pub struct FileMetadataState {
     pub(crate) file_metadata: Option<Option<Metadata>>,
     pub(crate) entry_file_type: Option<Option<FileType>>,
     pub(crate) content_stats: Option<Option<ContentStats>>,
     pub(crate) dimensions: Option<Option<Dimensions>>,
+    pub(crate) image_hashes: Option<Option<ImageHashes>>,
     pub(crate) duration: Option<Option<Duration>>,
     pub(crate) audio_info: Option<Option<AudioInfo>>,
     pub(crate) exif_metadata: Option<Option<HashMap<String, String>>>,
@@ -27,6 +42,23 @@ pub struct FileMetadataState {
     pub(crate) sha256_hash: Option<String>,
     pub(crate) sha512_hash: Option<String>,
     pub(crate) sha3_hash: Option<String>,
+    pub(crate) md5_hash: Option<String>,
+    pub(crate) crc32_hash: Option<String>,
+    pub(crate) blake3_hash: Option<String>,
+    pub(crate) xxh3_hash: Option<String>,
+    pub(crate) ssdeep_hash: Option<String>,
+    pub(crate) archive_summary: Option<Option<ArchiveSummary>>,
+    pub(crate) uncompressed_size: Option<Option<u64>>,
+    /// Content of the archived file, buffered once for all the fields and functions reading it
+    pub(crate) archived_content: Option<Option<ArchiveReader>>,
+    #[cfg(target_os = "linux")]
+    pub(crate) file_stat: Option<Option<Statx>>,
+    /// Directory the current entry was read from, for `statx` relative to it
+    #[cfg(target_os = "linux")]
+    pub(crate) dir_fd: Option<RawFd>,
+    /// Attributes to request from `statx`, kept across files; zero disables it
+    #[cfg(target_os = "linux")]
+    pub(crate) statx_mask: u32,
 }
 
 impl FileMetadataState {
@@ -36,6 +68,7 @@ impl FileMetadataState {
             entry_file_type: None,
             content_stats: None,
             dimensions: None,
+            image_hashes: None,
             duration: None,
             audio_info: None,
             exif_metadata: None,
@@ -44,11 +77,31 @@ impl FileMetadataState {
             sha256_hash: None,
             sha512_hash: None,
             sha3_hash: None,
+            md5_hash: None,
+            crc32_hash: None,
+            blake3_hash: None,
+            xxh3_hash: None,
+            ssdeep_hash: None,
+            archive_summary: None,
+            uncompressed_size: None,
+            archived_content: None,
+            #[cfg(target_os = "linux")]
+            file_stat: None,
+            #[cfg(target_os = "linux")]
+            dir_fd: None,
+            #[cfg(target_os = "linux")]
+            statx_mask: 0,
         }
     }
 
     pub fn clear(&mut self) {
+        #[cfg(target_os = "linux")]
+        let statx_mask = self.statx_mask;
         *self = Self::new();
+        #[cfg(target_os = "linux")]
+        {
+            self.statx_mask = statx_mask;
+        }
     }
 
     pub fn update_file_metadata(&mut self, entry: &DirEntry, follow_symlinks: bool) {
@@ -57,6 +110,71 @@ impl FileMetadataState {
         }
     }
 
+    /// Enables the `statx` fast path for the attributes in `mask`, computed
+    /// once per query from the fields it uses.
+    #[cfg(target_os = "linux")]
+    pub fn set_statx_mask(&mut self, mask: u32) {
+        self.statx_mask = mask;
+    }
+
+    /// Seed the file descriptor of the directory the entry was read from.
+    /// The descriptor must stay open until the next `clear()`.
+    #[cfg(target_os = "linux")]
+    pub fn seed_dir_fd(&mut self, dir_fd: Option<RawFd>) {
+        self.dir_fd = dir_fd;
+    }
+
+    /// Seed the content of the archived file being checked, read while its
+    /// archive was being listed, so that it isn't looked up in the archive again.
+    pub fn seed_archived_content(&mut self, content: Option<ArchiveReader>) {
+        if content.is_some() {
+            self.archived_content = Some(content);
+        }
+    }
+
+    /// Takes the buffered content of the archived file back, e.g. to search
+    /// it as a nested archive once its fields are evaluated.
+    pub fn take_archived_content(&mut self) -> Option<ArchiveReader> {
+        let mut content = self.archived_content.take().flatten()?;
+        content.rewind().ok()?;
+        Some(content)
+    }
+
+    /// Streams the content of the archive entry to `f`, see `read_archive_entry_content`.
+    pub fn read_archive_entry<T>(
+        &mut self,
+        entry: &DirEntry,
+        file_info: &FileInfo,
+        f: impl FnOnce(&mut dyn Read, u64) -> Option<T>,
+    ) -> Option<T> {
+        read_archive_entry_content(&mut self.archived_content, entry, file_info, f)
+    }
+
+    /// Attributes of the entry from a single `statx` call relative to its
+    /// directory, if the fast path is enabled and covers all of `mask`.
+    /// `None` tells the caller to read the full metadata instead, which is
+    /// also preferred once it has been loaded anyway.
+    #[cfg(target_os = "linux")]
+    pub fn get_file_stat(&mut self, entry: &DirEntry, follow_symlinks: bool, mask: u32) -> Option<Statx> {
+        if self.statx_mask & mask != mask || self.file_metadata.is_some() {
+            return None;
+        }
+        let dir_fd = self.dir_fd?;
+
+        if self.file_stat.is_none() {
+            self.file_stat = match statx_at(dir_fd, &entry.file_name(), follow_symlinks, self.statx_mask) {
+                Ok(stat) => Some(Some(stat)),
+                Err(StatxError::Unsupported) => {
+                    self.statx_mask = 0;
+                    Some(None)
+                }
+                Err(StatxError::Failed) => Some(None),
+            };
+        }
+
+        self.file_stat.flatten().filter(|stat| stat.has(mask))
+    }
+
     pub fn get_file_metadata(&self) -> Option<&Metadata> {
         self.file_metadata.as_ref().and_then(|o| o.as_ref())
     }
@@ -93,21 +211,30 @@ impl FileMetadataState {
         self.entry_file_type.flatten()
     }
 
-    pub fn update_line_count(&mut self, entry: &DirEntry) {
+    pub fn update_line_count(&mut self, entry: &DirEntry, file_info: &Option<FileInfo>, decompress: bool) {
         // Always derived from the content-stats pass: a raw newline-byte scan
         // disagrees with the decoded text for UTF-16/32 files (0x0A occurs
         // inside multibyte code units), which made the reported count depend
         // on which content field happened to be evaluated first.
-        self.update_content_stats(entry);
+        self.update_content_stats(entry, file_info, decompress);
     }
 
     pub fn get_line_count(&self) -> Option<usize> {
         self.get_content_stats().map(|stats| stats.line_count)
     }
 
-    pub fn update_content_stats(&mut self, entry: &DirEntry) {
+    pub fn update_content_stats(&mut self, entry: &DirEntry, file_info: &Option<FileInfo>, decompress: bool) {
         if self.content_stats.is_none() {
-            self.content_stats = Some(get_content_stats(entry));
+            let archived_content = &mut self.archived_content;
+            self.content_stats = Some(match file_info {
+                Some(file_info) if decompress => read_archived_file(archived_content, entry, file_info, |reader, _| {
+                    content_stats_from_reader(decompress_content(BufReader::new(reader)).ok()?)
+                }),
+                Some(file_info) => {
+                    read_archived_file(archived_content, entry, file_info, |reader, _| content_stats_from_reader(reader))
+                }
+                None => get_content_stats(entry, decompress),
+            });
         }
     }
 
@@ -142,11 +269,17 @@ impl FileMetadataState {
             .map(Variant::from_string)
     }
 
-    pub fn update_mime_type(&mut self, entry: &DirEntry) {
+    pub fn update_mime_type(&mut self, entry: &DirEntry, file_info: &Option<FileInfo>) {
         if self.mime_type.is_none() {
-            self.mime_type = Some(
-                tree_magic_mini::from_filepath(&entry.path()).map(String::from)
-            );
+            self.mime_type = Some(match file_info {
+                Some(file_info) => read_archived_file(&mut self.archived_content, entry, file_info, |reader, _| {
+                    // As much as tree_magic looks at when sniffing a file on disk
+                    let mut head = Vec::with_capacity(MIME_SNIFF_LEN);
+                    reader.take(MIME_SNIFF_LEN as u64).read_to_end(&mut head).ok()?;
+                    Some(String::from(tree_magic_mini::from_u8(&head)))
+                }),
+                None => tree_magic_mini::from_filepath(&entry.path()).map(String::from),
+            });
         }
     }
 
@@ -154,32 +287,72 @@ impl FileMetadataState {
         self.mime_type.as_ref().and_then(|o| o.as_deref())
     }
 
-    pub fn get_or_compute_sha1(&mut self, entry: &DirEntry) -> &str {
-        if self.sha1_hash.is_none() {
-            self.sha1_hash = Some(get_sha1_file_hash(entry));
-        }
-        self.sha1_hash.as_deref().unwrap()
+    /// The file's digest with the given algorithm, computed once and memoised.
+    /// Archive entries are read from the archive, files go through the hash cache.
+    pub fn get_or_compute_hash(
+        &mut self,
+        algorithm: HashAlgorithm,
+        entry: &DirEntry,
+        file_info: &Option<FileInfo>,
+        hash_cache: &mut HashCache,
+    ) -> &str {
+        let slot = match algorithm {
+            HashAlgorithm::Sha1 => &mut self.sha1_hash,
+            HashAlgorithm::Sha256 => &mut self.sha256_hash,
+            HashAlgorithm::Sha512 => &mut self.sha512_hash,
+            HashAlgorithm::Sha3 => &mut self.sha3_hash,
+            HashAlgorithm::Md5 => &mut self.md5_hash,
+            HashAlgorithm::Crc32 => &mut self.crc32_hash,
+            HashAlgorithm::Blake3 => &mut self.blake3_hash,
+            HashAlgorithm::Xxh3 => &mut self.xxh3_hash,
+        };
+
+        slot.get_or_insert_with(|| match file_info {
+            // Archives keep the checksum of their entries
+            Some(FileInfo { crc32: Some(crc32), .. }) if algorithm == HashAlgorithm::Crc32 => format!("{:08x}", crc32),
+            Some(file_info) => read_archive_entry_content(&mut self.archived_content, entry, file_info, |reader, _| {
+                algorithm.hash_reader(reader)
+            })
+            .unwrap_or_default(),
+            None => hash_cache.get_or_compute(algorithm.name(), &entry.path(), |path| algorithm.hash_file(path)),
+        })
     }
 
-    pub fn get_or_compute_sha256(&mut self, entry: &DirEntry) -> &str {
-        if self.sha256_hash.is_none() {
-            self.sha256_hash = Some(get_sha256_file_hash(entry));
-        }
-        self.sha256_hash.as_deref().unwrap()
+    /// The file's fuzzy hash, computed once and memoised. Archive entries are
+    /// read from the archive, files go through the hash cache.
+    pub fn get_or_compute_ssdeep(
+        &mut self,
+        entry: &DirEntry,
+        file_info: &Option<FileInfo>,
+        hash_cache: &mut HashCache,
+    ) -> &str {
+        self.ssdeep_hash.get_or_insert_with(|| match file_info {
+            Some(file_info) => {
+                read_archived_file(&mut self.archived_content, entry, file_info, |reader, size| fuzzy_hash_reader(reader, size))
+                    .unwrap_or_default()
+            }
+            None => hash_cache.get_or_compute("ssdeep", &entry.path(), fuzzy_hash_file),
+        })
     }
 
-    pub fn get_or_compute_sha512(&mut self, entry: &DirEntry) -> &str {
-        if self.sha512_hash.is_none() {
-            self.sha512_hash = Some(get_sha512_file_hash(entry));
+    pub fn update_archive_summary(&mut self, entry: &DirEntry, format: ArchiveFormat) {
+        if self.archive_summary.is_none() {
+            self.archive_summary = Some(get_archive_summary(&entry.path(), format));
         }
-        self.sha512_hash.as_deref().unwrap()
     }
 
-    pub fn get_or_compute_sha3(&mut self, entry: &DirEntry) -> &str {
-        if self.sha3_hash.is_none() {
-            self.sha3_hash = Some(get_sha3_512_file_hash(entry));
+    pub fn get_archive_summary(&self) -> Option<&ArchiveSummary> {
+        self.archive_summary.as_ref().and_then(|o| o.as_ref())
+    }
+
+    pub fn update_uncompressed_size(&mut self, entry: &DirEntry) {
+        if self.uncompressed_size.is_none() {
+            self.uncompressed_size = Some(get_uncompressed_size(&entry.path()));
         }
-        self.sha3_hash.as_deref().unwrap()
+    }
+
+    pub fn get_uncompressed_size(&self) -> Option<u64> {
+        self.uncompressed_size.flatten()
     }
 
     pub fn update_dimensions(&mut self, entry: &DirEntry) {
@@ -192,6 +365,16 @@ impl FileMetadataState {
         self.dimensions.as_ref().and_then(|o| o.as_ref())
     }
 
+    pub fn update_image_hashes(&mut self, entry: &DirEntry) {
+        if self.image_hashes.is_none() {
+            self.image_hashes = Some(get_image_hashes(&entry.path()));
+        }
+    }
+
+    pub fn get_image_hashes(&self) -> Option<&ImageHashes> {
+        self.image_hashes.as_ref().and_then(|o| o.as_ref())
+    }
+
     pub fn update_duration(&mut self, entry: &DirEntry) {
         if self.duration.is_none() {
             // Audio durations come from lofty (via the cached audio info);
@@ -211,6 +394,43 @@ impl FileMetadataState {
     }
 }
 
+/// Bytes of an archived file looked at to tell its MIME type
+const MIME_SNIFF_LEN: usize = 2048;
+
+/// Streams the content of a file entry of the archive to `f`. Directory
+/// entries have no content.
+fn read_archived_file<T>(
+    archived_content: &mut Option<Option<ArchiveReader>>,
+    entry: &DirEntry,
+    file_info: &FileInfo,
+    f: impl FnOnce(&mut dyn Read, u64) -> Option<T>,
+) -> Option<T> {
+    if file_info.name.ends_with('/') {
+        return None;
+    }
+    read_archive_entry_content(archived_content, entry, file_info, f)
+}
+
+/// Streams the content of the archive entry to `f`. The entry is looked up in
+/// the archive only the first time, as that means reading through a tar or 7z
+/// archive, and buffering the archives it's nested in.
+fn read_archive_entry_content<T>(
+    archived_content: &mut Option<Option<ArchiveReader>>,
+    entry: &DirEntry,
+    file_info: &FileInfo,
+    f: impl FnOnce(&mut dyn Read, u64) -> Option<T>,
+) -> Option<T> {
+    let content = archived_content
+        .get_or_insert_with(|| {
+            read_archive_entry(&entry.path(), &file_info.name, |reader, size| buffer_archive_entry(reader, size).ok())
+        })
+        .as_mut()?;
+
+    let size = content.seek(SeekFrom::End(0)).ok()?;
+    content.rewind().ok()?;
+    f(content, size)
+}
+
 pub struct FieldContext<'a> {
     pub entry: &'a DirEntry,
     pub file_info: &'a Option<FileInfo>,
@@ -218,7 +438,11 @@ pub struct FieldContext<'a> {
     pub fms: &'a mut FileMetadataState,
     #[cfg(feature = "git")]
     pub git_cache: &'a mut GitCache,
+    pub dir_size_cache: &'a mut DirSizeCache,
+    pub hash_cache: &'a mut HashCache,
+    pub checksum_files: &'a mut ChecksumFiles,
     pub follow_symlinks: bool,
+    pub decompress: bool,
     pub config: &'a Config,
     pub default_config: &'a Config,
     #[cfg(all(unix, feature = "users"))]
@@ -237,6 +461,7 @@ mod tests {
         assert!(state.entry_file_type.is_none());
         assert!(state.content_stats.is_none());
         assert!(state.dimensions.is_none());
+        assert!(state.image_hashes.is_none());
         assert!(state.duration.is_none());
         assert!(state.audio_info.is_none());
         assert!(state.exif_metadata.is_none());
@@ -245,6 +470,20 @@ mod tests {
         assert!(state.sha256_hash.is_none());
         assert!(state.sha512_hash.is_none());
         assert!(state.sha3_hash.is_none());
+        assert!(state.md5_hash.is_none());
+        assert!(state.crc32_hash.is_none());
+        assert!(state.blake3_hash.is_none());
+        assert!(state.xxh3_hash.is_none());
+        assert!(state.ssdeep_hash.is_none());
+        assert!(state.archive_summary.is_none());
+        assert!(state.uncompressed_size.is_none());
+        assert!(state.archived_content.is_none());
+        #[cfg(target_os = "linux")]
+        {
+            assert!(state.file_stat.is_none());
+            assert!(state.dir_fd.is_none());
+            assert_eq!(state.statx_mask, 0);
+        }
     }
 
     #[test]
@@ -255,6 +494,7 @@ mod tests {
         state.entry_file_type = Some(None);
         state.content_stats = Some(None);
         state.dimensions = Some(None);
+        state.image_hashes = Some(None);
         state.duration = Some(None);
         state.audio_info = Some(None);
         state.exif_metadata = Some(None);
@@ -263,6 +503,19 @@ mod tests {
         state.sha256_hash = Some(String::new());
         state.sha512_hash = Some(String::new());
         state.sha3_hash = Some(String::new());
+        state.md5_hash = Some(String::new());
+        state.crc32_hash = Some(String::new());
+        state.blake3_hash = Some(String::new());
+        state.xxh3_hash = Some(String::new());
+        state.ssdeep_hash = Some(String::new());
+        state.uncompressed_size = Some(Some(1));
+        state.archived_content = Some(None);
+        #[cfg(target_os = "linux")]
+        {
+            state.file_stat = Some(None);
+            state.dir_fd = Some(3);
+            state.statx_mask = crate::util::statx::STATX_SIZE;
+        }
 
         state.clear();
 
@@ -270,6 +523,7 @@ mod tests {
         assert!(state.entry_file_type.is_none());
         assert!(state.content_stats.is_none());
         assert!(state.dimensions.is_none());
+        assert!(state.image_hashes.is_none());
         assert!(state.duration.is_none());
         assert!(state.audio_info.is_none());
         assert!(state.exif_metadata.is_none());
@@ -278,5 +532,46 @@ mod tests {
         assert!(state.sha256_hash.is_none());
         assert!(state.sha512_hash.is_none());
         assert!(state.sha3_hash.is_none());
+        assert!(state.md5_hash.is_none());
+        assert!(state.crc32_hash.is_none());
+        assert!(state.blake3_hash.is_none());
+        assert!(state.xxh3_hash.is_none());
+        assert!(state.ssdeep_hash.is_none());
+        assert!(state.uncompressed_size.is_none());
+        assert!(state.archived_content.is_none());
+        #[cfg(target_os = "linux")]
+        {
+            assert!(state.file_stat.is_none());
+            assert!(state.dir_fd.is_none());
+            // The query-wide mask survives moving to the next file
+            assert_eq!(state.statx_mask, crate::util::statx::STATX_SIZE);
+        }
+    }
+
+    #[test]
+    fn test_seeded_archived_content() {
+        // The archive doesn't exist, so the content can only come from the seed
+        let entry = DirEntry::from_path(std::env::temp_dir().join("fselect_test_missing_archive.zip"));
+        let file_info = Some(FileInfo { name: String::from("logs/app.log"), ..Default::default() });
+        let mut hash_cache = HashCache::new(false);
+
+        let mut state = FileMetadataState::new();
+        state.seed_archived_content(Some(Box::new(std::io::Cursor::new(b"one\ntwo\n".to_vec()))));
+
+        state.update_content_stats(&entry, &file_info, false);
+        assert_eq!(state.get_line_count(), Some(2));
+        assert_eq!(
+            state.get_or_compute_hash(HashAlgorithm::Sha1, &entry, &file_info, &mut hash_cache),
+            "c708d7ef841f7e1748436b8ef5670d0b2de1a227"
+        );
+        assert_eq!(state.read_archive_entry(&entry, file_info.as_ref().unwrap(), |_, size| Some(size)), Some(8));
+
+        let mut content = String::new();
+        state.take_archived_content().unwrap().read_to_string(&mut content).unwrap();
+        assert_eq!(content, "one\ntwo\n");
+
+        state.clear();
+        state.update_content_stats(&entry, &file_info, false);
+        assert!(state.get_content_stats().is_none());
     }
 }
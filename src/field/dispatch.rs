@@ -1,7 +1,7 @@
 use crate::field::Field;
 use crate::field::context::FieldContext;
 use crate::field::{
-    content_handlers, exif_handlers, git_handlers, hash_handlers, media_handlers,
+    archive_handlers, content_handlers, exif_handlers, git_handlers, hash_handlers, media_handlers,
     metadata_handlers, mode_handlers, path_handlers,
 };
 use crate::util::*;
@@ -22,9 +22,23 @@ pub fn get_field_value(ctx: &mut FieldContext, field: &Field) -> Result<Variant,
         Field::Directory => path_handlers::handle_directory(ctx),
         Field::AbsDir => path_handlers::handle_absdir(ctx),
 
+        // Archive entries and archives
+        Field::ArchivePath => archive_handlers::handle_archive_path(ctx),
+        Field::EntryPath => archive_handlers::handle_entry_path(ctx),
+        Field::CompressedSize => archive_handlers::handle_compressed_size(ctx),
+        Field::CompressionRatio => archive_handlers::handle_compression_ratio(ctx),
+        Field::CompressionMethod => archive_handlers::handle_compression_method(ctx),
+        Field::IsEncrypted => archive_handlers::handle_is_encrypted(ctx),
+        Field::ArchiveEntryCount => archive_handlers::handle_archive_entry_count(ctx),
+        Field::ArchiveUncompressedSize => archive_handlers::handle_archive_uncompressed_size(ctx),
+
         // Size / type metadata
         Field::Size => metadata_handlers::handle_size(ctx),
         Field::FormattedSize => metadata_handlers::handle_formatted_size(ctx),
+        Field::AllocatedSize => metadata_handlers::handle_allocated_size(ctx),
+        Field::DirSize => metadata_handlers::handle_dir_size(ctx),
+        Field::DirDiskUsage => metadata_handlers::handle_dir_disk_usage(ctx),
+        Field::UncompressedSize => content_handlers::handle_uncompressed_size(ctx),
         Field::IsDir => metadata_handlers::handle_is_dir(ctx),
         Field::IsFile => metadata_handlers::handle_is_file(ctx),
         Field::IsSymlink => metadata_handlers::handle_is_symlink(ctx),
@@ -55,6 +69,9 @@ pub fn get_field_value(ctx: &mut FieldContext, field: &Field) -> Result<Variant,
         Field::XattrCount => metadata_handlers::handle_xattr_count(ctx),
         Field::Extattrs => metadata_handlers::handle_extattrs(ctx),
         Field::HasExtattrs => metadata_handlers::handle_has_extattrs(ctx),
+        Field::IsSparse => metadata_handlers::handle_is_sparse(ctx),
+        Field::HoleBytes => metadata_handlers::handle_hole_bytes(ctx),
+        Field::ExtentCount => metadata_handlers::handle_extent_count(ctx),
         Field::Acl => metadata_handlers::handle_acl(ctx),
         Field::HasAcl => metadata_handlers::handle_has_acl(ctx),
         Field::DefaultAcl => metadata_handlers::handle_default_acl(ctx),
@@ -107,6 +124,7 @@ pub fn get_field_value(ctx: &mut FieldContext, field: &Field) -> Result<Variant,
         // Media (dimensions, audio)
         Field::Width => media_handlers::handle_width(ctx),
         Field::Height => media_handlers::handle_height(ctx),
+        Field::Phash | Field::Dhash => media_handlers::handle_image_hash(ctx, field),
         Field::Duration => media_handlers::handle_duration(ctx),
         Field::Bitrate => media_handlers::handle_bitrate(ctx),
         Field::Freq => media_handlers::handle_freq(ctx),
@@ -184,5 +202,11 @@ pub fn get_field_value(ctx: &mut FieldContext, field: &Field) -> Result<Variant,
         Field::Sha256 => hash_handlers::handle_sha256(ctx),
         Field::Sha512 => hash_handlers::handle_sha512(ctx),
         Field::Sha3 => hash_handlers::handle_sha3(ctx),
+        Field::Md5 => hash_handlers::handle_md5(ctx),
+        Field::Crc32 => hash_handlers::handle_crc32(ctx),
+        Field::Blake3 => hash_handlers::handle_blake3(ctx),
+        Field::Xxh3 => hash_handlers::handle_xxh3(ctx),
+        Field::Ssdeep => hash_handlers::handle_ssdeep(ctx),
+        Field::ChecksumStatus => hash_handlers::handle_checksum_status(ctx),
     }
 }
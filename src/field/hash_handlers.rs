@@ -1,23 +1,70 @@
 use crate::field::context::FieldContext;
 use crate::util::error::SearchError;
+use crate::util::hashes::HashAlgorithm;
 use crate::util::Variant;
+use crate::util::variant::VariantType;
 
-pub fn handle_sha1(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
-    let hash = ctx.fms.get_or_compute_sha1(ctx.entry).to_string();
+fn handle_hash(ctx: &mut FieldContext, algorithm: HashAlgorithm) -> Result<Variant, SearchError> {
+    let hash = ctx.fms.get_or_compute_hash(algorithm, ctx.entry, ctx.file_info, ctx.hash_cache).to_string();
     Ok(Variant::from_string(&hash))
 }
 
+pub fn handle_sha1(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
+    handle_hash(ctx, HashAlgorithm::Sha1)
+}
+
 pub fn handle_sha256(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
-    let hash = ctx.fms.get_or_compute_sha256(ctx.entry).to_string();
-    Ok(Variant::from_string(&hash))
+    handle_hash(ctx, HashAlgorithm::Sha256)
 }
 
 pub fn handle_sha512(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
-    let hash = ctx.fms.get_or_compute_sha512(ctx.entry).to_string();
-    Ok(Variant::from_string(&hash))
+    handle_hash(ctx, HashAlgorithm::Sha512)
 }
 
 pub fn handle_sha3(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
-    let hash = ctx.fms.get_or_compute_sha3(ctx.entry).to_string();
+    handle_hash(ctx, HashAlgorithm::Sha3)
+}
+
+pub fn handle_md5(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
+    handle_hash(ctx, HashAlgorithm::Md5)
+}
+
+pub fn handle_crc32(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
+    handle_hash(ctx, HashAlgorithm::Crc32)
+}
+
+pub fn handle_blake3(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
+    handle_hash(ctx, HashAlgorithm::Blake3)
+}
+
+pub fn handle_xxh3(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
+    handle_hash(ctx, HashAlgorithm::Xxh3)
+}
+
+pub fn handle_ssdeep(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
+    let hash = ctx.fms.get_or_compute_ssdeep(ctx.entry, ctx.file_info, ctx.hash_cache).to_string();
     Ok(Variant::from_string(&hash))
 }
+
+/// Compares the file with the digest listed in a sidecar or a manifest.
+/// Directories and unreadable files have no status.
+pub fn handle_checksum_status(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
+    ctx.fms.update_file_metadata(ctx.entry, ctx.follow_symlinks);
+    if !ctx.fms.get_file_metadata().is_some_and(|attrs| attrs.is_file()) {
+        return Ok(Variant::empty(VariantType::String));
+    }
+
+    let expected = match ctx.checksum_files.find_checksum(&ctx.entry.path()) {
+        Some(expected) => expected,
+        None => return Ok(Variant::from_string(&String::from("missing"))),
+    };
+
+    let hash = ctx.fms.get_or_compute_hash(expected.algorithm, ctx.entry, ctx.file_info, ctx.hash_cache);
+    let status = match hash {
+        "" => return Ok(Variant::empty(VariantType::String)),
+        hash if hash == expected.hash => "ok",
+        _ => "mismatch",
+    };
+
+    Ok(Variant::from_string(&status.to_string()))
+}
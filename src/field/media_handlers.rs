@@ -1,6 +1,9 @@
+use crate::field::Field;
+use crate::field::content_handlers::check_extension;
 use crate::field::context::FieldContext;
 use crate::util::*;
 use crate::util::error::SearchError;
+use crate::util::image_hash::format_image_hash;
 
 pub fn handle_width(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
     ctx.fms.update_dimensions(ctx.entry);
@@ -18,6 +21,23 @@ pub fn handle_height(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
     Ok(Variant::empty(VariantType::String))
 }
 
+/// Only files that `is_image` would accept are decoded.
+pub fn handle_image_hash(ctx: &mut FieldContext, field: &Field) -> Result<Variant, SearchError> {
+    let name = ctx.entry.file_name();
+    if !check_extension(&name.to_string_lossy(), &ctx.config.is_image, &ctx.default_config.is_image) {
+        return Ok(Variant::empty(VariantType::String));
+    }
+
+    ctx.fms.update_image_hashes(ctx.entry);
+    let hash = match (field, ctx.fms.get_image_hashes()) {
+        (Field::Phash, Some(hashes)) => hashes.phash,
+        (Field::Dhash, Some(hashes)) => hashes.dhash,
+        _ => return Ok(Variant::empty(VariantType::String)),
+    };
+
+    Ok(Variant::from_string(&format_image_hash(hash)))
+}
+
 pub fn handle_duration(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
     ctx.fms.update_duration(ctx.entry);
     if let Some(&Duration { length, .. }) = ctx.fms.get_duration() {
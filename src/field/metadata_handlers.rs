@@ -10,7 +10,10 @@ use xattr::FileExt;
 use crate::field::context::FieldContext;
 use crate::mode;
 use crate::util::*;
+use crate::util::dir_size::{get_allocated_size, DirUsage};
 use crate::util::error::SearchError;
+#[cfg(target_os = "linux")]
+use crate::util::statx::{STATX_BLOCKS, STATX_INO, STATX_MTIME, STATX_NLINK, STATX_SIZE};
 
 /// Defines a handler that reads a single integer value from the entry's Unix
 /// metadata. The accessor (e.g. `ino`, `dev`) is only referenced on Unix, so the
@@ -28,6 +31,23 @@ macro_rules! unix_int_handler {
             Ok(Variant::empty($empty))
         }
     };
+    // Same, but served by the Linux `statx` fast path when it covers `$statx_mask`
+    ($name:ident, $accessor:ident, $empty:expr, $statx_mask:expr) => {
+        pub fn $name(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
+            #[cfg(target_os = "linux")]
+            if let Some(stat) = ctx.fms.get_file_stat(ctx.entry, ctx.follow_symlinks, $statx_mask) {
+                return Ok(Variant::from_int(stat.$accessor() as i64));
+            }
+            #[cfg(unix)]
+            {
+                ctx.fms.update_file_metadata(ctx.entry, ctx.follow_symlinks);
+                if let Some(attrs) = ctx.fms.get_file_metadata() {
+                    return Ok(Variant::from_int(attrs.$accessor() as i64));
+                }
+            }
+            Ok(Variant::empty($empty))
+        }
+    };
 }
 
 /// Defines the paired `<time>`/`<time>_nsec` handlers. On Unix they read the
@@ -90,7 +110,7 @@ macro_rules! datetime_handler {
 /// Opens the entry and reads the named extended attribute, flattening an absent
 /// attribute and any I/O error into `None`. Linux-only.
 #[cfg(target_os = "linux")]
-fn read_xattr(entry: &fs::DirEntry, name: &str) -> Option<Vec<u8>> {
+fn read_xattr(entry: &DirEntry, name: &str) -> Option<Vec<u8>> {
     fs::File::open(entry.path())
         .ok()
         .and_then(|file| file.get_xattr(name).ok().flatten())
@@ -102,6 +122,10 @@ pub fn handle_size(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
             Ok(Variant::from_int(file_info.size as i64))
         }
         _ => {
+            #[cfg(target_os = "linux")]
+            if let Some(stat) = ctx.fms.get_file_stat(ctx.entry, ctx.follow_symlinks, STATX_SIZE) {
+                return Ok(Variant::from_int(stat.size() as i64));
+            }
             ctx.fms.update_file_metadata(ctx.entry, ctx.follow_symlinks);
             if let Some(attrs) = ctx.fms.get_file_metadata() {
                 return Ok(Variant::from_int(attrs.len() as i64));
@@ -123,6 +147,16 @@ pub fn handle_formatted_size(ctx: &mut FieldContext) -> Result<Variant, SearchEr
             )?))
         }
         _ => {
+            #[cfg(target_os = "linux")]
+            if let Some(stat) = ctx.fms.get_file_stat(ctx.entry, ctx.follow_symlinks, STATX_SIZE) {
+                return Ok(Variant::from_string(&format_filesize(
+                    stat.size(),
+                    ctx.config
+                        .default_file_size_format
+                        .as_ref()
+                        .unwrap_or(&String::new()),
+                )?));
+            }
             ctx.fms.update_file_metadata(ctx.entry, ctx.follow_symlinks);
             if let Some(attrs) = ctx.fms.get_file_metadata() {
                 return Ok(Variant::from_string(&format_filesize(
@@ -138,6 +172,51 @@ pub fn handle_formatted_size(ctx: &mut FieldContext) -> Result<Variant, SearchEr
     }
 }
 
+pub fn handle_allocated_size(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
+    #[cfg(target_os = "linux")]
+    if let Some(stat) = ctx.fms.get_file_stat(ctx.entry, ctx.follow_symlinks, STATX_BLOCKS) {
+        return Ok(Variant::from_int((stat.blocks() * 512) as i64));
+    }
+    ctx.fms.update_file_metadata(ctx.entry, ctx.follow_symlinks);
+    if let Some(attrs) = ctx.fms.get_file_metadata() {
+        return Ok(Variant::from_int(get_allocated_size(attrs) as i64));
+    }
+    Ok(Variant::empty(VariantType::String))
+}
+
+pub fn handle_dir_size(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
+    match get_dir_usage(ctx) {
+        Some(usage) => Ok(Variant::from_int(usage.size as i64)),
+        None => Ok(Variant::empty(VariantType::String)),
+    }
+}
+
+pub fn handle_dir_disk_usage(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
+    match get_dir_usage(ctx) {
+        Some(usage) => Ok(Variant::from_int(usage.allocated as i64)),
+        None => Ok(Variant::empty(VariantType::String)),
+    }
+}
+
+/// Totals for `dir_size` and `dir_disk_usage`. Like `du`, a directory counts
+/// its descendants plus its own allocation, while any other file just counts itself.
+fn get_dir_usage(ctx: &mut FieldContext) -> Option<DirUsage> {
+    ctx.fms.update_file_metadata(ctx.entry, ctx.follow_symlinks);
+    let attrs = ctx.fms.get_file_metadata()?;
+    let own_allocated = get_allocated_size(attrs);
+
+    match attrs.is_dir() {
+        true => ctx.dir_size_cache.get_dir_usage(&ctx.entry.path()).map(|usage| DirUsage {
+            size: usage.size,
+            allocated: usage.allocated + own_allocated,
+        }),
+        false => Some(DirUsage {
+            size: attrs.len(),
+            allocated: own_allocated,
+        }),
+    }
+}
+
 pub fn handle_is_dir(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
     match ctx.file_info {
         Some(file_info) => {
@@ -230,7 +309,7 @@ pub fn handle_is_broken_symlink(ctx: &mut FieldContext) -> Result<Variant, Searc
 fn file_type_predicate(
     ctx: &mut FieldContext,
     from_metadata: impl Fn(&fs::Metadata) -> bool,
-    from_file_type: impl Fn(fs::FileType) -> bool,
+    from_file_type: impl Fn(FileType) -> bool,
 ) -> Variant {
     if ctx.fms.file_metadata_loaded() {
         return match ctx.fms.get_file_metadata() {
@@ -267,10 +346,10 @@ pub fn handle_is_socket(ctx: &mut FieldContext) -> Result<Variant, SearchError>
 
 unix_int_handler!(handle_device, dev, VariantType::String);
 unix_int_handler!(handle_rdev, rdev, VariantType::String);
-unix_int_handler!(handle_inode, ino, VariantType::String);
-unix_int_handler!(handle_blocks, blocks, VariantType::String);
+unix_int_handler!(handle_inode, ino, VariantType::String, STATX_INO);
+unix_int_handler!(handle_blocks, blocks, VariantType::String, STATX_BLOCKS);
 unix_int_handler!(handle_blksize, blksize, VariantType::String);
-unix_int_handler!(handle_hardlinks, nlink, VariantType::String);
+unix_int_handler!(handle_hardlinks, nlink, VariantType::String, STATX_NLINK);
 epoch_time_handler!(handle_atime, handle_atime_nsec, atime, atime_nsec, accessed);
 epoch_time_handler!(handle_mtime, handle_mtime_nsec, mtime, mtime_nsec, modified);
 epoch_time_handler!(handle_ctime, handle_ctime_nsec, ctime, ctime_nsec, created);
@@ -281,13 +360,17 @@ datetime_handler!(handle_accessed, accessed);
 pub fn handle_modified(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
     match ctx.file_info {
         Some(file_info) => {
-            if let Some(file_info_modified) = &file_info.modified {
-                let dt = to_local_datetime(file_info_modified);
-                return Ok(Variant::from_datetime(dt));
+            if let Some(file_info_modified) = file_info.modified {
+                return Ok(Variant::from_datetime(file_info_modified));
             }
             Ok(Variant::empty(VariantType::String))
         }
         _ => {
+            #[cfg(target_os = "linux")]
+            if let Some(stat) = ctx.fms.get_file_stat(ctx.entry, ctx.follow_symlinks, STATX_MTIME)
+                && let Some(naive) = system_time_to_naive_local(stat.modified()) {
+                    return Ok(Variant::from_datetime(naive));
+                }
             ctx.fms.update_file_metadata(ctx.entry, ctx.follow_symlinks);
             if let Some(attrs) = ctx.fms.get_file_metadata()
                 && let Ok(sdt) = attrs.modified()
@@ -427,6 +510,53 @@ pub fn handle_has_extattrs(ctx: &mut FieldContext) -> Result<Variant, SearchErro
     Ok(Variant::empty(VariantType::Bool))
 }
 
+#[cfg(target_os = "linux")]
+fn get_hole_bytes(ctx: &mut FieldContext) -> Option<u64> {
+    ctx.fms.update_file_metadata(ctx.entry, ctx.follow_symlinks);
+    let len = match ctx.fms.get_file_metadata() {
+        Some(attrs) if attrs.is_file() => attrs.len(),
+        _ => return None,
+    };
+    let file = fs::File::open(ctx.entry.path()).ok()?;
+    crate::util::sparse::get_hole_bytes(&file, len)
+}
+
+pub fn handle_is_sparse(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
+    #[cfg(target_os = "linux")]
+    {
+        if let Some(holes) = get_hole_bytes(ctx) {
+            return Ok(Variant::from_bool(holes > 0));
+        }
+    }
+
+    Ok(Variant::empty(VariantType::Bool))
+}
+
+pub fn handle_hole_bytes(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
+    #[cfg(target_os = "linux")]
+    {
+        if let Some(holes) = get_hole_bytes(ctx) {
+            return Ok(Variant::from_int(holes as i64));
+        }
+    }
+
+    Ok(Variant::empty(VariantType::Int))
+}
+
+pub fn handle_extent_count(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
+    #[cfg(target_os = "linux")]
+    {
+        ctx.fms.update_file_metadata(ctx.entry, ctx.follow_symlinks);
+        if ctx.fms.get_file_metadata().is_some_and(|attrs| attrs.is_file())
+            && let Ok(file) = fs::File::open(ctx.entry.path())
+            && let Some(count) = crate::util::sparse::get_extent_count(&file) {
+                return Ok(Variant::from_int(count as i64));
+            }
+    }
+
+    Ok(Variant::empty(VariantType::Int))
+}
+
 pub fn handle_acl(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
     #[cfg(target_os = "linux")]
     {
@@ -575,9 +705,10 @@ mod tests {
     use crate::field::context::{FieldContext, FileMetadataState};
     use crate::field::dispatch;
     use crate::fileinfo::FileInfo;
+    use crate::util::DirEntry;
 
     fn test_field(
-        entry: &fs::DirEntry,
+        entry: &DirEntry,
         file_info: &Option<FileInfo>,
         root_path: &Path,
         field: &Field,
@@ -587,6 +718,9 @@ mod tests {
         let mut fms = FileMetadataState::new();
         #[cfg(feature = "git")]
         let mut git_cache = crate::util::git::GitCache::new();
+        let mut dir_size_cache = crate::util::dir_size::DirSizeCache::new();
+        let mut hash_cache = crate::util::hash_cache::HashCache::with_file(None);
+        let mut checksum_files = crate::util::checksums::ChecksumFiles::new();
         #[cfg(all(unix, feature = "users"))]
         let user_cache = uzers::UsersCache::new();
         let mut ctx = FieldContext {
@@ -596,7 +730,11 @@ mod tests {
             fms: &mut fms,
             #[cfg(feature = "git")]
             git_cache: &mut git_cache,
+            dir_size_cache: &mut dir_size_cache,
+            hash_cache: &mut hash_cache,
+            checksum_files: &mut checksum_files,
             follow_symlinks: true,
+            decompress: false,
             config: &config,
             default_config: &default_config,
             #[cfg(all(unix, feature = "users"))]
@@ -612,12 +750,13 @@ mod tests {
         fs::create_dir_all(&tmp).unwrap();
         fs::write(tmp.join("dummy.txt"), "").unwrap();
 
-        let entry = fs::read_dir(&tmp).unwrap().next().unwrap().unwrap();
+        let entry = DirEntry::from(fs::read_dir(&tmp).unwrap().next().unwrap().unwrap());
         let file_info = Some(FileInfo {
             name: String::from("somedir\\"),
             size: 0,
             mode: None,
             modified: None,
+            ..Default::default()
         });
 
         let result = test_field(&entry, &file_info, &tmp, &Field::IsFile);
@@ -632,12 +771,13 @@ mod tests {
         fs::create_dir_all(&tmp).unwrap();
         fs::write(tmp.join("dummy.txt"), "").unwrap();
 
-        let entry = fs::read_dir(&tmp).unwrap().next().unwrap().unwrap();
+        let entry = DirEntry::from(fs::read_dir(&tmp).unwrap().next().unwrap().unwrap());
         let file_info = Some(FileInfo {
             name: String::from("somedir\\"),
             size: 0,
             mode: None,
             modified: None,
+            ..Default::default()
         });
 
         let is_dir = test_field(&entry, &file_info, &tmp, &Field::IsDir);
@@ -664,6 +804,7 @@ mod tests {
             .unwrap()
             .filter_map(|e| e.ok())
             .find(|e| e.file_name() == "link")
+            .map(DirEntry::from)
             .unwrap();
 
         let result = test_field(&entry, &None, &tmp, &Field::IsSymlink);
@@ -692,6 +833,7 @@ mod tests {
             .unwrap()
             .filter_map(|e| e.ok())
             .find(|e| e.file_name() == "link")
+            .map(DirEntry::from)
             .unwrap();
 
         let result = test_field(&entry, &None, &tmp, &Field::LinkTarget);
@@ -715,6 +857,7 @@ mod tests {
             .unwrap()
             .filter_map(|e| e.ok())
             .find(|e| e.file_name() == "real_file.txt")
+            .map(DirEntry::from)
             .unwrap();
 
         let result = test_field(&entry, &None, &tmp, &Field::LinkTarget);
@@ -739,6 +882,7 @@ mod tests {
             .unwrap()
             .filter_map(|e| e.ok())
             .find(|e| e.file_name() == "link")
+            .map(DirEntry::from)
             .unwrap();
 
         let result = test_field(&entry, &None, &tmp, &Field::IsBrokenSymlink);
@@ -763,6 +907,7 @@ mod tests {
             .unwrap()
             .filter_map(|e| e.ok())
             .find(|e| e.file_name() == "link")
+            .map(DirEntry::from)
             .unwrap();
 
         let result = test_field(&entry, &None, &tmp, &Field::IsBrokenSymlink);
@@ -782,6 +927,7 @@ mod tests {
             .unwrap()
             .filter_map(|e| e.ok())
             .find(|e| e.file_name() == "real_file.txt")
+            .map(DirEntry::from)
             .unwrap();
 
         let result = test_field(&entry, &None, &tmp, &Field::IsBrokenSymlink);
@@ -807,6 +953,7 @@ mod tests {
             .unwrap()
             .filter_map(|e| e.ok())
             .find(|e| e.file_name() == "link")
+            .map(DirEntry::from)
             .unwrap();
 
         let result = test_field(&entry, &None, &tmp, &Field::Size);
@@ -821,4 +968,37 @@ mod tests {
             expected_size, size
         );
     }
+
+    #[test]
+    fn test_dir_size_sums_nested_files() {
+        let tmp = std::env::temp_dir().join("fselect_test_dir_size_h");
+        let _ = fs::remove_dir_all(&tmp);
+        fs::create_dir_all(tmp.join("data/nested")).unwrap();
+        fs::write(tmp.join("data/a.bin"), vec![1u8; 1000]).unwrap();
+        fs::write(tmp.join("data/nested/b.bin"), vec![1u8; 24]).unwrap();
+
+        let entry = fs::read_dir(&tmp)
+            .unwrap()
+            .filter_map(|e| e.ok())
+            .find(|e| e.file_name() == "data")
+            .map(DirEntry::from)
+            .unwrap();
+        let file_entry = fs::read_dir(tmp.join("data"))
+            .unwrap()
+            .filter_map(|e| e.ok())
+            .find(|e| e.file_name() == "a.bin")
+            .map(DirEntry::from)
+            .unwrap();
+
+        let dir_size = test_field(&entry, &None, &tmp, &Field::DirSize);
+        let file_dir_size = test_field(&file_entry, &None, &tmp, &Field::DirSize);
+        let allocated = test_field(&file_entry, &None, &tmp, &Field::AllocatedSize);
+        let dir_disk_usage = test_field(&entry, &None, &tmp, &Field::DirDiskUsage);
+        let _ = fs::remove_dir_all(&tmp);
+
+        assert_eq!(dir_size.to_int(), 1024);
+        assert_eq!(file_dir_size.to_int(), 1000);
+        assert!(allocated.to_int() >= 0);
+        assert!(dir_disk_usage.to_int() >= allocated.to_int());
+    }
 }
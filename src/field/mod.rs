@@ -2,6 +2,7 @@
 
 pub mod context;
 pub mod dispatch;
+mod archive_handlers;
 mod content_handlers;
 mod exif_handlers;
 mod git_handlers;
@@ -207,6 +208,16 @@ fields! {
         @weight = 1
         @description = "Returns the absolute directory of the file"
         AbsDir,
+
+        #[text = ["archive_path"]]
+        @for_archived = true
+        @description = "Returns the path of the archive an entry belongs to, including the archives it's nested in"
+        ArchivePath,
+
+        #[text = ["entry_path"]]
+        @for_archived = true
+        @description = "Returns the path of an entry within its archive"
+        EntryPath,
         
         #[text = ["size"], data_type = "numeric"]
         @for_archived = true
@@ -219,24 +230,78 @@ fields! {
         @weight = 1
         @description = "Returns the size of the file accompanied with the unit"
         FormattedSize,
+
+        #[text = ["uncompressed_size"], data_type = "numeric"]
+        @weight = 1024
+        @description = "Returns the size of the content of a gzip, bzip2, xz or zstd compressed file once decompressed"
+        UncompressedSize,
+
+        #[text = ["compressed_size"], data_type = "numeric"]
+        @for_archived = true
+        @description = "Returns the compressed size of an archive entry in bytes"
+        CompressedSize,
+
+        #[text = ["compression_ratio"], data_type = "numeric"]
+        @for_archived = true
+        @description = "Returns the size of an archive entry divided by its compressed size"
+        CompressionRatio,
+
+        #[text = ["compression_method"]]
+        @for_archived = true
+        @description = "Returns the compression method of an archive entry"
+        CompressionMethod,
+
+        #[text = ["is_encrypted"], data_type = "boolean"]
+        @for_archived = true
+        @description = "Returns a boolean signifying whether an archive entry is encrypted"
+        IsEncrypted,
+
+        #[text = ["allocated_size", "disk_usage"], data_type = "numeric"]
+        @weight = 1
+        @description = "Returns the space the file occupies on disk in bytes (blocks * 512)"
+        AllocatedSize,
+
+        #[text = ["dir_size"], data_type = "numeric"]
+        @weight = 1024
+        @description = "Returns the total size of all files within the directory, recursively"
+        DirSize,
+
+        #[text = ["dir_disk_usage"], data_type = "numeric"]
+        @weight = 1024
+        @description = "Returns the total space occupied on disk by the directory and its contents, recursively"
+        DirDiskUsage,
+
+        #[text = ["archive_entry_count"], data_type = "numeric"]
+        @weight = 1024
+        @description = "Returns the number of entries in an archive"
+        ArchiveEntryCount,
+
+        #[text = ["archive_uncompressed_size"], data_type = "numeric"]
+        @weight = 1024
+        @description = "Returns the total size of the entries in an archive once uncompressed"
+        ArchiveUncompressedSize,
         
         #[text = ["uid"], data_type = "numeric"]
+        @for_archived = true
         @weight = 1
         @description = "Returns the UID of the owner"
         Uid,
         
         #[text = ["gid"], data_type = "numeric"]
+        @for_archived = true
         @weight = 1
         @description = "Returns the GID of the owner's group"
         Gid,
         
         #[text = ["user"]]
+        @for_archived = true
         @weight = 1
         @description = "Returns the name of the owner for this file"
         #[cfg(all(unix, feature = "users"))]
         User,
         
         #[text = ["group"]]
+        @for_archived = true
         @weight = 1
         @description = "Returns the name of the owner's group for this file"
         #[cfg(all(unix, feature = "users"))]
@@ -494,6 +559,21 @@ fields! {
         @description = "Returns a boolean signifying whether the file has any extended file attributes set"
         HasExtattrs,
 
+        #[text = ["is_sparse"], data_type = "boolean"]
+        @weight = 2
+        @description = "Returns a boolean signifying whether the file has holes, i.e. unallocated ranges (Linux only)"
+        IsSparse,
+
+        #[text = ["hole_bytes"], data_type = "numeric"]
+        @weight = 2
+        @description = "Returns the total size of holes in the file in bytes (Linux only)"
+        HoleBytes,
+
+        #[text = ["extent_count", "extents"], data_type = "numeric"]
+        @weight = 2
+        @description = "Returns the number of extents the file data is stored in (Linux only)"
+        ExtentCount,
+
         #[text = ["acl"]]
         @weight = 2
         @description = "Returns all POSIX ACL entries in standard form"
@@ -591,6 +671,16 @@ fields! {
         @weight = 16
         @description = "Returns the number of pixels along the height of the photo or MP4 file"
         Height,
+
+        #[text = ["phash"]]
+        @weight = 1024
+        @description = "Returns the perceptual (DCT) hash of an image"
+        Phash,
+
+        #[text = ["dhash"]]
+        @weight = 1024
+        @description = "Returns the difference hash of an image"
+        Dhash,
         
         #[text = ["duration"], data_type = "numeric"]
         @weight = 16
@@ -843,46 +933,55 @@ fields! {
         ExifDigitalZoom,
         
         #[text = ["mime"]]
+        @for_archived = true
         @weight = 16
         @description = "Returns MIME type of the file"
         Mime,
         
         #[text = ["line_count"], data_type = "numeric"]
+        @for_archived = true
         @weight = 1024
         @description = "Returns a number of lines in a text file"
         LineCount,
 
         #[text = ["word_count", "words"], data_type = "numeric"]
+        @for_archived = true
         @weight = 1024
         @description = "Returns the number of whitespace-separated words in a text file"
         WordCount,
 
         #[text = ["char_count", "chars"], data_type = "numeric"]
+        @for_archived = true
         @weight = 1024
         @description = "Returns the number of characters in a text file"
         CharCount,
 
         #[text = ["encoding"]]
+        @for_archived = true
         @weight = 1024
         @description = "Returns the detected text encoding of the file (e.g., ASCII, UTF-8, UTF-16LE), or an empty value for binary files"
         Encoding,
 
         #[text = ["has_bom"], data_type = "boolean"]
+        @for_archived = true
         @weight = 16
         @description = "Returns a boolean signifying whether the file begins with a byte-order mark (BOM)"
         HasBom,
 
         #[text = ["line_ending", "line_endings", "eol"]]
+        @for_archived = true
         @weight = 1024
         @description = "Returns the line ending style of a text file (LF, CRLF, CR, Mixed, or empty)"
         LineEnding,
 
         #[text = ["is_binary"], data_type = "boolean"]
+        @for_archived = true
         @weight = 16
         @description = "Returns a boolean signifying whether the file has binary contents"
         IsBinary,
         
         #[text = ["is_text"], data_type = "boolean"]
+        @for_archived = true
         @weight = 16
         @description = "Returns a boolean signifying whether the file has text contents"
         IsText,
@@ -928,24 +1027,90 @@ fields! {
         IsVideo,
         
         #[text = ["sha1"]]
+        @for_archived = true
         @weight = 1024
         @description = "Returns SHA-1 digest of a file"
         Sha1,
         
         #[text = ["sha2_256", "sha256"]]
+        @for_archived = true
         @weight = 1024
         @description = "Returns SHA2-256 digest of a file"
         Sha256,
         
         #[text = ["sha2_512", "sha512"]]
+        @for_archived = true
         @weight = 1024
         @description = "Returns SHA2-512 digest of a file"
         Sha512,
         
         #[text = ["sha3_512", "sha3"]]
+        @for_archived = true
         @weight = 1024
         @description = "Returns SHA-3 digest of a file"
         Sha3,
+
+        #[text = ["md5"]]
+        @for_archived = true
+        @weight = 1024
+        @description = "Returns MD5 digest of a file"
+        Md5,
+
+        #[text = ["crc32"]]
+        @for_archived = true
+        @weight = 1024
+        @description = "Returns CRC32 checksum of a file"
+        Crc32,
+
+        #[text = ["blake3"]]
+        @for_archived = true
+        @weight = 1024
+        @description = "Returns BLAKE3 digest of a file"
+        Blake3,
+
+        #[text = ["xxh3", "xxh3_64"]]
+        @for_archived = true
+        @weight = 1024
+        @description = "Returns 64-bit xxHash3 digest of a file"
+        Xxh3,
+
+        #[text = ["ssdeep", "fuzzy_hash"]]
+        @for_archived = true
+        @weight = 1024
+        @description = "Returns context-triggered piecewise (fuzzy) hash of a file"
+        Ssdeep,
+
+        #[text = ["checksum_status"]]
+        @weight = 1024
+        @description = "Verifies a file against its checksum file (ok, mismatch or missing)"
+        ChecksumStatus,
+    }
+}
+
+impl Field {
+    /// Whether the field is computed from the content of the file
+    pub fn reads_content(&self) -> bool {
+        matches!(
+            self,
+            Field::LineCount
+                | Field::WordCount
+                | Field::CharCount
+                | Field::Encoding
+                | Field::HasBom
+                | Field::LineEnding
+                | Field::Mime
+                | Field::IsBinary
+                | Field::IsText
+                | Field::Sha1
+                | Field::Sha256
+                | Field::Sha512
+                | Field::Sha3
+                | Field::Md5
+                | Field::Crc32
+                | Field::Blake3
+                | Field::Xxh3
+                | Field::Ssdeep
+        )
     }
 }
 
@@ -995,6 +1160,9 @@ mod tests {
         assert!(field.is_available_for_archived_files());
 
         let field = Field::LineCount;
+        assert!(field.is_available_for_archived_files());
+
+        let field = Field::Width;
         assert!(!field.is_available_for_archived_files());
     }
 
@@ -3,6 +3,8 @@ use crate::field::metadata_handlers::check_file_mode;
 use crate::mode;
 use crate::util::*;
 use crate::util::error::SearchError;
+#[cfg(target_os = "linux")]
+use crate::util::statx::{STATX_GID, STATX_MODE, STATX_TYPE, STATX_UID};
 
 pub fn handle_mode(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
     match ctx.file_info {
@@ -13,6 +15,10 @@ pub fn handle_mode(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
             Ok(Variant::empty(VariantType::String))
         }
         _ => {
+            #[cfg(target_os = "linux")]
+            if let Some(stat) = ctx.fms.get_file_stat(ctx.entry, ctx.follow_symlinks, STATX_TYPE | STATX_MODE) {
+                return Ok(Variant::from_string(&mode::format_mode(stat.mode())));
+            }
             ctx.fms.update_file_metadata(ctx.entry, ctx.follow_symlinks);
             if let Some(attrs) = ctx.fms.get_file_metadata() {
                 return Ok(Variant::from_string(&mode::get_mode(attrs)));
@@ -83,6 +89,16 @@ pub fn handle_is_sticky(ctx: &mut FieldContext) -> Result<Variant, SearchError>
 }
 
 pub fn handle_uid(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
+    if let Some(file_info) = ctx.file_info {
+        return Ok(match file_info.uid {
+            Some(uid) => Variant::from_int(uid as i64),
+            None => Variant::empty(VariantType::String),
+        });
+    }
+    #[cfg(target_os = "linux")]
+    if let Some(stat) = ctx.fms.get_file_stat(ctx.entry, ctx.follow_symlinks, STATX_UID) {
+        return Ok(Variant::from_int(stat.uid() as i64));
+    }
     ctx.fms.update_file_metadata(ctx.entry, ctx.follow_symlinks);
     if let Some(attrs) = ctx.fms.get_file_metadata()
         && let Some(uid) = mode::get_uid(attrs) {
@@ -92,6 +108,16 @@ pub fn handle_uid(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
 }
 
 pub fn handle_gid(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
+    if let Some(file_info) = ctx.file_info {
+        return Ok(match file_info.gid {
+            Some(gid) => Variant::from_int(gid as i64),
+            None => Variant::empty(VariantType::String),
+        });
+    }
+    #[cfg(target_os = "linux")]
+    if let Some(stat) = ctx.fms.get_file_stat(ctx.entry, ctx.follow_symlinks, STATX_GID) {
+        return Ok(Variant::from_int(stat.gid() as i64));
+    }
     ctx.fms.update_file_metadata(ctx.entry, ctx.follow_symlinks);
     if let Some(attrs) = ctx.fms.get_file_metadata()
         && let Some(gid) = mode::get_gid(attrs) {
@@ -104,14 +130,18 @@ pub fn handle_gid(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
 pub fn handle_user(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
     use uzers::Users;
 
-    ctx.fms.update_file_metadata(ctx.entry, ctx.follow_symlinks);
-    if let Some(attrs) = ctx.fms.get_file_metadata() {
-        if let Some(uid) = mode::get_uid(attrs) {
-            if let Some(user) = ctx.user_cache.get_user_by_uid(uid) {
-                return Ok(Variant::from_string(
-                    &user.name().to_string_lossy().to_string(),
-                ));
-            }
+    let uid = match ctx.file_info {
+        Some(file_info) => file_info.uid,
+        None => {
+            ctx.fms.update_file_metadata(ctx.entry, ctx.follow_symlinks);
+            ctx.fms.get_file_metadata().and_then(mode::get_uid)
+        }
+    };
+    if let Some(uid) = uid {
+        if let Some(user) = ctx.user_cache.get_user_by_uid(uid) {
+            return Ok(Variant::from_string(
+                &user.name().to_string_lossy().to_string(),
+            ));
         }
     }
     Ok(Variant::empty(VariantType::String))
@@ -121,14 +151,18 @@ pub fn handle_user(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
 pub fn handle_group(ctx: &mut FieldContext) -> Result<Variant, SearchError> {
     use uzers::Groups;
 
-    ctx.fms.update_file_metadata(ctx.entry, ctx.follow_symlinks);
-    if let Some(attrs) = ctx.fms.get_file_metadata() {
-        if let Some(gid) = mode::get_gid(attrs) {
-            if let Some(group) = ctx.user_cache.get_group_by_gid(gid) {
-                return Ok(Variant::from_string(
-                    &group.name().to_string_lossy().to_string(),
-                ));
-            }
+    let gid = match ctx.file_info {
+        Some(file_info) => file_info.gid,
+        None => {
+            ctx.fms.update_file_metadata(ctx.entry, ctx.follow_symlinks);
+            ctx.fms.get_file_metadata().and_then(mode::get_gid)
+        }
+    };
+    if let Some(gid) = gid {
+        if let Some(group) = ctx.user_cache.get_group_by_gid(gid) {
+            return Ok(Variant::from_string(
+                &group.name().to_string_lossy().to_string(),
+            ));
         }
     }
     Ok(Variant::empty(VariantType::String))
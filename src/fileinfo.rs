@@ -1,10 +1,26 @@
-use zip::DateTime;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Cursor, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, UNIX_EPOCH};
 
+use chrono::NaiveDateTime;
+
+use crate::util::datetime::{system_time_to_naive_local, to_local_datetime};
+use crate::util::decompress::decompress;
+
+#[derive(Default)]
 pub struct FileInfo {
     pub name: String,
     pub size: u64,
     pub mode: Option<u32>,
-    pub modified: Option<DateTime>,
+    pub modified: Option<NaiveDateTime>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub compressed_size: Option<u64>,
+    pub compression_method: Option<String>,
+    pub encrypted: Option<bool>,
+    pub crc32: Option<u32>,
 }
 
 pub fn to_file_info<R>(zipped_file: &zip::read::ZipFile<R>) -> FileInfo
@@ -15,6 +31,286 @@ where
         name: zipped_file.name().to_string(),
         size: zipped_file.size(),
         mode: zipped_file.unix_mode(),
-        modified: zipped_file.last_modified(),
+        modified: zipped_file.last_modified().map(|dt| to_local_datetime(&dt)),
+        compressed_size: Some(zipped_file.compressed_size()),
+        compression_method: Some(zipped_file.compression().to_string().to_lowercase()),
+        encrypted: Some(zipped_file.encrypted()),
+        crc32: Some(zipped_file.crc32()),
+        ..Default::default()
     }
 }
+
+/// File type bits tar keeps apart from the mode
+const S_IFREG: u32 = 0o100000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFLNK: u32 = 0o120000;
+
+pub fn tar_entry_to_file_info<R: Read>(tar_entry: &tar::Entry<R>) -> FileInfo {
+    let header = tar_entry.header();
+    let entry_type = header.entry_type();
+
+    // Like in zip archives, directories are told apart by the trailing slash
+    let mut name = String::from_utf8_lossy(&tar_entry.path_bytes()).to_string();
+    if entry_type.is_dir() && !name.ends_with('/') {
+        name.push('/');
+    }
+
+    let file_type = match entry_type {
+        tar::EntryType::Directory => S_IFDIR,
+        tar::EntryType::Symlink => S_IFLNK,
+        _ => S_IFREG,
+    };
+
+    FileInfo {
+        name,
+        size: tar_entry.size(),
+        mode: header.mode().ok().map(|mode| (mode & 0o7777) | file_type),
+        modified: header
+            .mtime()
+            .ok()
+            .and_then(|mtime| system_time_to_naive_local(UNIX_EPOCH + Duration::from_secs(mtime))),
+        uid: header.uid().ok().and_then(|uid| u32::try_from(uid).ok()),
+        gid: header.gid().ok().and_then(|gid| u32::try_from(gid).ok()),
+        ..Default::default()
+    }
+}
+
+/// Set in the Windows attributes of 7z entries when their high 16 bits hold the Unix mode
+#[cfg(feature = "sevenz")]
+const FILE_ATTRIBUTE_UNIX_EXTENSION: u32 = 0x8000;
+
+#[cfg(feature = "sevenz")]
+pub fn sevenz_entry_to_file_info(sevenz_entry: &sevenz_rust::SevenZArchiveEntry) -> FileInfo {
+    let mut name = sevenz_entry.name().replace('\\', "/");
+    if sevenz_entry.is_directory() && !name.ends_with('/') {
+        name.push('/');
+    }
+
+    let attributes = sevenz_entry.windows_attributes();
+    let mode = if sevenz_entry.has_windows_attributes && attributes & FILE_ATTRIBUTE_UNIX_EXTENSION != 0 {
+        Some(attributes >> 16)
+    } else {
+        None
+    };
+
+    let modified = if sevenz_entry.has_last_modified_date {
+        u64::try_from(sevenz_entry.last_modified_date().to_unix_time())
+            .ok()
+            .and_then(|mtime| system_time_to_naive_local(UNIX_EPOCH + Duration::from_secs(mtime)))
+    } else {
+        None
+    };
+
+    FileInfo {
+        name,
+        size: sevenz_entry.size(),
+        mode,
+        modified,
+        crc32: sevenz_entry.has_crc.then_some(sevenz_entry.crc as u32),
+        ..Default::default()
+    }
+}
+
+/// Opens a tar archive, decompressing it on the fly. The compression is told
+/// by the magic bytes rather than the extension, which is often just `.tgz`.
+pub fn open_tar_archive(reader: impl Read + 'static) -> io::Result<tar::Archive<Box<dyn Read>>> {
+    Ok(tar::Archive::new(decompress(BufReader::new(reader))?))
+}
+
+#[cfg(feature = "sevenz")]
+pub fn open_7z_archive(mut reader: ArchiveReader) -> Result<sevenz_rust::SevenZReader<ArchiveReader>, sevenz_rust::Error> {
+    let len = reader.seek(SeekFrom::End(0))?;
+    reader.rewind()?;
+    sevenz_rust::SevenZReader::new(reader, len, sevenz_rust::Password::empty())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArchiveFormat {
+    Zip,
+    Tar,
+    #[cfg(feature = "sevenz")]
+    SevenZ,
+    #[cfg(feature = "rar")]
+    Rar,
+}
+
+/// Number of entries of an archive and their total uncompressed size
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ArchiveSummary {
+    pub entry_count: u64,
+    pub uncompressed_size: u64,
+}
+
+/// Sums up the entries of an archive on disk. The sizes are the ones the
+/// archive declares, so nothing is decompressed but tar archives.
+pub fn get_archive_summary(path: &Path, format: ArchiveFormat) -> Option<ArchiveSummary> {
+    let file = File::open(path).ok()?;
+
+    let sizes: Vec<u64> = match format {
+        ArchiveFormat::Zip => {
+            let mut archive = zip::ZipArchive::new(file).ok()?;
+            (0..archive.len())
+                .filter_map(|i| archive.by_index_raw(i).ok().map(|afile| afile.size()))
+                .collect()
+        }
+        ArchiveFormat::Tar => {
+            let mut archive = open_tar_archive(file).ok()?;
+            let mut sizes = vec![];
+            for tar_entry in archive.entries().ok()? {
+                match tar_entry {
+                    Ok(tar_entry) => sizes.push(tar_entry.size()),
+                    // Not a tar archive at all
+                    Err(_) if sizes.is_empty() => return None,
+                    Err(_) => break,
+                }
+            }
+            sizes
+        }
+        #[cfg(feature = "sevenz")]
+        ArchiveFormat::SevenZ => open_7z_archive(Box::new(file))
+            .ok()?
+            .archive()
+            .files
+            .iter()
+            .filter(|sevenz_entry| !sevenz_entry.is_anti_item())
+            .map(|sevenz_entry| sevenz_entry.size())
+            .collect(),
+        #[cfg(feature = "rar")]
+        ArchiveFormat::Rar => crate::util::rar::list_rar_entries(file)
+            .ok()?
+            .iter()
+            .map(|file_info| file_info.size)
+            .collect(),
+    };
+
+    Some(ArchiveSummary {
+        entry_count: sizes.len() as u64,
+        uncompressed_size: sizes.iter().sum(),
+    })
+}
+
+/// Separates the names of nested archives in the name of an entry, e.g. `inner.jar!/com/x/Y.class`
+pub const NESTED_ARCHIVE_SEPARATOR: &str = "!/";
+
+/// Nested archives up to this size are kept in memory, larger ones go to a temp file
+const NESTED_ARCHIVE_MEMORY_LIMIT: u64 = 16 * 1024 * 1024;
+
+static TEMP_ARCHIVE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+pub trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
+
+/// An archive file on disk, or an archive nested in another one.
+pub type ArchiveReader = Box<dyn ReadSeek>;
+
+/// Copy of a nested archive, removed when dropped.
+struct TempArchive {
+    file: File,
+    path: PathBuf,
+}
+
+impl Read for TempArchive {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.file.read(buf)
+    }
+}
+
+impl Seek for TempArchive {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.file.seek(pos)
+    }
+}
+
+impl Drop for TempArchive {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Reads an archive entry that is an archive itself, so that it can be opened
+/// the same way as an archive on disk.
+pub fn buffer_archive_entry(reader: &mut dyn Read, size: u64) -> io::Result<ArchiveReader> {
+    if size <= NESTED_ARCHIVE_MEMORY_LIMIT {
+        let mut content = Vec::with_capacity(size as usize);
+        reader.read_to_end(&mut content)?;
+        return Ok(Box::new(Cursor::new(content)));
+    }
+
+    let counter = TEMP_ARCHIVE_COUNTER.fetch_add(1, Ordering::Relaxed);
+    let path = std::env::temp_dir().join(format!("fselect-nested-{}-{}", std::process::id(), counter));
+    let file = File::options().read(true).write(true).create_new(true).open(&path)?;
+    let mut temp_archive = TempArchive { file, path };
+
+    io::copy(reader, &mut temp_archive.file)?;
+    temp_archive.file.rewind()?;
+    Ok(Box::new(temp_archive))
+}
+
+/// Opens the named entry of the archive found during the search and passes
+/// its content and uncompressed size to `f`. Entries of nested archives are
+/// named with the path through the archives they are nested in.
+pub fn read_archive_entry<T>(
+    archive_path: &Path,
+    name: &str,
+    f: impl FnOnce(&mut dyn Read, u64) -> Option<T>,
+) -> Option<T> {
+    let mut reader: ArchiveReader = Box::new(File::open(archive_path).ok()?);
+
+    let mut names: Vec<&str> = name.split(NESTED_ARCHIVE_SEPARATOR).collect();
+    let entry_name = names.pop()?;
+    for nested_archive_name in names {
+        reader = read_entry_from(reader, nested_archive_name, |content, size| {
+            buffer_archive_entry(content, size).ok()
+        })?;
+    }
+
+    read_entry_from(reader, entry_name, f)
+}
+
+fn read_entry_from<T>(
+    mut reader: ArchiveReader,
+    name: &str,
+    f: impl FnOnce(&mut dyn Read, u64) -> Option<T>,
+) -> Option<T> {
+    if let Ok(mut archive) = zip::ZipArchive::new(&mut reader) {
+        let mut entry = archive.by_name(name).ok()?;
+        let size = entry.size();
+        return f(&mut entry, size);
+    }
+
+    #[cfg(feature = "sevenz")]
+    {
+        let len = reader.seek(SeekFrom::End(0)).ok()?;
+        reader.rewind().ok()?;
+        if let Ok(mut archive) = sevenz_rust::SevenZReader::new(&mut reader, len, sevenz_rust::Password::empty()) {
+            let mut result = None;
+            let mut f = Some(f);
+            let _ = archive.for_each_entries(|sevenz_entry, reader| {
+                // Blocks after the one holding the entry are still visited
+                if f.is_none() {
+                    return Ok(false);
+                }
+                if sevenz_entry_to_file_info(sevenz_entry).name != name {
+                    // Entries of a solid block are decompressed one after another
+                    io::copy(reader, &mut io::sink())?;
+                    return Ok(true);
+                }
+                result = f.take().and_then(|f| f(reader, sevenz_entry.size()));
+                Ok(false)
+            });
+            return result;
+        }
+    }
+
+    // A tar archive can only be read through up to the entry
+    reader.rewind().ok()?;
+    let mut archive = open_tar_archive(reader).ok()?;
+    let mut entry = archive
+        .entries()
+        .ok()?
+        .map_while(Result::ok)
+        .find(|entry| tar_entry_to_file_info(entry).name == name)?;
+    let size = entry.size();
+    f(&mut entry, size)
+}
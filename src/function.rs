@@ -5,9 +5,9 @@ use std::collections::HashMap;
 use std::fmt::Display;
 use std::fmt::Error;
 use std::fmt::Formatter;
-use std::fs::DirEntry;
 use std::fs::File;
 use std::io::prelude::*;
+use std::io::BufReader;
 use std::str::FromStr;
 use std::time::Duration;
 
@@ -24,8 +24,13 @@ use serde::ser::{Serialize, Serializer};
 #[cfg(unix)]
 use xattr::FileExt;
 
+use crate::field::context::FileMetadataState;
 use crate::fileinfo::FileInfo;
-use crate::util::{capitalize_initials, format_date, format_time, format_datetime, parse_datetime};
+use crate::util::decompress::decompress as decompress_content;
+use crate::util::fuzzy_hash::fuzzy_similarity;
+use crate::util::hashes::{ByteRange, HashAlgorithm};
+use crate::util::image_hash::hamming_distance;
+use crate::util::{capitalize_initials, DirEntry, format_date, format_time, format_datetime, parse_datetime};
 use crate::util::variant::{Variant, VariantType};
 
 macro_rules! functions {
@@ -739,17 +744,29 @@ pub fn get_value(
             }
         }
         // ===== File functions =====
-        Function::Contains => {
-            if file_info.is_some() {
-                return Ok(Variant::empty(VariantType::Bool));
-            }
+        Function::Contains => Ok(contains(&function_arg, entry, file_info, &mut FileMetadataState::new(), false)),
+        Function::Hash => hash(&function_arg, &function_args, entry, file_info, &mut FileMetadataState::new()),
+        Function::Hamming => {
+            let other = match function_args.first() {
+                Some(other) => other,
+                None => return Err("HAMMING requires two hashes to compare".to_string()),
+            };
 
-            if let Some(entry) = entry
-                && let Ok(file) = File::open(entry.path()) {
-                    return Ok(Variant::from_bool(file_contains(file, function_arg.as_bytes())));
-                }
+            match hamming_distance(&function_arg, other) {
+                Some(distance) => Ok(Variant::from_int(distance as i64)),
+                None => Ok(Variant::empty(VariantType::Int)),
+            }
+        }
+        Function::Similarity => {
+            let other = match function_args.first() {
+                Some(other) => other,
+                None => return Err("SIMILARITY requires two hashes to compare".to_string()),
+            };
 
-            Ok(Variant::empty(VariantType::Bool))
+            match fuzzy_similarity(&function_arg, other) {
+                Some(score) => Ok(Variant::from_int(score as i64)),
+                None => Ok(Variant::empty(VariantType::Int)),
+            }
         }
         #[cfg(unix)]
         Function::HasXattr => {
@@ -1003,6 +1020,93 @@ pub fn get_value(
     }
 }
 
+/// Applies a function like `get_value` to the file being checked. Archived
+/// files are read through `fms`, which keeps their content for the other
+/// fields and functions, and files are read decompressed when `decompress` is set.
+pub fn get_file_value(
+    function: &Function,
+    function_arg: String,
+    function_args: Vec<String>,
+    entry: Option<&DirEntry>,
+    file_info: &Option<FileInfo>,
+    fms: &mut FileMetadataState,
+    decompress: bool,
+) -> Result<Variant, String> {
+    match function {
+        Function::Contains => Ok(contains(&function_arg, entry, file_info, fms, decompress)),
+        Function::Hash => hash(&function_arg, &function_args, entry, file_info, fms),
+        _ => get_value(function, function_arg, function_args, entry, file_info),
+    }
+}
+
+fn contains(
+    needle: &str,
+    entry: Option<&DirEntry>,
+    file_info: &Option<FileInfo>,
+    fms: &mut FileMetadataState,
+    decompress: bool,
+) -> Variant {
+    let entry = match entry {
+        Some(entry) => entry,
+        None => return Variant::empty(VariantType::Bool),
+    };
+
+    let contains = match file_info {
+        Some(file_info) if file_info.name.ends_with('/') => None,
+        Some(file_info) => fms.read_archive_entry(entry, file_info, |reader, _| {
+            match decompress {
+                true => Some(file_contains(decompress_content(BufReader::new(reader)).ok()?, needle.as_bytes())),
+                false => Some(file_contains(reader, needle.as_bytes())),
+            }
+        }),
+        None if decompress => File::open(entry.path())
+            .and_then(|file| decompress_content(BufReader::new(file)))
+            .ok()
+            .map(|reader| file_contains(reader, needle.as_bytes())),
+        None => File::open(entry.path()).ok().map(|file| file_contains(file, needle.as_bytes())),
+    };
+
+    match contains {
+        Some(contains) => Variant::from_bool(contains),
+        None => Variant::empty(VariantType::Bool),
+    }
+}
+
+fn hash(
+    function_arg: &str,
+    function_args: &[String],
+    entry: Option<&DirEntry>,
+    file_info: &Option<FileInfo>,
+    fms: &mut FileMetadataState,
+) -> Result<Variant, String> {
+    let algorithm = match HashAlgorithm::from_name(function_arg) {
+        Some(algorithm) => algorithm,
+        None => return Err(format!("Unknown algorithm in HASH function: {}", function_arg)),
+    };
+
+    let range = match function_args.first() {
+        Some(len) => match len.parse::<i64>() {
+            Ok(len) => ByteRange::from_len(len),
+            Err(_) => return Err(format!("Could not parse length argument of HASH function: {}", len)),
+        },
+        None => ByteRange::All,
+    };
+
+    let entry = match entry {
+        Some(entry) => entry,
+        None => return Ok(Variant::empty(VariantType::String)),
+    };
+
+    let hash = match file_info {
+        Some(file_info) => fms
+            .read_archive_entry(entry, file_info, |reader, size| algorithm.hash_stream_range(reader, size, range))
+            .unwrap_or_default(),
+        None => algorithm.hash_file_range(&entry.path(), range),
+    };
+
+    Ok(Variant::from_string(&hash))
+}
+
 pub fn get_aggregate_value(
     function: &Function,
     accumulator: &GroupAccumulator,
@@ -1406,6 +1510,22 @@ functions! {
         @description = "Checks if a file contains a substring"
         Contains,
 
+        #[text = ["hash"]]
+        @weight = 1024
+        @group = "Other"
+        @description = "Hash the file with an algorithm, optionally only the first N (or last -N) bytes. Usage: HASH(sha256, 4096)"
+        Hash,
+
+        #[text = ["hamming", "hamming_distance"], data_type = "numeric"]
+        @group = "Other"
+        @description = "Count differing bits of two hex hashes, e.g. phash values. Usage: HAMMING(phash, 'c3d1...')"
+        Hamming,
+
+        #[text = ["similarity", "fuzzy_compare"], data_type = "numeric"]
+        @group = "Other"
+        @description = "Compare two fuzzy hashes from 0 to 100. Usage: SIMILARITY(ssdeep, '96:xyz...:abc...')"
+        Similarity,
+
         #[text = ["has_xattr"], data_type = "boolean"]
         @weight = 2
         @group = "Xattr"
@@ -1529,7 +1649,14 @@ functions! {
     }
 }
 
-#[derive(Debug, Default)]
+impl Function {
+    /// Whether the function reads the content of the file
+    pub fn reads_content(&self) -> bool {
+        matches!(self, Function::Contains | Function::Hash)
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct FieldAccumulator {
     pub count: usize,
     /// Number of pushed values that were not empty, regardless of whether they
@@ -1578,9 +1705,41 @@ impl FieldAccumulator {
                 }
             }
     }
+
+    /// Adds up accumulators of two parts of the same group.
+    pub fn merge(&mut self, other: FieldAccumulator) {
+        self.non_empty_count += other.non_empty_count;
+        if other.min_value.is_some() && (self.min_value.is_none() || other.min_value < self.min_value) {
+            self.min_value = other.min_value;
+        }
+        if other.max_value > self.max_value {
+            self.max_value = other.max_value;
+        }
+
+        if other.count == 0 {
+            return;
+        }
+        if self.count == 0 {
+            self.count = other.count;
+            self.min = other.min;
+            self.max = other.max;
+            self.sum = other.sum;
+            self.m2 = other.m2;
+            return;
+        }
+
+        // Parallel variant of Welford's algorithm
+        let count = (self.count + other.count) as f64;
+        let delta = other.sum / other.count as f64 - self.sum / self.count as f64;
+        self.m2 += other.m2 + delta * delta * self.count as f64 * other.count as f64 / count;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.count += other.count;
+        self.sum += other.sum;
+    }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct GroupAccumulator {
     pub total_count: usize,
     pub fields: HashMap<String, FieldAccumulator>,
@@ -1594,6 +1753,13 @@ impl GroupAccumulator {
     pub fn push(&mut self, field: &str, value: &str) {
         self.fields.entry(field.to_string()).or_default().push(value);
     }
+
+    pub fn merge(&mut self, other: GroupAccumulator) {
+        self.total_count += other.total_count;
+        for (field, acc) in other.fields {
+            self.fields.entry(field).or_default().merge(acc);
+        }
+    }
 }
 
 #[cfg(test)]
@@ -2852,6 +3018,25 @@ mod tests {
         assert_eq!(result, "3.5");
     }
 
+    #[test]
+    fn merged_accumulators_match_single_accumulator() {
+        let values = ["4", "", "8", "x", "15", "16", "23", "42"];
+        let whole = make_accumulator("val", &values);
+        let mut merged = make_accumulator("val", &values[..3]);
+        merged.merge(make_accumulator("val", &values[3..]));
+        merged.merge(GroupAccumulator::default());
+
+        for function in [Function::Count, Function::Sum, Function::Min, Function::Max, Function::Avg, Function::StdDevPop, Function::VarSamp] {
+            assert_eq!(
+                get_aggregate_value(&function, &merged, "val".to_string(), &None),
+                get_aggregate_value(&function, &whole, "val".to_string(), &None),
+                "{:?}", function
+            );
+        }
+        assert_eq!(merged.fields["val"].min_value, Some(String::from("15")));
+        assert_eq!(merged.fields["val"].max_value, Some(String::from("x")));
+    }
+
     #[test]
     fn sum_ignores_negative_values() {
         let acc = make_accumulator("val", &["-5", "10"]);
@@ -3889,13 +4074,14 @@ mod tests {
         path
     }
 
-    fn dir_entry_for(path: &std::path::Path) -> std::fs::DirEntry {
+    fn dir_entry_for(path: &std::path::Path) -> DirEntry {
         let parent = path.parent().unwrap();
         let target = path.file_name().unwrap();
         std::fs::read_dir(parent)
             .unwrap()
             .filter_map(|e| e.ok())
             .find(|e| e.file_name() == target)
+            .map(DirEntry::from)
             .unwrap()
     }
 
@@ -3924,6 +4110,52 @@ mod tests {
         let _ = std::fs::remove_file(&path);
     }
 
+    #[test]
+    fn hash_of_whole_file_and_of_its_ends() {
+        let path = write_temp_file("hash.txt", b"headbodytail");
+        let entry = dir_entry_for(&path);
+        let hash = |algorithm: &str, args: Vec<String>| {
+            get_value(&Function::Hash, String::from(algorithm), args, Some(&entry), &None)
+                .unwrap()
+                .to_string()
+        };
+
+        assert_eq!(hash("md5", vec![]), "b54d98ca0b9ac82ccefedc4eba87e688");
+        assert_eq!(hash("MD5", vec![String::from("4")]), "96e89a298e0a9f469b9ae458d6afae9f");
+        assert_eq!(
+            hash("sha256", vec![String::from("-4")]),
+            "0c62f876ef1dea830de9f32c2f4b46dd6d74d50d15896e09ef5a2fcd4ac7e1d7"
+        );
+        assert_eq!(hash("crc32", vec![]), "9e46667c");
+
+        assert!(get_value(&Function::Hash, String::from("whirlpool"), vec![], Some(&entry), &None).is_err());
+        assert!(get_value(&Function::Hash, String::from("md5"), vec![String::from("x")], Some(&entry), &None).is_err());
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn hamming_counts_differing_bits() {
+        let hamming = |a: &str, b: &str| {
+            get_value(&Function::Hamming, String::from(a), vec![String::from(b)], None, &None).map(|v| v.to_string())
+        };
+
+        assert_eq!(hamming("ff7e9918e7668100", "ff7e9918e7668101"), Ok(String::from("1")));
+        assert_eq!(hamming("ff7e9918e7668100", ""), Ok(String::new()));
+        assert!(get_value(&Function::Hamming, String::from("ff"), vec![], None, &None).is_err());
+    }
+
+    #[test]
+    fn similarity_of_fuzzy_hashes() {
+        let similarity = |a: &str, b: &str| {
+            get_value(&Function::Similarity, String::from(a), vec![String::from(b)], None, &None).map(|v| v.to_string())
+        };
+
+        assert_eq!(similarity("3:abcdefgh:ab", "3:abcdefgh:ab"), Ok(String::from("100")));
+        assert_eq!(similarity("3:abcdefgh:ab", "garbage"), Ok(String::new()));
+        assert!(get_value(&Function::Similarity, String::from("3::"), vec![], None, &None).is_err());
+    }
+
     #[test]
     fn contains_finds_substring_crossing_chunk_boundary() {
         // Force the substring to straddle a chunk boundary. The streaming
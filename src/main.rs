@@ -31,7 +31,8 @@ use crate::output::OutputFormat;
 use crate::parser::Parser;
 use crate::query::RootOptions;
 use crate::searcher::Searcher;
-use crate::util::{set_us_dates, str_to_bool};
+use crate::util::{parse_filesize, set_us_dates, str_to_bool};
+use crate::util::hash_cache::HashCache;
 use crate::util::error::{error_message, get_no_errors, set_no_errors, set_use_colors};
 
 mod config;
@@ -112,6 +113,10 @@ fn main() -> ExitCode {
         return ExitCode::SUCCESS;
     }
 
+    if first_arg == "--prune-cache" {
+        return prune_hash_cache();
+    }
+
     #[allow(unused_mut)]
     let mut interactive = false;
 
@@ -142,6 +147,34 @@ fn main() -> ExitCode {
             config.everything = Some(true);
         } else if first_arg == "--plocate" {
             config.plocate = Some(true);
+        } else if first_arg == "--unique" {
+            config.unique = Some(true);
+        } else if first_arg == "--no-cache" {
+            config.hash_cache = Some(false);
+        } else if first_arg == "--progress" {
+            config.progress = Some(true);
+        } else if matches!(first_arg.as_str(), "--timeout" | "--max-files" | "--max-depth-default" | "--max-memory") {
+            let value = match args.get(1) {
+                Some(value) => value.clone(),
+                None => {
+                    eprintln!("Error: {} requires a value", first_arg);
+                    return ExitCode::from(2);
+                }
+            };
+
+            let parsed = match first_arg.as_str() {
+                "--timeout" => parse_timeout(&value).map(|secs| config.timeout = Some(secs)),
+                "--max-files" => value.parse().ok().map(|count| config.max_files = Some(count)),
+                "--max-memory" => parse_filesize(&value).map(|_| config.max_memory = Some(value.clone())),
+                _ => value.parse().ok().map(|depth| config.max_depth_default = Some(depth)),
+            };
+
+            if parsed.is_none() {
+                eprintln!("Error: invalid value for {}: {}", first_arg, value);
+                return ExitCode::from(2);
+            }
+
+            args.remove(0);
         } else {
             break;
         }
@@ -291,6 +324,20 @@ fn main() -> ExitCode {
     ExitCode::SUCCESS
 }
 
+fn prune_hash_cache() -> ExitCode {
+    let mut hash_cache = HashCache::new(true);
+    match hash_cache.prune() {
+        Ok((removed, remaining)) => {
+            println!("Removed {} stale hash cache entries, {} left", removed, remaining);
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            error_message("hash cache", &err.to_string());
+            ExitCode::from(2)
+        }
+    }
+}
+
 fn exec_search(query: Vec<String>, config: &mut Config, default_config: &Config, no_color: bool) -> u8 {
     if config.debug {
         dbg!(&query);
@@ -327,8 +374,13 @@ fn exec_search(query: Vec<String>, config: &mut Config, default_config: &Config,
                 }
             }
 
+            if let Some(limit) = searcher.limit_reached {
+                error_message("search", &format!("{}, results are incomplete", limit));
+            }
+
             match abort_code {
                 Some(code) => code,
+                None if searcher.limit_reached.is_some() => 3,
                 None if searcher.error_count > 0 => 1,
                 None => 0,
             }
@@ -340,6 +392,19 @@ fn exec_search(query: Vec<String>, config: &mut Config, default_config: &Config,
     }
 }
 
+/// Parses a timeout given in seconds, optionally with an `s`, `m` or `h` suffix.
+fn parse_timeout(value: &str) -> Option<u64> {
+    let value = value.trim().to_ascii_lowercase();
+    let (number, multiplier) = match value.char_indices().last()? {
+        (i, 's') => (&value[..i], 1),
+        (i, 'm') => (&value[..i], 60),
+        (i, 'h') => (&value[..i], 60 * 60),
+        _ => (value.as_str(), 1),
+    };
+
+    number.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
 fn short_usage_info(no_color: bool) {
     const VERSION: &str = env!("CARGO_PKG_VERSION");
 
@@ -585,11 +650,14 @@ fn complete_output_formats_info() {
 mod tests {
     #[cfg(feature = "interactive")]
     use super::extract_cd_path;
-    use super::exec_search;
+    use super::{exec_search, parse_timeout};
     use crate::config::Config;
 
     fn run_query(query: &str) -> u8 {
-        let mut config = Config::default();
+        run_query_with_config(query, Config::default())
+    }
+
+    fn run_query_with_config(query: &str, mut config: Config) -> u8 {
         let default_config = Config::default();
         exec_search(vec![String::from(query)], &mut config, &default_config, true)
     }
@@ -635,6 +703,28 @@ mod tests {
         assert_eq!(run_query(","), 2);
     }
 
+    #[test]
+    fn exec_search_returns_3_when_resource_limit_is_reached() {
+        let (tmp, path) = make_test_dir("fselect_exit_code_limit");
+        std::fs::write(tmp.join("b.txt"), "y").unwrap();
+        let mut config = Config::default();
+        config.max_files = Some(1);
+        let code = run_query_with_config(&format!("name from {} into json", path), config);
+        let _ = std::fs::remove_dir_all(&tmp);
+        assert_eq!(code, 3);
+    }
+
+    #[test]
+    fn test_parse_timeout() {
+        assert_eq!(parse_timeout("30"), Some(30));
+        assert_eq!(parse_timeout("30s"), Some(30));
+        assert_eq!(parse_timeout("5M"), Some(300));
+        assert_eq!(parse_timeout("2h"), Some(7200));
+        assert_eq!(parse_timeout("h"), None);
+        assert_eq!(parse_timeout("soon"), None);
+        assert_eq!(parse_timeout(""), None);
+    }
+
     #[cfg(feature = "interactive")]
     #[test]
     fn extract_cd_path_preserves_internal_whitespace() {
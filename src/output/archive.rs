@@ -0,0 +1,303 @@
+//! Handles export of the found files into a zip or tar archive.
+//! Rows carry the file to add and its name in the archive ahead of the
+//! selected columns, which are reported once the file has been added.
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+
+use flate2::write::GzEncoder;
+use zip::write::SimpleFileOptions;
+
+use crate::output::ResultsFormatter;
+use crate::util::Variant;
+use crate::util::datetime::{system_time_to_naive_local, to_zip_datetime};
+use crate::util::error::error_message;
+
+/// Name of the item holding the path of the file to add
+pub const ARCHIVE_SOURCE: &str = "\0source";
+/// Name of the item holding the name of the file in the archive
+pub const ARCHIVE_ENTRY: &str = "\0entry";
+
+const SEPARATOR: char = '\0';
+
+pub struct ArchiveFormatter;
+
+impl ResultsFormatter for ArchiveFormatter {
+    fn header(&mut self, _: &str, _: usize) -> Option<String> {
+        None
+    }
+
+    fn row_started(&mut self) -> Option<String> {
+        None
+    }
+
+    fn format_element(&mut self, name: &str, record: &Variant, is_last: bool) -> Option<String> {
+        match name == ARCHIVE_SOURCE || name == ARCHIVE_ENTRY {
+            true => Some(format!("{}{}", record, SEPARATOR)),
+            false if is_last => Some(record.to_string()),
+            false => Some(format!("{}\t", record)),
+        }
+    }
+
+    fn row_ended(&mut self) -> Option<String> {
+        Some(String::from("\n"))
+    }
+
+    fn footer(&mut self) -> Option<String> {
+        None
+    }
+}
+
+/// Name of the file in the archive: its path relative to the search root,
+/// with `/` as the separator. Paths outside the root lose their prefix.
+pub fn get_entry_name(path: &Path, root_path: &Path) -> String {
+    let relative_path = path.strip_prefix(root_path).unwrap_or(path);
+    relative_path
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Path of the archive being created, which doesn't exist until the first
+/// file is added, with the directory it's in resolved. Any path to the same
+/// file resolves to it, so that the archive isn't added to itself.
+pub fn resolve_output_path(path: &Path) -> Option<PathBuf> {
+    let file_name = path.file_name()?;
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    fs::canonicalize(dir).ok().map(|dir| dir.join(file_name))
+}
+
+enum ArchiveBuilder {
+    Zip(Box<zip::ZipWriter<File>>),
+    Tar(tar::Builder<File>),
+    TarGz(Box<tar::Builder<GzEncoder<File>>>),
+}
+
+pub struct ArchiveOutput {
+    path: PathBuf,
+    zip: bool,
+    builder: Option<ArchiveBuilder>,
+    /// Number of files that couldn't be added
+    failed_files: usize,
+}
+
+impl ArchiveOutput {
+    pub fn zip(path: &str) -> ArchiveOutput {
+        ArchiveOutput { path: PathBuf::from(path), zip: true, builder: None, failed_files: 0 }
+    }
+
+    pub fn tar(path: &str) -> ArchiveOutput {
+        ArchiveOutput { path: PathBuf::from(path), zip: false, builder: None, failed_files: 0 }
+    }
+
+    /// The archive is created with the first file added, or when finished.
+    fn builder(&mut self) -> io::Result<&mut ArchiveBuilder> {
+        if self.builder.is_none() {
+            let file = File::create(&self.path)?;
+            let name = self.path.to_string_lossy().to_lowercase();
+            self.builder = Some(if self.zip {
+                ArchiveBuilder::Zip(Box::new(zip::ZipWriter::new(file)))
+            } else if name.ends_with(".gz") || name.ends_with(".tgz") {
+                ArchiveBuilder::TarGz(Box::new(tar::Builder::new(GzEncoder::new(file, flate2::Compression::default()))))
+            } else {
+                ArchiveBuilder::Tar(tar::Builder::new(file))
+            });
+        }
+
+        Ok(self.builder.as_mut().unwrap())
+    }
+
+    /// Adds the file of a row to the archive and writes the rest of the row
+    /// as the report. Files that can't be added are reported as errors.
+    pub fn write_row(&mut self, writer: &mut dyn Write, rendered: &str) -> io::Result<()> {
+        let mut parts = rendered.splitn(3, SEPARATOR);
+        let (Some(source), Some(name), Some(report)) = (parts.next(), parts.next(), parts.next()) else {
+            return write!(writer, "{}", rendered);
+        };
+
+        if name.is_empty() {
+            return Ok(());
+        }
+
+        match self.add(Path::new(source), name) {
+            Ok(()) => write!(writer, "{}", report),
+            Err(err) => {
+                self.failed_files += 1;
+                error_message(source, &err.to_string());
+                Ok(())
+            }
+        }
+    }
+
+    pub fn failed_files(&self) -> usize {
+        self.failed_files
+    }
+
+    fn add(&mut self, source: &Path, name: &str) -> io::Result<()> {
+        match self.builder()? {
+            ArchiveBuilder::Zip(zip) => {
+                let metadata = fs::metadata(source)?;
+                let mut options = SimpleFileOptions::default().large_file(metadata.len() >= u32::MAX as u64);
+                if let Some(modified) = metadata
+                    .modified()
+                    .ok()
+                    .and_then(system_time_to_naive_local)
+                    .and_then(|modified| to_zip_datetime(&modified))
+                {
+                    options = options.last_modified_time(modified);
+                }
+                #[cfg(unix)]
+                {
+                    use std::os::unix::fs::PermissionsExt;
+                    options = options.unix_permissions(metadata.permissions().mode());
+                }
+
+                if metadata.is_dir() {
+                    zip.add_directory(name, options).map_err(io::Error::other)
+                } else {
+                    let mut file = File::open(source)?;
+                    zip.start_file(name, options).map_err(io::Error::other)?;
+                    io::copy(&mut file, zip.as_mut()).map(|_| ())
+                }
+            }
+            // Tar keeps the mode, the owner and the modification time of the file
+            ArchiveBuilder::Tar(tar) => tar.append_path_with_name(source, name),
+            ArchiveBuilder::TarGz(tar) => tar.append_path_with_name(source, name),
+        }
+    }
+
+    /// Writes the end of the archive, creating an empty one if no files were found.
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.builder()?;
+        match self.builder.take() {
+            Some(ArchiveBuilder::Zip(zip)) => zip.finish().map(|_| ()).map_err(io::Error::other),
+            Some(ArchiveBuilder::Tar(tar)) => tar.into_inner().map(|_| ()),
+            Some(ArchiveBuilder::TarGz(tar)) => tar.into_inner()?.finish().map(|_| ()),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::output::test::write_test_items;
+
+    #[test]
+    fn test() {
+        let result = write_test_items(&mut ArchiveFormatter);
+        assert_eq!("foo_value\tBAR value\n123\t\n", result);
+
+        let mut formatter = ArchiveFormatter;
+        let source = formatter.format_element(ARCHIVE_SOURCE, &Variant::from_string(&String::from("/logs/app.log")), false).unwrap();
+        let entry = formatter.format_element(ARCHIVE_ENTRY, &Variant::from_string(&String::from("app.log")), false).unwrap();
+        assert_eq!(source + &entry, "/logs/app.log\0app.log\0");
+    }
+
+    fn write_archive(archive: &mut ArchiveOutput, dir: &Path) -> String {
+        let mut report = Vec::new();
+        for name in ["logs", "logs/app.log", "missing.log"] {
+            let source = dir.join("src").join(name);
+            let rendered = format!("{}\0{}\0{}\t1\n", source.to_string_lossy(), name, name);
+            archive.write_row(&mut report, &rendered).unwrap();
+        }
+        archive.finish().unwrap();
+        String::from_utf8(report).unwrap()
+    }
+
+    #[test]
+    fn test_zip_output() {
+        let dir = std::env::temp_dir().join("fselect_test_zip_output");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("src/logs")).unwrap();
+        fs::write(dir.join("src/logs/app.log"), "log line\n").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(dir.join("src/logs/app.log"), fs::Permissions::from_mode(0o640)).unwrap();
+        }
+
+        let out = dir.join("out.zip");
+        let mut archive = ArchiveOutput::zip(&out.to_string_lossy());
+        let report = write_archive(&mut archive, &dir);
+        let failed_files = archive.failed_files();
+
+        let mut zip = zip::ZipArchive::new(File::open(&out).unwrap()).unwrap();
+        let names: Vec<&str> = zip.file_names().collect();
+        assert_eq!(names.len(), 2);
+        let mut entry = zip.by_name("logs/app.log").unwrap();
+        assert!(entry.last_modified().is_some());
+        #[cfg(unix)]
+        assert_eq!(entry.unix_mode().map(|mode| mode & 0o777), Some(0o640));
+        let mut content = String::new();
+        io::Read::read_to_string(&mut entry, &mut content).unwrap();
+        drop(entry);
+        assert!(zip.by_name("logs/").unwrap().is_dir());
+        let _ = fs::remove_dir_all(&dir);
+
+        // Missing files aren't reported as added
+        assert_eq!(content, "log line\n");
+        assert_eq!(failed_files, 1);
+        assert_eq!(report, "logs\t1\nlogs/app.log\t1\n");
+    }
+
+    #[test]
+    fn test_tar_gz_output() {
+        let dir = std::env::temp_dir().join("fselect_test_tar_gz_output");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("src/logs")).unwrap();
+        fs::write(dir.join("src/logs/app.log"), "log line\n").unwrap();
+
+        let out = dir.join("out.tar.gz");
+        let report = write_archive(&mut ArchiveOutput::tar(&out.to_string_lossy()), &dir);
+
+        let modified = fs::metadata(dir.join("src/logs/app.log")).unwrap().modified().unwrap();
+        let mut tar = crate::fileinfo::open_tar_archive(File::open(&out).unwrap()).unwrap();
+        let entries: Vec<(String, u64)> = tar
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                (entry.path().unwrap().to_string_lossy().to_string(), entry.header().mtime().unwrap())
+            })
+            .collect();
+        let _ = fs::remove_dir_all(&dir);
+
+        let mtime = modified.duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+        assert_eq!(entries, vec![(String::from("logs"), entries[0].1), (String::from("logs/app.log"), mtime)]);
+        assert_eq!(report, "logs\t1\nlogs/app.log\t1\n");
+    }
+
+    #[test]
+    fn test_resolve_output_path() {
+        let dir = std::env::temp_dir().join("fselect_test_resolve_output_path");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("sub")).unwrap();
+
+        let resolved = resolve_output_path(&dir.join("sub/../out.zip"));
+        let direct = resolve_output_path(&dir.join("out.zip"));
+        let missing_dir = resolve_output_path(&dir.join("missing/out.zip"));
+        let _ = fs::remove_dir_all(&dir);
+
+        assert_eq!(resolved, direct);
+        assert!(direct.unwrap().is_absolute());
+        assert_eq!(missing_dir, None);
+        assert_eq!(resolve_output_path(Path::new("out.zip")), Some(std::env::current_dir().unwrap().join("out.zip")));
+    }
+
+    #[test]
+    fn test_get_entry_name() {
+        assert_eq!(get_entry_name(Path::new("/var/log/app/x.log"), Path::new("/var/log")), "app/x.log");
+        assert_eq!(get_entry_name(Path::new("./src/main.rs"), Path::new(".")), "src/main.rs");
+        assert_eq!(get_entry_name(Path::new("/etc/hosts"), Path::new(".")), "etc/hosts");
+        assert_eq!(get_entry_name(Path::new("../other/x"), Path::new(".")), "other/x");
+    }
+}
@@ -1,6 +1,7 @@
 //! Handles export of results in CSV format
 
 use crate::output::ResultsFormatter;
+use crate::util::Variant;
 
 #[derive(Default)]
 pub struct CsvFormatter {
@@ -16,8 +17,8 @@ impl ResultsFormatter for CsvFormatter {
         None
     }
 
-    fn format_element(&mut self, _: &str, record: &str, _is_last: bool) -> Option<String> {
-        self.records.push(record.to_owned());
+    fn format_element(&mut self, _: &str, record: &Variant, _is_last: bool) -> Option<String> {
+        self.records.push(record.to_string());
         None
     }
 
@@ -57,13 +58,14 @@ mod test {
     #[test]
     fn multibyte_row_larger_than_internal_buffer_is_not_dropped() {
         use crate::output::ResultsFormatter;
+        use crate::util::Variant;
 
         // A row larger than the csv writer's 8KB internal buffer used to be
         // flushed at a non-character byte boundary and silently discarded.
         let mut formatter = CsvFormatter::default();
         let big_value = "é".repeat(6000); // 12000 bytes
-        formatter.format_element("col1", &big_value, false);
-        formatter.format_element("col2", "x", true);
+        formatter.format_element("col1", &Variant::from_string(&big_value), false);
+        formatter.format_element("col2", &Variant::from_string(&String::from("x")), true);
 
         let row = formatter.row_ended().expect("row must be produced");
         assert!(row.contains(&big_value));
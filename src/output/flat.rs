@@ -1,6 +1,7 @@
 //! Handles export of results in line-separated, list-separated, and tab-separated formats
 
 use crate::output::ResultsFormatter;
+use crate::util::Variant;
 
 pub const LINES_FORMATTER: FlatWriter = FlatWriter {
     record_separator: '\n',
@@ -31,7 +32,7 @@ impl ResultsFormatter for FlatWriter {
         None
     }
 
-    fn format_element(&mut self, _: &str, record: &str, is_last: bool) -> Option<String> {
+    fn format_element(&mut self, _: &str, record: &Variant, is_last: bool) -> Option<String> {
         match is_last {
             true => Some(record.to_string()),
             false => Some(format!("{}{}", record, self.record_separator)),
@@ -1,6 +1,7 @@
 //! Handles export of results in HTML format
 
 use crate::output::ResultsFormatter;
+use crate::util::Variant;
 
 pub struct HtmlFormatter;
 
@@ -28,8 +29,8 @@ impl ResultsFormatter for HtmlFormatter {
         Some("<tr>".to_owned())
     }
 
-    fn format_element(&mut self, _: &str, record: &str, _is_last: bool) -> Option<String> {
-        Some(format!("<td>{}</td>", escape_html(record)))
+    fn format_element(&mut self, _: &str, record: &Variant, _is_last: bool) -> Option<String> {
+        Some(format!("<td>{}</td>", escape_html(&record.to_string())))
     }
 
     fn row_ended(&mut self) -> Option<String> {
@@ -1,28 +1,58 @@
-//! Handles export of results in JSON format
+//! Handles export of results in JSON and JSON Lines formats
 
 use std::collections::HashMap;
 
+use serde_json::Value;
+
 use crate::output::ResultsFormatter;
+use crate::util::{Variant, VariantType};
+
+pub const JSON_FORMATTER: JsonFormatter = JsonFormatter {
+    lines: false,
+    row: Vec::new(),
+};
+
+/// Writes every row as an object on its own line, so the results can be
+/// consumed while the search goes on.
+pub const JSON_LINES_FORMATTER: JsonFormatter = JsonFormatter {
+    lines: true,
+    row: Vec::new(),
+};
 
-#[derive(Default)]
 pub struct JsonFormatter {
+    lines: bool,
     /// Column values in the order they were emitted. Stored as a `Vec` rather
     /// than a map so we preserve the user's SELECT column order and so we can
     /// disambiguate duplicate column names below.
-    row: Vec<(String, String)>,
+    row: Vec<(String, Value)>,
+}
+
+/// Numbers and booleans are written as such, and missing ones as `null`.
+fn to_json_value(record: &Variant) -> Value {
+    let value = record.to_string();
+    match record.get_type() {
+        VariantType::String => Value::String(value),
+        _ if value.is_empty() => Value::Null,
+        VariantType::Int | VariantType::Float => value.parse().map_or(Value::String(value), Value::Number),
+        VariantType::Bool => Value::Bool(record.to_bool()),
+        VariantType::DateTime => Value::String(value),
+    }
 }
 
 impl ResultsFormatter for JsonFormatter {
     fn header(&mut self, _: &str, _: usize) -> Option<String> {
-        Some("[".to_owned())
+        match self.lines {
+            true => None,
+            false => Some("[".to_owned()),
+        }
     }
 
     fn row_started(&mut self) -> Option<String> {
         None
     }
 
-    fn format_element(&mut self, name: &str, record: &str, _is_last: bool) -> Option<String> {
-        self.row.push((name.to_owned(), record.to_owned()));
+    fn format_element(&mut self, name: &str, record: &Variant, _is_last: bool) -> Option<String> {
+        self.row.push((name.to_owned(), to_json_value(record)));
         None
     }
 
@@ -45,35 +75,48 @@ impl ResultsFormatter for JsonFormatter {
             };
             out.push_str(&serde_json::to_string(&resolved).unwrap_or_else(|_| "\"\"".to_string()));
             out.push(':');
-            out.push_str(&serde_json::to_string(value).unwrap_or_else(|_| "\"\"".to_string()));
+            out.push_str(&value.to_string());
         }
         out.push('}');
+        if self.lines {
+            out.push('\n');
+        }
         self.row.clear();
         Some(out)
     }
 
     fn footer(&mut self) -> Option<String> {
-        Some("]".to_owned())
+        match self.lines {
+            true => None,
+            false => Some("]".to_owned()),
+        }
     }
 
     fn row_separator(&self) -> Option<String> {
-        Some(",".to_owned())
+        match self.lines {
+            true => None,
+            false => Some(",".to_owned()),
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::output::ResultsFormatter;
-    use crate::output::json::JsonFormatter;
+    #![allow(const_item_mutation)]
+    use super::*;
     use crate::output::test::write_test_items;
 
+    fn string(value: &str) -> Variant {
+        Variant::from_string(&value.to_string())
+    }
+
     #[test]
     fn test() {
-        let result = write_test_items(&mut JsonFormatter::default());
+        let result = write_test_items(&mut JSON_FORMATTER);
         // Columns must appear in the order they were emitted (foo, bar),
         // not alphabetised by a BTreeMap.
         assert_eq!(
-            r#"[{"foo":"foo_value","bar":"BAR value"},{"foo":"123","bar":""}]"#,
+            r#"[{"foo":"foo_value","bar":"BAR value"},{"foo":123,"bar":null}]"#,
             result
         );
     }
@@ -83,13 +126,13 @@ mod test {
         // Duplicate column names within one row must produce a *valid* JSON
         // object: first occurrence keeps the name, subsequent ones are
         // suffixed with `_2`, `_3`, ...
-        let mut f = JsonFormatter::default();
+        let mut f = JSON_FORMATTER;
         let mut out = String::new();
         if let Some(s) = f.header("", 3) { out.push_str(&s); }
         if let Some(s) = f.row_started() { out.push_str(&s); }
-        if let Some(s) = f.format_element("name", "first", false) { out.push_str(&s); }
-        if let Some(s) = f.format_element("name", "second", false) { out.push_str(&s); }
-        if let Some(s) = f.format_element("name", "third", true) { out.push_str(&s); }
+        if let Some(s) = f.format_element("name", &string("first"), false) { out.push_str(&s); }
+        if let Some(s) = f.format_element("name", &string("second"), false) { out.push_str(&s); }
+        if let Some(s) = f.format_element("name", &string("third"), true) { out.push_str(&s); }
         if let Some(s) = f.row_ended() { out.push_str(&s); }
         if let Some(s) = f.footer() { out.push_str(&s); }
         assert_eq!(
@@ -102,16 +145,16 @@ mod test {
     fn duplicate_counter_resets_between_rows() {
         // The duplicate-suffix counter is per-row state and must not leak
         // into subsequent rows.
-        let mut f = JsonFormatter::default();
+        let mut f = JSON_FORMATTER;
         let mut out = String::new();
         if let Some(s) = f.header("", 1) { out.push_str(&s); }
         if let Some(s) = f.row_started() { out.push_str(&s); }
-        if let Some(s) = f.format_element("name", "a", false) { out.push_str(&s); }
-        if let Some(s) = f.format_element("name", "b", true) { out.push_str(&s); }
+        if let Some(s) = f.format_element("name", &string("a"), false) { out.push_str(&s); }
+        if let Some(s) = f.format_element("name", &string("b"), true) { out.push_str(&s); }
         if let Some(s) = f.row_ended() { out.push_str(&s); }
         if let Some(s) = f.row_separator() { out.push_str(&s); }
         if let Some(s) = f.row_started() { out.push_str(&s); }
-        if let Some(s) = f.format_element("name", "c", true) { out.push_str(&s); }
+        if let Some(s) = f.format_element("name", &string("c"), true) { out.push_str(&s); }
         if let Some(s) = f.row_ended() { out.push_str(&s); }
         if let Some(s) = f.footer() { out.push_str(&s); }
         assert_eq!(
@@ -119,4 +162,35 @@ mod test {
             r#"[{"name":"a","name_2":"b"},{"name":"c"}]"#,
         );
     }
+
+    #[test]
+    fn values_keep_their_types() {
+        let mut f = JSON_FORMATTER;
+        let mut out = String::new();
+        if let Some(s) = f.row_started() { out.push_str(&s); }
+        let values = [
+            ("size", Variant::from_int(1024)),
+            ("ratio", Variant::from_float(2.5)),
+            ("is_dir", Variant::from_bool(false)),
+            ("width", Variant::empty(VariantType::Int)),
+            ("name", string("42")),
+        ];
+        for (name, value) in values.iter() {
+            if let Some(s) = f.format_element(name, value, false) { out.push_str(&s); }
+        }
+        if let Some(s) = f.row_ended() { out.push_str(&s); }
+        assert_eq!(
+            out,
+            r#"{"size":1024,"ratio":2.5,"is_dir":false,"width":null,"name":"42"}"#,
+        );
+    }
+
+    #[test]
+    fn json_lines() {
+        let result = write_test_items(&mut JSON_LINES_FORMATTER);
+        assert_eq!(
+            "{\"foo\":\"foo_value\",\"bar\":\"BAR value\"}\n{\"foo\":123,\"bar\":null}\n",
+            result
+        );
+    }
 }
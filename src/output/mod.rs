@@ -1,36 +1,56 @@
+use std::borrow::Cow;
 use std::io::Write;
 
+use crate::output::archive::{ArchiveFormatter, ArchiveOutput};
 use crate::output::csv::CsvFormatter;
 use crate::output::flat::{LINES_FORMATTER, LIST_FORMATTER, TABS_FORMATTER};
 use crate::output::html::HtmlFormatter;
-use crate::output::json::JsonFormatter;
+use crate::output::json::{JSON_FORMATTER, JSON_LINES_FORMATTER};
+use crate::output::table::TableFormatter;
 pub(crate) use crate::query::OutputFormat;
+use crate::util::Variant;
 
+pub(crate) mod archive;
 mod csv;
 mod flat;
 mod html;
 mod json;
+mod table;
 
 pub trait ResultsFormatter {
     fn header(&mut self, raw_query: &str, col_count: usize) -> Option<String>;
     fn row_started(&mut self) -> Option<String>;
-    fn format_element(&mut self, name: &str, record: &str, is_last: bool) -> Option<String>;
+    fn format_element(&mut self, name: &str, record: &Variant, is_last: bool) -> Option<String>;
     fn row_ended(&mut self) -> Option<String>;
     fn footer(&mut self) -> Option<String>;
 
     fn row_separator(&self) -> Option<String> {
         None
     }
+
+    /// Text to write out for a formatted row. Formats that look at the rows
+    /// first, like the table measuring its columns, keep it and return `None`.
+    fn collect_row<'r>(&mut self, rendered: &'r str) -> Option<Cow<'r, str>> {
+        Some(Cow::Borrowed(rendered))
+    }
 }
 
 pub struct ResultsWriter {
     formatter: Box<dyn ResultsFormatter>,
+    archive: Option<ArchiveOutput>,
 }
 
 impl ResultsWriter {
-    pub fn new(format: &OutputFormat) -> ResultsWriter {
+    pub fn new(format: &OutputFormat, output_path: &Option<String>) -> ResultsWriter {
+        let archive = match (format, output_path) {
+            (OutputFormat::Zip, Some(path)) => Some(ArchiveOutput::zip(path)),
+            (OutputFormat::Tar, Some(path)) => Some(ArchiveOutput::tar(path)),
+            _ => None,
+        };
+
         ResultsWriter {
             formatter: select_formatter(format),
+            archive,
         }
     }
 
@@ -49,7 +69,7 @@ impl ResultsWriter {
     pub fn write_row(
         &mut self,
         writer: &mut dyn Write,
-        values: Vec<(String, String)>,
+        values: Vec<(String, Variant)>,
     ) -> std::io::Result<()> {
         self.write_row_start(writer)?;
         let len = values.len();
@@ -59,7 +79,24 @@ impl ResultsWriter {
         self.write_row_end(writer)
     }
 
+    /// Writes a row formatted before, adding its file to the archive being created if any.
+    pub fn write_rendered(&mut self, writer: &mut dyn Write, rendered: &str) -> std::io::Result<()> {
+        match self.archive {
+            Some(ref mut archive) => archive.write_row(writer, rendered),
+            None => self.formatter.collect_row(rendered).map_or(Ok(()), |value| write!(writer, "{}", value)),
+        }
+    }
+
+    /// Number of found files that couldn't be added to the archive being created.
+    pub fn failed_files(&self) -> usize {
+        self.archive.as_ref().map_or(0, ArchiveOutput::failed_files)
+    }
+
     pub fn write_footer(&mut self, writer: &mut dyn Write) -> std::io::Result<()> {
+        if let Some(ref mut archive) = self.archive {
+            archive.finish()?;
+        }
+
         self.formatter
             .footer()
             .map_or(Ok(()), |value| write!(writer, "{}", value))
@@ -74,7 +111,7 @@ impl ResultsWriter {
         &mut self,
         writer: &mut dyn Write,
         name: &str,
-        value: &str,
+        value: &Variant,
         is_last: bool,
     ) -> std::io::Result<()> {
         self.formatter
@@ -95,14 +132,18 @@ fn select_formatter(format: &OutputFormat) -> Box<dyn ResultsFormatter> {
         OutputFormat::Lines => Box::new(LINES_FORMATTER),
         OutputFormat::List => Box::new(LIST_FORMATTER),
         OutputFormat::Csv => Box::<CsvFormatter>::default(),
-        OutputFormat::Json => Box::<JsonFormatter>::default(),
+        OutputFormat::Json => Box::new(JSON_FORMATTER),
+        OutputFormat::JsonLines => Box::new(JSON_LINES_FORMATTER),
         OutputFormat::Html => Box::new(HtmlFormatter),
+        OutputFormat::Table => Box::<TableFormatter>::default(),
+        OutputFormat::Zip | OutputFormat::Tar => Box::new(ArchiveFormatter),
     }
 }
 
 #[cfg(test)]
 mod test {
     use crate::output::ResultsFormatter;
+    use crate::util::{Variant, VariantType};
 
     pub(crate) fn write_test_items<T: ResultsFormatter>(under_test: &mut T) -> String {
         let mut result = String::from("");
@@ -110,9 +151,9 @@ mod test {
         if let Some(s) = under_test
             .row_started() { let _: () = result.push_str(&s); }
         if let Some(s) = under_test
-            .format_element("foo", "foo_value", false) { let _: () = result.push_str(&s); }
+            .format_element("foo", &Variant::from_string(&String::from("foo_value")), false) { let _: () = result.push_str(&s); }
         if let Some(s) = under_test
-            .format_element("bar", "BAR value", true) { let _: () = result.push_str(&s); }
+            .format_element("bar", &Variant::from_string(&String::from("BAR value")), true) { let _: () = result.push_str(&s); }
         if let Some(s) = under_test
             .row_ended() { let _: () = result.push_str(&s); }
         if let Some(s) = under_test
@@ -120,9 +161,9 @@ mod test {
         if let Some(s) = under_test
             .row_started() { let _: () = result.push_str(&s); }
         if let Some(s) = under_test
-            .format_element("foo", "123", false) { let _: () = result.push_str(&s); }
+            .format_element("foo", &Variant::from_int(123), false) { let _: () = result.push_str(&s); }
         if let Some(s) = under_test
-            .format_element("bar", "", true) { let _: () = result.push_str(&s); }
+            .format_element("bar", &Variant::empty(VariantType::Int), true) { let _: () = result.push_str(&s); }
         if let Some(s) = under_test
             .row_ended() { let _: () = result.push_str(&s); }
         if let Some(s) = under_test.footer() { let _: () = result.push_str(&s); }
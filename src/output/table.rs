@@ -0,0 +1,284 @@
+//! Handles export of results as a table aligned for reading in the terminal
+
+use std::borrow::Cow;
+
+use unicode_width::UnicodeWidthChar;
+
+use crate::output::ResultsFormatter;
+use crate::util::Variant;
+
+/// Wider values are cut to this many columns on the screen
+const MAX_COLUMN_WIDTH: usize = 60;
+
+/// Rows kept in memory to measure the columns. The rows after them are
+/// written out right away, and their values wider than a column are cut.
+const MEASURED_ROWS: usize = 1000;
+
+const CELL_SEPARATOR: char = '\0';
+
+const ESCAPE: char = '\x1b';
+const RESET: &str = "\x1b[0m";
+
+/// Rows are written out once the column widths are known, from the first
+/// `MEASURED_ROWS` rows, or in the footer if there are fewer.
+#[derive(Default)]
+pub struct TableFormatter {
+    names: Vec<String>,
+    row_names: Vec<String>,
+    rows: Vec<Vec<String>>,
+    widths: Option<Vec<usize>>,
+}
+
+impl ResultsFormatter for TableFormatter {
+    fn header(&mut self, _: &str, _: usize) -> Option<String> {
+        None
+    }
+
+    fn row_started(&mut self) -> Option<String> {
+        self.row_names.clear();
+        None
+    }
+
+    fn format_element(&mut self, name: &str, record: &Variant, is_last: bool) -> Option<String> {
+        self.row_names.push(name.to_owned());
+        // Line breaks and tabs would break the alignment
+        let record = record.to_string().replace(['\n', '\r', '\t', CELL_SEPARATOR], " ");
+        match is_last {
+            true => Some(record),
+            false => Some(format!("{}{}", record, CELL_SEPARATOR)),
+        }
+    }
+
+    fn row_ended(&mut self) -> Option<String> {
+        if self.names.is_empty() {
+            self.names = std::mem::take(&mut self.row_names);
+        }
+        None
+    }
+
+    fn footer(&mut self) -> Option<String> {
+        match self.rows.is_empty() {
+            true => None,
+            false => Some(self.write_measured_rows()),
+        }
+    }
+
+    fn collect_row<'r>(&mut self, rendered: &'r str) -> Option<Cow<'r, str>> {
+        let cells: Vec<String> = rendered.split(CELL_SEPARATOR).map(String::from).collect();
+        if let Some(ref widths) = self.widths {
+            let cells: Vec<String> = cells.iter().zip(widths).map(|(cell, width)| truncate_to(cell, *width)).collect();
+            return Some(Cow::Owned(format_line(&cells, widths)));
+        }
+
+        self.rows.push(cells);
+        match self.rows.len() >= MEASURED_ROWS {
+            true => Some(Cow::Owned(self.write_measured_rows())),
+            false => None,
+        }
+    }
+}
+
+impl TableFormatter {
+    /// Measures the columns from the rows kept so far, and writes them out with the column names.
+    fn write_measured_rows(&mut self) -> String {
+        let rows = std::mem::take(&mut self.rows);
+        let col_count = rows.iter().map(Vec::len).max().unwrap_or(0);
+        let names: Vec<String> = (0..col_count)
+            .map(|i| self.names.get(i).map(|name| truncate(name)).unwrap_or_default())
+            .collect();
+        let rows: Vec<Vec<String>> = rows
+            .iter()
+            .map(|row| row.iter().map(|cell| truncate(cell)).collect())
+            .collect();
+
+        let mut widths: Vec<usize> = names.iter().map(|name| display_width(name)).collect();
+        for row in &rows {
+            for (i, cell) in row.iter().enumerate() {
+                widths[i] = widths[i].max(display_width(cell));
+            }
+        }
+
+        let mut result = format_line(&names, &widths);
+        let rule: Vec<String> = widths.iter().map(|width| "-".repeat(*width)).collect();
+        result.push_str(&rule.join("-+-"));
+        result.push('\n');
+        for row in &rows {
+            result.push_str(&format_line(row, &widths));
+        }
+
+        self.widths = Some(widths);
+        result
+    }
+}
+
+fn format_line(cells: &[String], widths: &[usize]) -> String {
+    let mut padded = vec![];
+    for (i, width) in widths.iter().enumerate() {
+        let cell = cells.get(i).map(String::as_str).unwrap_or_default();
+        let padding = width - display_width(cell);
+        padded.push(format!("{}{}", cell, " ".repeat(padding)));
+    }
+
+    // The last column is not padded to keep the lines free of trailing spaces
+    let mut line = padded.join(" | ").trim_end().to_string();
+    line.push('\n');
+    line
+}
+
+/// Number of columns the value takes on the screen. Escape sequences that
+/// color the value take none, wide characters like CJK ideographs take two.
+fn display_width(s: &str) -> usize {
+    let mut width = 0;
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == ESCAPE {
+            skip_escape_sequence(&mut chars);
+        } else {
+            width += c.width().unwrap_or(0);
+        }
+    }
+    width
+}
+
+/// Cuts the value to `MAX_COLUMN_WIDTH` columns on the screen, ending it with an ellipsis.
+fn truncate(s: &str) -> String {
+    truncate_to(s, MAX_COLUMN_WIDTH)
+}
+
+/// Cuts the value to `max_width` columns on the screen, ending it with an ellipsis.
+fn truncate_to(s: &str, max_width: usize) -> String {
+    if display_width(s) <= max_width {
+        return s.to_string();
+    }
+    if max_width == 0 {
+        return String::new();
+    }
+
+    let mut result = String::new();
+    let mut width = 0;
+    let mut colored = false;
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == ESCAPE {
+            let before = chars.as_str();
+            skip_escape_sequence(&mut chars);
+            result.push(c);
+            result.push_str(&before[..before.len() - chars.as_str().len()]);
+            colored = true;
+            continue;
+        }
+
+        let char_width = c.width().unwrap_or(0);
+        if width + char_width > max_width - 1 {
+            break;
+        }
+        width += char_width;
+        result.push(c);
+    }
+
+    result.push('…');
+    if colored {
+        result.push_str(RESET);
+    }
+    result
+}
+
+/// Skips a CSI sequence like `[1;34m`, the escape character is already taken.
+fn skip_escape_sequence(chars: &mut std::str::Chars) {
+    if chars.clone().next() != Some('[') {
+        return;
+    }
+    chars.next();
+    for c in chars.by_ref() {
+        if ('\x40'..='\x7e').contains(&c) {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn write_rows(formatter: &mut TableFormatter, rows: &[&[(&str, &str)]]) -> String {
+        let mut result = String::new();
+        for row in rows {
+            let mut rendered = formatter.row_started().unwrap_or_default();
+            for (i, (name, value)) in row.iter().enumerate() {
+                rendered.push_str(&formatter.format_element(name, &Variant::from_string(&value.to_string()), i == row.len() - 1).unwrap_or_default());
+            }
+            rendered.push_str(&formatter.row_ended().unwrap_or_default());
+            if let Some(text) = formatter.collect_row(&rendered) {
+                result.push_str(&text);
+            }
+        }
+        result.push_str(&formatter.footer().unwrap_or_default());
+        result
+    }
+
+    #[test]
+    fn test() {
+        let result = write_rows(
+            &mut TableFormatter::default(),
+            &[
+                &[("name", "readme.md"), ("size", "1024")],
+                &[("name", "日本.txt"), ("size", "5")],
+                &[("name", "a\tb"), ("size", "")],
+            ],
+        );
+        assert_eq!(
+            result,
+            "name      | size\n\
+             ----------+-----\n\
+             readme.md | 1024\n\
+             日本.txt  | 5\n\
+             a b       |\n"
+        );
+    }
+
+    #[test]
+    fn test_rows_after_the_measured_ones() {
+        let mut formatter = TableFormatter::default();
+        let measured: Vec<[(&str, &str); 2]> = (0..MEASURED_ROWS).map(|_| [("name", "a.txt"), ("size", "10")]).collect();
+        let measured: Vec<&[(&str, &str)]> = measured.iter().map(|row| &row[..]).collect();
+        let result = write_rows(&mut formatter, &measured);
+        assert!(formatter.rows.is_empty());
+        assert_eq!(result.lines().count(), MEASURED_ROWS + 2);
+        assert!(result.starts_with("name  | size\n------+-----\na.txt | 10\n"));
+
+        // Written right away, cut to the measured columns
+        let result = write_rows(&mut formatter, &[&[("name", "longer.txt"), ("size", "123456")], &[("name", "b"), ("size", "")]]);
+        assert_eq!(result, "long… | 123…\nb     |\n");
+        assert!(formatter.rows.is_empty());
+    }
+
+    #[test]
+    fn test_no_rows() {
+        assert_eq!(write_rows(&mut TableFormatter::default(), &[]), "");
+    }
+
+    #[test]
+    fn test_display_width() {
+        assert_eq!(display_width("abc"), 3);
+        assert_eq!(display_width("日本"), 4);
+        assert_eq!(display_width("\x1b[1;34mabc\x1b[0m"), 3);
+    }
+
+    #[test]
+    fn test_truncate() {
+        let long = "x".repeat(100);
+        let truncated = truncate(&long);
+        assert_eq!(display_width(&truncated), MAX_COLUMN_WIDTH);
+        assert!(truncated.ends_with('…'));
+
+        let colored = format!("\x1b[1;34m{}\x1b[0m", long);
+        let truncated = truncate(&colored);
+        assert_eq!(display_width(&truncated), MAX_COLUMN_WIDTH);
+        assert!(truncated.starts_with("\x1b[1;34mxxx"));
+        assert!(truncated.ends_with(RESET));
+
+        assert_eq!(truncate("short"), "short");
+        assert_eq!(truncate_to("short", 3), "sh…");
+        assert_eq!(truncate_to("short", 0), "");
+    }
+}
@@ -1,11 +1,8 @@
 //! Handles the parsing of the query string
 
 use std::collections::HashSet;
-use std::path::PathBuf;
 use std::str::FromStr;
 
-#[cfg(feature = "interactive")]
-use directories::UserDirs;
 use crate::expr::Expr;
 use crate::field::Field;
 use crate::function::Function;
@@ -17,9 +14,11 @@ use crate::operators::Op;
 use crate::query::Query;
 use crate::query::Root;
 use crate::query::TraversalMode::{Bfs, Dfs};
-use crate::query::{OutputFormat, RootOptions};
+use crate::query::{EntryOrder, OutputFormat, RootOptions};
 #[cfg(not(feature = "git"))]
 use crate::util::error::error_message;
+use crate::util::hashes::HashAlgorithm;
+use crate::util::{expand_root_path, PATH_LIST_ROOT_PREFIX, STDIN_ROOT};
 
 pub struct Parser<'a> {
     lexer: &'a mut Lexer,
@@ -95,7 +94,13 @@ impl <'a> Parser<'a> {
             }
         }
 
-        let output_format = self.parse_output_format()?;
+        let (output_format, output_path) = self.parse_output_format()?;
+
+        if output_format.is_archive()
+            && (!grouping_fields.is_empty() || fields.iter().any(|expr| expr.has_aggregate_function()))
+        {
+            return Err(String::from("Archive output needs file rows, it can't be used with aggregates or GROUP BY"));
+        }
 
         if roots.is_empty() {
             roots = self.parse_roots()?;
@@ -105,6 +110,8 @@ impl <'a> Parser<'a> {
             roots.push(Root::default(root_options));
         }
 
+        let roots = Self::expand_roots(roots);
+
         // A SELECT list that touches no file fields (e.g. `select 1+2`) yields
         // the same row for every file, so collapse it to a single row. This
         // must not apply to aggregate or grouped queries: `count(*)` requires
@@ -131,6 +138,7 @@ impl <'a> Parser<'a> {
             limit,
             offset,
             output_format,
+            output_path,
             raw_query,
         })
     }
@@ -280,14 +288,6 @@ impl <'a> Parser<'a> {
                         Lexeme::String(s) | Lexeme::RawString(s) => match mode {
                             RootParsingMode::From | RootParsingMode::Comma => {
                                 path = s.to_string();
-                                #[cfg(feature = "interactive")]
-                                if path.starts_with("~")
-                                    && let Some(ud) = UserDirs::new() {
-                                        let mut pb = PathBuf::from(path.clone());
-                                        pb = pb.components().skip(1).collect();
-                                        pb = ud.home_dir().to_path_buf().join(pb);
-                                        path = pb.to_string_lossy().to_string();
-                                    }
                                 mode = RootParsingMode::Root;
                             }
                             RootParsingMode::Root => {
@@ -363,6 +363,32 @@ impl <'a> Parser<'a> {
         Ok(roots)
     }
 
+    /// Expands `~`, environment variables, braces and globs in root paths.
+    /// Every resulting path becomes a separate root with the same options.
+    /// Regexp roots are matched later by the searcher and are left as is.
+    /// For `@file` roots the expansion applies to the list file name.
+    fn expand_roots(roots: Vec<Root>) -> Vec<Root> {
+        roots
+            .into_iter()
+            .flat_map(|root| {
+                if root.subquery.is_some() || root.options.regexp || root.path == STDIN_ROOT {
+                    return vec![root];
+                }
+
+                match root.path.strip_prefix(PATH_LIST_ROOT_PREFIX) {
+                    Some(list_file) => expand_root_path(list_file)
+                        .into_iter()
+                        .map(|path| Root::clone_with_path(format!("{}{}", PATH_LIST_ROOT_PREFIX, path), root.clone()))
+                        .collect(),
+                    None => expand_root_path(&root.path)
+                        .into_iter()
+                        .map(|path| Root::clone_with_path(path, root.clone()))
+                        .collect(),
+                }
+            })
+            .collect()
+    }
+
     fn parse_root_options(&mut self) -> Result<Option<RootOptions>, String> {
         #[derive(Debug, PartialEq)]
         enum RootParsingMode {
@@ -370,6 +396,7 @@ impl <'a> Parser<'a> {
             Options,
             MinDepth,
             Depth,
+            ArchiveDepth,
             Alias,
         }
 
@@ -378,6 +405,8 @@ impl <'a> Parser<'a> {
         let mut min_depth: u32 = 0;
         let mut max_depth: u32 = 0;
         let mut archives = false;
+        let mut archive_depth: u32 = 0;
+        let mut decompress = false;
         let mut symlinks = false;
         let mut gitignore = None;
         let mut hgignore = None;
@@ -385,6 +414,7 @@ impl <'a> Parser<'a> {
         let mut traversal = Bfs;
         let mut regexp = false;
         let mut alias: Option<String> = None;
+        let mut entry_order: Option<EntryOrder> = None;
 
         loop {
             let lexeme = self.next_lexeme();
@@ -397,9 +427,14 @@ impl <'a> Parser<'a> {
                                 mode = RootParsingMode::MinDepth;
                             } else if s == "maxdepth" || s == "depth" {
                                 mode = RootParsingMode::Depth;
+                            } else if s == "archive_depth" {
+                                mode = RootParsingMode::ArchiveDepth;
                             } else if s.starts_with("arc") {
                                 archives = true;
                                 mode = RootParsingMode::Options;
+                            } else if s == "decompress" {
+                                decompress = true;
+                                mode = RootParsingMode::Options;
                             } else if s.starts_with("sym") {
                                 symlinks = true;
                                 mode = RootParsingMode::Options;
@@ -439,6 +474,15 @@ impl <'a> Parser<'a> {
                             } else if s.starts_with("regex") {
                                 regexp = true;
                                 mode = RootParsingMode::Options;
+                            } else if s == "sorted" || s == "sort" {
+                                entry_order = Some(EntryOrder::Name);
+                                mode = RootParsingMode::Options;
+                            } else if s == "natsorted" || s == "natsort" {
+                                entry_order = Some(EntryOrder::Natural);
+                                mode = RootParsingMode::Options;
+                            } else if s == "unsorted" || s == "nosort" {
+                                entry_order = Some(EntryOrder::Unsorted);
+                                mode = RootParsingMode::Options;
                             } else if s == "as" {
                                 mode = RootParsingMode::Alias;
                             } else {
@@ -472,6 +516,21 @@ impl <'a> Parser<'a> {
                                 }
                             }
                         }
+                        RootParsingMode::ArchiveDepth => {
+                            let d: Result<u32, _> = s.parse();
+                            match d {
+                                Ok(d) => {
+                                    // Nested archives are searched within archives
+                                    archives = archives || d > 0;
+                                    archive_depth = d;
+                                    mode = RootParsingMode::Options;
+                                }
+                                _ => {
+                                    self.drop_lexeme();
+                                    break;
+                                }
+                            }
+                        }
                         RootParsingMode::Alias => {
                             alias = Some(s.to_string());
                             mode = RootParsingMode::Options;
@@ -504,6 +563,8 @@ impl <'a> Parser<'a> {
                 min_depth,
                 max_depth,
                 archives,
+                archive_depth,
+                decompress,
                 symlinks,
                 gitignore,
                 hgignore,
@@ -511,6 +572,7 @@ impl <'a> Parser<'a> {
                 traversal,
                 regexp,
                 alias,
+                entry_order,
             })),
         }
     }
@@ -521,6 +583,7 @@ impl <'a> Parser<'a> {
             || s == "mindepth"
             || s == "maxdepth"
             || s.starts_with("arc")
+            || s == "decompress"
             || s.starts_with("sym")
             || s.starts_with("git")
             || s.starts_with("hg")
@@ -531,6 +594,12 @@ impl <'a> Parser<'a> {
             || s == "bfs"
             || s == "dfs"
             || s.starts_with("regex")
+            || s == "sorted"
+            || s == "sort"
+            || s == "natsorted"
+            || s == "natsort"
+            || s == "unsorted"
+            || s == "nosort"
             || s == "as"
     }
 
@@ -1135,7 +1204,15 @@ impl <'a> Parser<'a> {
             return Ok(function_expr);
         }
 
-        if let Ok(Some(function_arg)) = self.parse_expr() {
+        if let Ok(Some(mut function_arg)) = self.parse_expr() {
+            // HASH(sha256) names the algorithm, it doesn't want the sha256 field
+            if function_expr.function == Some(Function::Hash)
+                && function_arg.left.is_none()
+                && function_arg.right.is_none()
+                && let Some(algorithm) = function_arg.field.as_ref().and_then(HashAlgorithm::from_field)
+            {
+                function_arg = Expr::value(algorithm.name().to_string());
+            }
             function_expr.add_left(function_arg);
         } else {
             self.next_lexeme();
@@ -1452,16 +1529,30 @@ impl <'a> Parser<'a> {
         Ok(0)
     }
 
-    fn parse_output_format(&mut self) -> Result<OutputFormat, &str> {
+    fn parse_output_format(&mut self) -> Result<(OutputFormat, Option<String>), &str> {
         let lexeme = self.next_lexeme();
         match lexeme {
             Some(Lexeme::Into) => {
                 let lexeme = self.next_lexeme();
                 match lexeme {
                     Some(Lexeme::RawString(s)) | Some(Lexeme::String(s)) => {
-                        return match OutputFormat::from(&s) {
-                            Some(output_format) => Ok(output_format),
-                            None => Err("Unknown output format"),
+                        let output_format = match OutputFormat::from(&s) {
+                            Some(output_format) => output_format,
+                            None => return Err("Unknown output format"),
+                        };
+                        if !output_format.is_archive() {
+                            return Ok((output_format, None));
+                        }
+
+                        // Archives are written to the file named next
+                        return match self.next_lexeme() {
+                            Some(Lexeme::RawString(path)) | Some(Lexeme::String(path)) => {
+                                Ok((output_format, Some(path)))
+                            }
+                            _ => {
+                                self.drop_lexeme();
+                                Err("Archive output requires a file name, e.g. into zip 'out.zip'")
+                            }
                         };
                     }
                     _ => {
@@ -1475,7 +1566,7 @@ impl <'a> Parser<'a> {
             }
         }
 
-        Ok(OutputFormat::Tabs)
+        Ok((OutputFormat::Tabs, None))
     }
 
     pub(crate) fn there_are_remaining_lexemes(&mut self) -> bool {
@@ -3170,6 +3261,22 @@ mod tests {
         assert_eq!(query.fields.len(), 1);
     }
 
+    #[test]
+    fn hash_function_takes_algorithm_field_as_name() {
+        let query = "select HASH(sha2_256, -100), HASH(name) from /test";
+        let mut lexer = Lexer::new(vec![query.to_string()]);
+        let mut p = Parser::new(&mut lexer);
+        let query = p.parse(false).unwrap();
+
+        let algorithm = query.fields[0].left.as_ref().unwrap();
+        assert_eq!(algorithm.field, None);
+        assert_eq!(algorithm.val, Some(String::from("sha256")));
+
+        // Other fields are still evaluated
+        let arg = query.fields[1].left.as_ref().unwrap();
+        assert_eq!(arg.field, Some(Field::Name));
+    }
+
     #[test]
     fn and_reorders_by_weight_like_or() {
         let query1 = "select name from /test where CONTAINS('foobar') and name like 'foobar'";
@@ -3647,4 +3754,127 @@ mod tests {
         assert!(!query.roots[1].is_subquery());
         assert_eq!(query.roots[1].path, "/b");
     }
+
+    #[test]
+    fn root_with_braces_expands_into_several_roots() {
+        let query = "select name from '/test/{a,b}' depth 2 archives, /c";
+        let mut lexer = Lexer::new(vec![query.to_string()]);
+        let mut p = Parser::new(&mut lexer);
+        let query = p.parse(false).unwrap();
+        assert!(!p.there_are_remaining_lexemes());
+
+        assert_eq!(
+            query.roots,
+            vec![
+                Root::new(
+                    String::from("/test/a"),
+                    RootOptions::from(0, 2, true, false, None, None, None, Bfs, false, None)
+                ),
+                Root::new(
+                    String::from("/test/b"),
+                    RootOptions::from(0, 2, true, false, None, None, None, Bfs, false, None)
+                ),
+                Root::new(
+                    String::from("/c"),
+                    RootOptions::from(0, 0, false, false, None, None, None, Bfs, false, None)
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn regexp_root_is_not_expanded() {
+        let query = "select name from '/test/{a,b}' regexp";
+        let mut lexer = Lexer::new(vec![query.to_string()]);
+        let mut p = Parser::new(&mut lexer);
+        let query = p.parse(false).unwrap();
+
+        assert_eq!(query.roots.len(), 1);
+        assert_eq!(query.roots[0].path, "/test/{a,b}");
+    }
+
+    #[test]
+    fn stdin_and_path_list_roots() {
+        let query = "select name, size from -, @files.txt depth 1";
+        let mut lexer = Lexer::new(vec![query.to_string()]);
+        let mut p = Parser::new(&mut lexer);
+        let query = p.parse(false).unwrap();
+        assert!(!p.there_are_remaining_lexemes());
+
+        assert_eq!(query.roots.len(), 2);
+        assert_eq!(query.roots[0].path, "-");
+        assert_eq!(query.roots[1].path, "@files.txt");
+        assert_eq!(query.roots[1].options.max_depth, 1);
+    }
+
+    #[test]
+    fn root_entry_order_options() {
+        let query = "select name from /a sorted, /b natsort dfs, /c nosort, /d";
+        let mut lexer = Lexer::new(vec![query.to_string()]);
+        let mut p = Parser::new(&mut lexer);
+        let query = p.parse(false).unwrap();
+        assert!(!p.there_are_remaining_lexemes());
+
+        let orders: Vec<Option<EntryOrder>> = query.roots.iter().map(|r| r.options.entry_order).collect();
+        assert_eq!(
+            orders,
+            vec![Some(EntryOrder::Name), Some(EntryOrder::Natural), Some(EntryOrder::Unsorted), None]
+        );
+        assert_eq!(query.roots[1].options.traversal, Dfs);
+    }
+
+    #[test]
+    fn root_archive_depth_option() {
+        let query = "select name from /a archive_depth 3 depth 2, /b archives, /c";
+        let mut lexer = Lexer::new(vec![query.to_string()]);
+        let mut p = Parser::new(&mut lexer);
+        let query = p.parse(false).unwrap();
+        assert!(!p.there_are_remaining_lexemes());
+
+        let options: Vec<(bool, u32)> = query
+            .roots
+            .iter()
+            .map(|r| (r.options.archives, r.options.archive_depth))
+            .collect();
+        assert_eq!(options, vec![(true, 3), (true, 0), (false, 0)]);
+        assert_eq!(query.roots[0].options.max_depth, 2);
+    }
+
+    #[test]
+    fn archive_output_formats() {
+        let query = "select path from /var/log where size lt 100m into zip 'logs.zip'";
+        let mut lexer = Lexer::new(vec![query.to_string()]);
+        let mut p = Parser::new(&mut lexer);
+        let query = p.parse(false).unwrap();
+        assert_eq!(query.output_format, OutputFormat::Zip);
+        assert_eq!(query.output_path, Some(String::from("logs.zip")));
+
+        let query = "select path from /var/log into tar";
+        let mut lexer = Lexer::new(vec![query.to_string()]);
+        let mut p = Parser::new(&mut lexer);
+        assert!(p.parse(false).is_err());
+
+        let query = "select count(*) from /var/log into tar logs.tgz";
+        let mut lexer = Lexer::new(vec![query.to_string()]);
+        let mut p = Parser::new(&mut lexer);
+        assert!(p.parse(false).is_err());
+
+        let query = "select path from /var/log into json";
+        let mut lexer = Lexer::new(vec![query.to_string()]);
+        let mut p = Parser::new(&mut lexer);
+        assert_eq!(p.parse(false).unwrap().output_path, None);
+    }
+
+    #[test]
+    fn root_decompress_option() {
+        let query = "select name from /var/log decompress depth 1, /tmp where line_count gt 10";
+        let mut lexer = Lexer::new(vec![query.to_string()]);
+        let mut p = Parser::new(&mut lexer);
+        let query = p.parse(false).unwrap();
+        assert!(!p.there_are_remaining_lexemes());
+
+        let options: Vec<bool> = query.roots.iter().map(|r| r.options.decompress).collect();
+        assert_eq!(options, vec![true, false]);
+        assert_eq!(query.roots[0].options.max_depth, 1);
+    }
 }
@@ -5,7 +5,10 @@ use std::collections::HashSet;
 
 use crate::expr::Expr;
 use crate::field::Field;
+use crate::function::Function;
+use crate::operators::{LogicalOp, Op};
 use crate::query::TraversalMode::Bfs;
+use crate::util::hashes::HashAlgorithm;
 
 #[derive(Debug, Clone, PartialOrd, PartialEq, Eq, Hash, Serialize)]
 /// Represents a query to be executed on .
@@ -29,6 +32,8 @@ pub struct Query {
     pub offset: u32,
     /// Output format
     pub output_format: OutputFormat,
+    /// File to write the output to, for the formats that create one
+    pub output_path: Option<String>,
     pub raw_query: String,
 }
 
@@ -71,6 +76,69 @@ impl Query {
             || !self.grouping_fields.is_empty()
             || self.having.as_ref().is_some_and(|h| h.has_aggregate_function())
     }
+
+    /// Recognizes duplicate-detection queries such as
+    /// `select sha256, count(*) from dir group by sha256 having count(*) > 1`:
+    /// grouped by a single file hash field that's not used anywhere else but
+    /// as the group key, with HAVING ruling out groups of a single file.
+    /// Returns the hash field, so that the search only has to hash files
+    /// that may have a duplicate.
+    pub fn get_duplicate_hash_field(&self) -> Option<Field> {
+        let group_expr = match self.grouping_fields.as_slice() {
+            [group_expr] => group_expr,
+            _ => return None,
+        };
+        let hash_field = group_expr.field.filter(|field| is_bare_field_expr(group_expr) && HashAlgorithm::from_field(field).is_some())?;
+
+        if !self.having.as_ref().is_some_and(requires_several_files) {
+            return None;
+        }
+
+        let used_elsewhere = self.fields.iter()
+            .chain(self.ordering_fields.iter())
+            .filter(|expr| !(is_bare_field_expr(expr) && expr.field == Some(hash_field)))
+            .chain(self.expr.iter())
+            .chain(self.having.iter())
+            .any(|expr| expr.get_required_fields().contains(&hash_field));
+
+        match used_elsewhere {
+            true => None,
+            false => Some(hash_field),
+        }
+    }
+}
+
+fn is_bare_field_expr(expr: &Expr) -> bool {
+    expr.field.is_some()
+        && expr.function.is_none()
+        && expr.left.is_none()
+        && expr.right.is_none()
+        && expr.root_alias.is_none()
+        && !expr.minus
+}
+
+/// Whether the HAVING condition only holds for groups of two or more files:
+/// `count(*) > N` with N >= 1 or `count(*) >= N` with N >= 2, possibly ANDed
+/// with other conditions.
+fn requires_several_files(having: &Expr) -> bool {
+    if having.logical_op == Some(LogicalOp::And) {
+        return having.left.as_deref().is_some_and(requires_several_files)
+            || having.right.as_deref().is_some_and(requires_several_files);
+    }
+
+    let is_count_all = having.left.as_deref().is_some_and(|left| {
+        left.function == Some(Function::Count)
+            && left.left.as_deref().is_some_and(|arg| arg.val.as_deref() == Some("*"))
+    });
+    let min_count = having.right.as_deref()
+        .and_then(|right| right.val.as_deref())
+        .and_then(|val| val.parse::<i64>().ok());
+
+    match (is_count_all, &having.op, min_count) {
+        (true, Some(Op::Gt), Some(count)) => count >= 1,
+        (true, Some(Op::Gte), Some(count)) => count >= 2,
+        _ => false,
+    }
 }
 
 #[derive(Debug, Clone, PartialOrd, PartialEq, Eq, Hash, Serialize)]
@@ -131,7 +199,13 @@ root_options! {
         
         @text = ["archives", "arc"], description = "Whether to search archives"
         pub archives: bool,
+
+        @text = ["archive_depth"], description = "How many levels of nested archives to search"
+        pub archive_depth: u32,
         
+        @text = ["decompress"], description = "Whether to decompress gzip, bzip2, xz and zstd files before reading their content"
+        pub decompress: bool,
+
         @text = ["symlinks", "sym"], description = "Whether to follow symlinks"
         pub symlinks: bool,
 
@@ -154,6 +228,11 @@ root_options! {
         @text = ["regexp", "rx"], description = "Treat the path as a regular expression"
         pub regexp: bool,
 
+        @text = ["sorted", "sort"], description = "Visit directory entries sorted by name"
+        @text = ["natsorted", "natsort"], description = "Visit directory entries in natural order"
+        @text = ["unsorted", "nosort"], description = "Visit directory entries in file system order (default)"
+        pub entry_order: Option<EntryOrder>,
+
         @text = ["as"], description = "Alias for the root path"
         pub alias: Option<String>,
     }
@@ -165,6 +244,8 @@ impl RootOptions {
             min_depth: 0,
             max_depth: 0,
             archives: false,
+            archive_depth: 0,
+            decompress: false,
             symlinks: false,
             gitignore: None,
             hgignore: None,
@@ -172,6 +253,7 @@ impl RootOptions {
             traversal: Bfs,
             regexp: false,
             alias: None,
+            entry_order: None,
         }
     }
 
@@ -193,6 +275,8 @@ impl RootOptions {
             min_depth,
             max_depth,
             archives,
+            archive_depth: 0,
+            decompress: false,
             symlinks,
             gitignore,
             hgignore,
@@ -200,6 +284,7 @@ impl RootOptions {
             traversal,
             regexp,
             alias,
+            entry_order: None,
         }
     }
 }
@@ -244,6 +329,18 @@ pub enum TraversalMode {
     Dfs,
 }
 
+/// Order in which directory entries are visited during traversal
+#[derive(Debug, Clone, Copy, PartialOrd, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryOrder {
+    /// As returned by the file system
+    Unsorted,
+    /// By file name, byte-wise
+    Name,
+    /// By file name, with digit runs compared by their numeric value
+    Natural,
+}
+
 macro_rules! output_format {
     (
         $(#[$enum_attrs:meta])*
@@ -322,10 +419,34 @@ output_format! {
         @text = "json"
         @description = "JSON format"
         Json,
+
+        @text = "jsonl"
+        @description = "JSON Lines, one object per line as the results are found"
+        JsonLines,
         
         @text = "html"
         @description = "HTML format"
         Html,
+
+        @text = "table"
+        @description = "Table with aligned columns and a header"
+        @supports_colorization = true
+        Table,
+
+        @text = "zip"
+        @description = "Zip archive of the found files, e.g. into zip 'out.zip'"
+        Zip,
+
+        @text = "tar"
+        @description = "Tar archive of the found files, gzipped if named .tar.gz or .tgz"
+        Tar,
+    }
+}
+
+impl OutputFormat {
+    /// Formats that write the found files into an archive rather than list them
+    pub fn is_archive(&self) -> bool {
+        matches!(self, OutputFormat::Zip | OutputFormat::Tar)
     }
 }
 
@@ -356,4 +477,35 @@ mod tests {
             "Csv should not support colorization"
         );
     }
-}
\ No newline at end of file
+
+    fn parse_query(query: &str) -> Query {
+        use crate::lexer::Lexer;
+        use crate::parser::Parser;
+
+        let mut lexer = Lexer::new(vec![query.to_string()]);
+        let mut parser = Parser::new(&mut lexer);
+        parser.parse(false).unwrap()
+    }
+
+    #[test]
+    fn test_get_duplicate_hash_field() {
+        let dup = |q: &str| parse_query(q).get_duplicate_hash_field();
+
+        assert_eq!(dup("select sha256, count(*) from . group by sha256 having count(*) > 1"), Some(Field::Sha256));
+        assert_eq!(
+            dup("select sha1, count(*), sum(size) from . where is_file group by sha1 having count(*) >= 2 and sum(size) > 100 order by sha1"),
+            Some(Field::Sha1)
+        );
+
+        // Groups of a single file are possible
+        assert_eq!(dup("select sha256, count(*) from . group by sha256"), None);
+        assert_eq!(dup("select sha256, count(*) from . group by sha256 having count(*) >= 1"), None);
+        assert_eq!(dup("select sha256, count(*) from . group by sha256 having count(*) > 1 or sum(size) > 0"), None);
+        // The hash is needed for more than the group key
+        assert_eq!(dup("select sha256, count(*) from . where sha256 like 'ab%' group by sha256 having count(*) > 1"), None);
+        assert_eq!(dup("select upper(sha256), count(*) from . group by sha256 having count(*) > 1"), None);
+        // Not grouped by a hash alone
+        assert_eq!(dup("select size, count(*) from . group by size having count(*) > 1"), None);
+        assert_eq!(dup("select sha256, size, count(*) from . group by sha256, size having count(*) > 1"), None);
+    }
+}
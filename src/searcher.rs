@@ -1,13 +1,17 @@
 //! Handles directory traversal and file processing.
 
 use std::cell::RefCell;
+use std::collections::hash_map::Entry;
 use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::{Display, Formatter};
 use std::fs;
-use std::fs::{DirEntry, FileType};
-use std::io::{ErrorKind, Write};
+use std::io::ErrorKind;
+#[cfg(target_os = "linux")]
+use std::os::unix::io::RawFd;
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::sync::LazyLock;
+use std::time::{Duration, Instant};
 #[cfg(feature = "git")]
 use git2::Repository;
 use lscolors::{LsColors, Style};
@@ -20,18 +24,33 @@ use crate::expr::Expr;
 use crate::field::Field;
 use crate::field::context::{FieldContext, FileMetadataState};
 use crate::field::dispatch;
-use crate::fileinfo::{to_file_info, FileInfo};
+use crate::fileinfo::{
+    buffer_archive_entry, open_tar_archive, read_archive_entry, tar_entry_to_file_info, to_file_info, ArchiveReader,
+    FileInfo, NESTED_ARCHIVE_SEPARATOR,
+};
+#[cfg(feature = "sevenz")]
+use crate::fileinfo::{open_7z_archive, sevenz_entry_to_file_info};
 use crate::function;
 use crate::ignore::docker::{
     matches_dockerignore_filter, search_upstream_dockerignore, DockerignoreFilter,
 };
 use crate::ignore::hg::{matches_hgignore_filter, search_upstream_hgignore, HgignoreFilter};
 use crate::operators::{LogicalOp, Op};
+use crate::output::archive::{get_entry_name, resolve_output_path, ARCHIVE_ENTRY, ARCHIVE_SOURCE};
 use crate::output::ResultsWriter;
 use crate::query::TraversalMode::{Bfs, Dfs};
-use crate::query::{Query, Root, TraversalMode};
+use crate::query::{EntryOrder, Query, Root, TraversalMode};
 use crate::util::*;
+use crate::util::dir_entry::read_dir;
+use crate::util::duplicates::DuplicateCandidates;
 use crate::util::error::{error_message, path_error_message, SearchError};
+use crate::util::hashes::HashAlgorithm;
+use crate::util::progress::Progress;
+#[cfg(feature = "rar")]
+use crate::util::rar::list_rar_entries;
+use crate::util::spill::{merge_groups, spill_error, MemSize, SpillRun};
+#[cfg(target_os = "linux")]
+use crate::util::statx::statx_mask_for_fields;
 
 
 pub struct Searcher<'a> {
@@ -40,11 +59,20 @@ pub struct Searcher<'a> {
     default_config: &'a Config,
     use_colors: bool,
     results_writer: ResultsWriter,
+    /// Resolved path of the archive the found files go into, which is never searched itself
+    output_archive_path: Option<PathBuf>,
     #[cfg(all(unix, feature = "users"))]
     user_cache: UsersCache,
     regex_cache: HashMap<String, Regex>,
     found: u32,
     accumulators: HashMap<Vec<String>, function::GroupAccumulator>,
+    /// Rough size of the accumulators in memory
+    accumulators_size: usize,
+    /// Accumulators spilled to disk, sorted by group key
+    accumulator_runs: Vec<SpillRun>,
+    /// Bytes the buffered results and accumulators may take before they
+    /// spill to disk, zero for unlimited
+    max_memory: usize,
     output_buffer: TopN<Criteria<String>, String>,
     ordering_fields_rc: Rc<Vec<Expr>>,
     ordering_asc_rc: Rc<Vec<bool>>,
@@ -56,29 +84,71 @@ pub struct Searcher<'a> {
     hgignore_filters: Vec<HgignoreFilter>,
     dockerignore_filters: Vec<DockerignoreFilter>,
     visited_dirs: HashSet<PathBuf>,
+    reported_files: HashSet<(FileIdentity, Option<String>)>,
     lscolors: LsColors,
     dir_queue: VecDeque<(PathBuf, u32)>,
     current_follow_symlinks: bool,
     current_min_depth: u32,
     current_max_depth: u32,
     current_search_archives: bool,
+    current_archive_depth: u32,
+    current_decompress: bool,
+    /// Whether the query reads the content of files, so archived files are buffered as they are listed
+    reads_archived_content: bool,
+    /// Content of the archived file about to be checked
+    current_archived_content: Option<ArchiveReader>,
     current_apply_gitignore: bool,
     current_apply_hgignore: bool,
     current_apply_dockerignore: bool,
     current_traversal_mode: TraversalMode,
+    current_entry_order: EntryOrder,
     current_root_dir: PathBuf,
+    /// Descriptor of the directory being listed, set only while its entries are checked
+    #[cfg(target_os = "linux")]
+    current_dir_fd: Option<RawFd>,
 
     fms: FileMetadataState,
     #[cfg(feature = "git")]
     git_cache: crate::util::git::GitCache,
+    dir_size_cache: crate::util::dir_size::DirSizeCache,
+    checksum_files: crate::util::checksums::ChecksumFiles,
+    /// Shared with the searchers of subqueries, saved once the outermost search is over
+    hash_cache: Rc<RefCell<crate::util::hash_cache::HashCache>>,
+    duplicate_hash_field: Option<Field>,
+    duplicate_candidates: DuplicateCandidates<(Option<String>, HashMap<String, String>)>,
     file_map: HashMap<String, String>,
     conforms_map: HashMap<String, String>,
     subquery_cache: HashMap<String, Vec<String>>,
     silent_mode: bool,
 
+    /// Time after which the search stops, set by the `timeout` option
+    deadline: Option<Instant>,
+    /// Number of entries the search may examine, zero for unlimited
+    max_files: u64,
+    scanned_files: u64,
+    /// Set when a resource limit cut the search short
+    pub limit_reached: Option<ResourceLimit>,
+    progress: Progress,
+
     pub error_count: i32,
 }
 
+/// Resource limit that stopped a search before it visited everything.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ResourceLimit {
+    Timeout,
+    MaxFiles,
+}
+
+impl Display for ResourceLimit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResourceLimit::Timeout => write!(f, "time limit reached"),
+            ResourceLimit::MaxFiles => write!(f, "limit of scanned entries reached"),
+        }
+    }
+}
+
 static FIELD_WITH_ALIAS: LazyLock<Regex> = LazyLock::new(|| {
     Regex::new("^([a-zA-Z0-9_]+)\\.([a-zA-Z0-9_]+)$").unwrap()
 });
@@ -111,6 +181,47 @@ fn apply_minus(column_expr: &Expr, value: Variant) -> Variant {
         .unwrap_or(value)
 }
 
+/// Types a value held as a string, like the ones cached in the file map,
+/// as declared by the field or function of the expression, so that the
+/// output keeps the type.
+fn restore_type(column_expr: &Expr, value: &String) -> Variant {
+    let (numeric, boolean) = match (&column_expr.function, &column_expr.field) {
+        (Some(function), _) => (function.is_numeric_function(), function.is_boolean_function()),
+        (None, Some(field)) => (field.is_numeric_field(), field.is_boolean_field()),
+        _ => (column_expr.arithmetic_op.is_some(), false),
+    };
+
+    if numeric {
+        return to_numeric_variant(value);
+    }
+    if boolean && let Some(bool_value) = str_to_bool(value) {
+        let variant = Variant::from_bool(bool_value);
+        if variant.to_string() == *value {
+            return variant;
+        }
+    }
+    Variant::from_string(value)
+}
+
+/// Numbers are typed as such, anything else like the minimum of dates stays
+/// a string. So does a number that would be written differently, like `1.50`.
+fn to_numeric_variant(value: &String) -> Variant {
+    let variant = if value.is_empty() {
+        Variant::empty(VariantType::Int)
+    } else if let Ok(int_value) = value.parse::<i64>() {
+        Variant::from_int(int_value)
+    } else if let Ok(float_value) = value.parse::<f64>() {
+        Variant::from_float(float_value)
+    } else {
+        return Variant::from_string(value);
+    };
+
+    match variant.to_string() == *value {
+        true => variant,
+        false => Variant::from_string(value),
+    }
+}
+
 fn is_subquery_cacheable(query: &Query) -> bool {
     let own_aliases: HashSet<String> = query
         .roots
@@ -171,6 +282,34 @@ fn expr_walk_external_alias(expr: &Expr, own: &HashSet<String>) -> bool {
     false
 }
 
+/// Collects directory entries sorted by file name. Since both BFS and DFS
+/// traversal follow the order entries are read in, this makes the whole
+/// traversal reproducible across file systems. Unreadable entries go last.
+fn sort_dir_entries(
+    entries: impl Iterator<Item = std::io::Result<DirEntry>>,
+    order: EntryOrder,
+) -> Vec<std::io::Result<DirEntry>> {
+    let mut entries: Vec<(Option<std::ffi::OsString>, std::io::Result<DirEntry>)> = entries
+        .map(|entry| (entry.as_ref().ok().map(|e| e.file_name()), entry))
+        .collect();
+
+    // Names are compared by their bytes, so that names which aren't valid
+    // UTF-8 keep a stable order too. The natural order falls back to the
+    // bytes for names that look the same once made valid UTF-8.
+    entries.sort_by(|(a, _), (b, _)| match (a, b) {
+        (Some(a), Some(b)) => match order {
+            EntryOrder::Natural => natural_cmp(&a.to_string_lossy(), &b.to_string_lossy())
+                .then_with(|| a.as_encoded_bytes().cmp(b.as_encoded_bytes())),
+            _ => a.as_encoded_bytes().cmp(b.as_encoded_bytes()),
+        },
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (None, None) => std::cmp::Ordering::Equal,
+    });
+
+    entries.into_iter().map(|(_, entry)| entry).collect()
+}
+
 #[cfg(any(all(windows, feature = "everything"), all(unix, feature = "plocate")))]
 fn external_index_depth(path: &Path, root_prefix: &str) -> Option<u32> {
     let s = path.to_string_lossy();
@@ -193,6 +332,14 @@ fn external_index_depth(path: &Path, root_prefix: &str) -> Option<u32> {
     Some(rel.matches(std::path::MAIN_SEPARATOR).count() as u32 + 1)
 }
 
+/// Reads the file entry of a zip archive, so that its content can be looked
+/// at by the fields or searched as a nested archive.
+fn buffer_zip_entry(archive: &mut zip::ZipArchive<ArchiveReader>, index: usize) -> Option<ArchiveReader> {
+    let mut zip_entry = archive.by_index(index).ok()?;
+    let size = zip_entry.size();
+    buffer_archive_entry(&mut zip_entry, size).ok()
+}
+
 impl<'a> Searcher<'a> {
     pub fn new(
         query: &'a Query,
@@ -212,21 +359,32 @@ impl<'a> Searcher<'a> {
         use_colors: bool,
     ) -> Self {
         let limit = query.limit;
-
-        let results_writer = ResultsWriter::new(&query.output_format);
+        let max_memory = config.max_memory.as_ref().or(default_config.max_memory.as_ref())
+            .and_then(|max_memory| parse_filesize(max_memory))
+            .unwrap_or(0) as usize;
+
+        let results_writer = ResultsWriter::new(&query.output_format, &query.output_path);
+        let output_archive_path = match query.output_format.is_archive() {
+            true => query.output_path.as_deref().and_then(|path| resolve_output_path(Path::new(path))),
+            false => None,
+        };
         Searcher {
             query,
             config,
             default_config,
             use_colors,
             results_writer,
+            output_archive_path,
             #[cfg(all(unix, feature = "users"))]
             user_cache: UsersCache::new(),
             regex_cache: HashMap::new(),
             found: 0,
             accumulators: HashMap::new(),
+            accumulators_size: 0,
+            accumulator_runs: vec![],
+            max_memory,
             output_buffer: if limit == 0 {
-                TopN::limitless()
+                TopN::limitless().with_memory_limit(max_memory)
             } else {
                 TopN::new(limit.saturating_add(query.offset))
             },
@@ -239,26 +397,50 @@ impl<'a> Searcher<'a> {
             hgignore_filters: vec![],
             dockerignore_filters: vec![],
             visited_dirs: HashSet::new(),
+            reported_files: HashSet::new(),
             lscolors: LsColors::from_env().unwrap_or_default(),
             dir_queue: VecDeque::new(),
             current_follow_symlinks: false,
             current_min_depth: 0,
             current_max_depth: 0,
             current_search_archives: false,
+            current_archive_depth: 1,
+            current_decompress: false,
+            reads_archived_content: false,
+            current_archived_content: None,
             current_apply_gitignore: false,
             current_apply_hgignore: false,
             current_apply_dockerignore: false,
             current_traversal_mode: TraversalMode::Bfs,
+            current_entry_order: EntryOrder::Unsorted,
             current_root_dir: PathBuf::new(),
+            #[cfg(target_os = "linux")]
+            current_dir_fd: None,
 
             fms: FileMetadataState::new(),
             #[cfg(feature = "git")]
             git_cache: crate::util::git::GitCache::new(),
+            dir_size_cache: crate::util::dir_size::DirSizeCache::new(),
+            checksum_files: crate::util::checksums::ChecksumFiles::new(),
+            hash_cache: Rc::new(RefCell::new(crate::util::hash_cache::HashCache::new(
+                config.hash_cache.unwrap_or(default_config.hash_cache.unwrap_or(false))
+            ))),
+            duplicate_hash_field: query.get_duplicate_hash_field(),
+            duplicate_candidates: DuplicateCandidates::new(),
             file_map: HashMap::new(),
             conforms_map: HashMap::new(),
             subquery_cache: HashMap::new(),
             silent_mode: false,
 
+            deadline: match config.timeout.unwrap_or(default_config.timeout.unwrap_or(0)) {
+                0 => None,
+                secs => Instant::now().checked_add(Duration::from_secs(secs)),
+            },
+            max_files: config.max_files.unwrap_or(default_config.max_files.unwrap_or(0)),
+            scanned_files: 0,
+            limit_reached: None,
+            progress: Progress::new(config.progress.unwrap_or(default_config.progress.unwrap_or(false))),
+
             error_count: 0,
         }
     }
@@ -267,6 +449,29 @@ impl<'a> Searcher<'a> {
         self.query.is_ordered() || self.query.is_aggregated() || self.query.offset > 0 || self.silent_mode
     }
 
+    /// Whether the traversal is over: an unbuffered query found all the rows
+    /// its LIMIT asks for, or a resource limit was reached.
+    fn should_stop(&mut self) -> bool {
+        (!self.is_buffered() && self.query.limit > 0 && self.query.limit <= self.found)
+            || self.check_resource_limits()
+    }
+
+    fn check_resource_limits(&mut self) -> bool {
+        if self.limit_reached.is_none() {
+            if self.max_files > 0 && self.scanned_files >= self.max_files {
+                self.limit_reached = Some(ResourceLimit::MaxFiles);
+            } else if self.is_timed_out() {
+                self.limit_reached = Some(ResourceLimit::Timeout);
+            }
+        }
+
+        self.limit_reached.is_some()
+    }
+
+    fn is_timed_out(&self) -> bool {
+        self.deadline.is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
     /// Searches directories based on configured query and outputs results to stdout.
     pub fn list_search_results(&mut self) -> Result<(), SearchError> {
         // Pre-flight: catch unparseable date/datetime literals once, up front,
@@ -277,6 +482,10 @@ impl<'a> Searcher<'a> {
                 return Err(SearchError::fatal(msg).with_source("where"));
             }
 
+        #[cfg(target_os = "linux")]
+        self.fms.set_statx_mask(self.get_statx_mask());
+        self.reads_archived_content = self.query_reads_content();
+
         let current_dir = std::env::current_dir()?;
 
         if !self.silent_mode {
@@ -380,7 +589,12 @@ impl<'a> Searcher<'a> {
 
         // ======== Explore each root =========
         for root in roots {
+            if self.check_resource_limits() {
+                break;
+            }
+
             self.current_follow_symlinks = root.options.symlinks;
+            self.current_decompress = root.options.decompress;
             self.current_alias = root.options.alias.clone();
             self.subquery_required_fields = match &self.current_alias {
                 Some(alias) => {
@@ -417,6 +631,7 @@ impl<'a> Searcher<'a> {
                 self.current_min_depth = 0;
                 self.current_max_depth = 0;
                 self.current_search_archives = root.options.archives;
+                self.current_archive_depth = root.options.archive_depth.max(1);
                 self.current_apply_gitignore = root
                     .options
                     .gitignore
@@ -439,10 +654,35 @@ impl<'a> Searcher<'a> {
                 continue;
             }
 
+            if is_path_list_root(&root.path) {
+                self.dir_queue.clear();
+                self.visited_dirs.clear();
+                self.current_root_dir = PathBuf::from(".");
+                self.current_min_depth = 0;
+                self.current_max_depth = 0;
+                self.current_search_archives = root.options.archives;
+                self.current_archive_depth = root.options.archive_depth.max(1);
+                self.current_apply_gitignore = false;
+                self.current_apply_hgignore = false;
+                self.current_apply_dockerignore = false;
+                self.current_traversal_mode = root.options.traversal;
+
+                let result = self.visit_path_list(&root.path);
+                if let Err(err) = result
+                    && err.is_fatal() {
+                        return Err(err);
+                    }
+                continue;
+            }
+
             self.current_root_dir = PathBuf::from(&root.path);
             self.current_min_depth = root.options.min_depth;
-            self.current_max_depth = root.options.max_depth;
+            self.current_max_depth = match root.options.max_depth {
+                0 => self.config.max_depth_default.unwrap_or(self.default_config.max_depth_default.unwrap_or(0)),
+                max_depth => max_depth,
+            };
             self.current_search_archives = root.options.archives;
+            self.current_archive_depth = root.options.archive_depth.max(1);
             self.current_apply_gitignore = root
                 .options
                 .gitignore
@@ -456,6 +696,10 @@ impl<'a> Searcher<'a> {
                 .dockerignore
                 .unwrap_or(self.config.dockerignore.unwrap_or(false));
             self.current_traversal_mode = root.options.traversal;
+            self.current_entry_order = root
+                .options
+                .entry_order
+                .unwrap_or(self.config.entry_order.unwrap_or(EntryOrder::Unsorted));
 
             self.dir_queue.clear();
             self.visited_dirs.clear();
@@ -516,10 +760,14 @@ impl<'a> Searcher<'a> {
                 }
         }
 
+        self.progress.clear();
+
         let compute_time = std::time::Instant::now();
 
         // ======== Compute results =========
         if self.query.is_aggregated() {
+            self.group_duplicate_candidates();
+
             if !self.query.grouping_fields.is_empty() {
                 let group_keys: Vec<String> = self
                     .query
@@ -527,7 +775,12 @@ impl<'a> Searcher<'a> {
                     .iter()
                     .map(|f| f.to_string())
                     .collect();
-                let accumulators = std::mem::take(&mut self.accumulators);
+                let mut accumulator_runs = std::mem::take(&mut self.accumulator_runs);
+                let accumulators = merge_groups(
+                    std::mem::take(&mut self.accumulators),
+                    &mut accumulator_runs,
+                    function::GroupAccumulator::merge,
+                ).map_err(spill_error)?;
 
                 let ordering_fields_rc = self.ordering_fields_rc.clone();
                 let ordering_asc_rc = self.ordering_asc_rc.clone();
@@ -558,15 +811,19 @@ impl<'a> Searcher<'a> {
                     sorting_indices.push(index);
                 }
 
-                let mut grouped_results: TopN<Criteria<String>, Vec<(String, String)>> =
+                // Rows are formatted right away, so that only strings are kept
+                // (and spilled to disk) until they're sorted
+                let mut grouped_results: TopN<Criteria<String>, String> =
                     if self.query.limit > 0 {
                         TopN::new(self.query.limit.saturating_add(self.query.offset))
                     } else {
-                        TopN::limitless()
+                        TopN::limitless().with_memory_limit(self.max_memory)
                     };
 
                 let having_expr = self.query.having.as_ref();
-                for (group_key, group_acc) in &accumulators {
+                for group in accumulators {
+                    let (group_key, group_acc) = group.map_err(spill_error)?;
+                    let group_acc = &group_acc;
                     let mut file_map = HashMap::new();
                     for (i, k) in group_keys.iter().enumerate() {
                         file_map.insert(k.clone(), group_key.get(i).cloned().unwrap_or_default());
@@ -575,13 +832,13 @@ impl<'a> Searcher<'a> {
                         && !self.group_conforms(&mut file_map, group_acc, having_expr)? {
                             continue;
                         }
-                    let mut items: Vec<(String, String)> = Vec::new();
+                    let mut items: Vec<(String, Variant)> = Vec::new();
                     for column_expr in &self.query.fields {
                         if let Ok(value) = self.get_column_expr_value(
                             None, &None, Path::new(""), &mut file_map, Some(group_acc), column_expr,
                         ) {
                             let field_name = column_expr.to_string().to_lowercase();
-                            items.push((field_name, value.to_string()));
+                            items.push((field_name, value));
                         }
                     }
                     let mut criteria_values: Vec<String> =
@@ -593,7 +850,7 @@ impl<'a> Searcher<'a> {
                             // Reuse the value already rendered for the SELECT list
                             Some(index) => items
                                 .get(*index)
-                                .map(|item| item.1.clone())
+                                .map(|item| item.1.to_string())
                                 .unwrap_or_default(),
                             // Not selected: evaluate the ordering expression
                             // against this group's accumulator (aggregates) or
@@ -612,26 +869,29 @@ impl<'a> Searcher<'a> {
                         };
                         criteria_values.push(value);
                     }
-                    grouped_results.insert(
+                    let mut buf = WritableBuffer::new();
+                    self.results_writer.write_row(&mut buf, items)?;
+                    grouped_results.insert_or_spill(
                         Criteria::new(ordering_fields_rc.clone(), criteria_values, ordering_asc_rc.clone()),
-                        items,
+                        String::from(buf),
                     );
                 }
 
                 let mut first = true;
                 let mut stdout = std::io::stdout().lock();
-                for items in grouped_results.iter_values().skip(self.query.offset as usize) {
-                    let mut buf = WritableBuffer::new();
-                    self.results_writer.write_row(&mut buf, items.clone())?;
-                    let rendered = String::from(buf);
+                for (i, rendered) in grouped_results.sorted_values().map_err(spill_error)?.enumerate() {
+                    let rendered = rendered.map_err(spill_error)?;
+                    if i < self.query.offset as usize {
+                        continue;
+                    }
                     if !self.silent_mode {
                         if !first {
                             try_output!(self.results_writer.write_row_separator(&mut stdout), Ok(()));
                         }
                         first = false;
-                        try_output!(write!(stdout, "{}", &rendered), Ok(()));
+                        try_output!(self.results_writer.write_rendered(&mut stdout, &rendered), Ok(()));
                     }
-                    self.output_buffer.insert(
+                    self.output_buffer.insert_or_spill(
                         Criteria::new(Rc::new(vec![]), vec![], Rc::new(vec![])),
                         rendered,
                     );
@@ -639,11 +899,19 @@ impl<'a> Searcher<'a> {
                 drop(stdout);
             } else {
                 let mut buf = WritableBuffer::new();
-                let mut items: Vec<(String, String)> = Vec::new();
+                let mut items: Vec<(String, Variant)> = Vec::new();
 
-                let accumulators = std::mem::take(&mut self.accumulators);
-                let empty_acc = function::GroupAccumulator::default();
-                let ungrouped_acc = accumulators.get(&vec![]).unwrap_or(&empty_acc);
+                let mut accumulator_runs = std::mem::take(&mut self.accumulator_runs);
+                let ungrouped_acc = merge_groups(
+                    std::mem::take(&mut self.accumulators),
+                    &mut accumulator_runs,
+                    function::GroupAccumulator::merge,
+                )
+                .and_then(|mut groups| groups.next().transpose())
+                .map_err(spill_error)?
+                .map(|(_, acc)| acc)
+                .unwrap_or_default();
+                let ungrouped_acc = &ungrouped_acc;
                 let mut passes_having = true;
                 if let Some(having_expr) = self.query.having.as_ref() {
                     passes_having =
@@ -659,7 +927,7 @@ impl<'a> Searcher<'a> {
                         column_expr
                     ) {
                         let field_name = column_expr.to_string().to_lowercase();
-                        items.push((field_name, value.to_string()));
+                        items.push((field_name, value));
                     }
                 }
 
@@ -674,20 +942,24 @@ impl<'a> Searcher<'a> {
                     // An ungrouped aggregate produces a single row; any OFFSET
                     // skips past it (the buffered row is offset-skipped by readers).
                     if !self.silent_mode && self.query.offset == 0 {
-                        try_output!(write!(std::io::stdout(), "{}", rendered), Ok(()));
+                        try_output!(self.results_writer.write_rendered(&mut std::io::stdout(), &rendered), Ok(()));
                     }
                 }
             }
         } else if self.is_buffered() && !self.silent_mode {
             let mut stdout = std::io::stdout().lock();
             let mut first = true;
-            for piece in self.output_buffer.iter_values().skip(self.query.offset as usize) {
+            for (i, piece) in self.output_buffer.sorted_values().map_err(spill_error)?.enumerate() {
+                let piece = piece.map_err(spill_error)?;
+                if i < self.query.offset as usize {
+                    continue;
+                }
                 if first {
                     first = false;
                 } else {
                     try_output!(self.results_writer.write_row_separator(&mut stdout), Ok(()));
                 }
-                try_output!(write!(stdout, "{}", piece), Ok(()));
+                try_output!(self.results_writer.write_rendered(&mut stdout, &piece), Ok(()));
             }
             drop(stdout);
         }
@@ -696,6 +968,7 @@ impl<'a> Searcher<'a> {
             let mut stdout = std::io::stdout().lock();
             self.results_writer.write_footer(&mut stdout)?;
         }
+        self.error_count += self.results_writer.failed_files() as i32;
 
         let completion_time = std::time::Instant::now();
 
@@ -728,7 +1001,7 @@ impl<'a> Searcher<'a> {
             if path_str.is_empty() {
                 continue;
             }
-            if !self.is_buffered() && self.query.limit > 0 && self.query.limit <= self.found {
+            if self.should_stop() {
                 break;
             }
             let path = PathBuf::from(&path_str);
@@ -747,7 +1020,7 @@ impl<'a> Searcher<'a> {
                     let mut matched_entry: Option<DirEntry> = None;
                     for entry in entries.flatten() {
                         if entry.file_name() == file_name {
-                            matched_entry = Some(entry);
+                            matched_entry = Some(DirEntry::from(entry));
                             break;
                         }
                     }
@@ -787,7 +1060,16 @@ impl<'a> Searcher<'a> {
         // Always run silent: is_buffered() must hold so results land in
         // output_buffer instead of leaking to stdout (debug mode included).
         sub_searcher.silent_mode = true;
-        if let Err(err) = sub_searcher.list_search_results() {
+        sub_searcher.progress = Progress::disabled();
+        sub_searcher.hash_cache = self.hash_cache.clone();
+        // Entries a subquery examines count against the same budget
+        sub_searcher.deadline = self.deadline;
+        sub_searcher.scanned_files = self.scanned_files;
+        sub_searcher.limit_reached = self.limit_reached;
+        let result = sub_searcher.list_search_results();
+        self.scanned_files = sub_searcher.scanned_files;
+        self.limit_reached = sub_searcher.limit_reached;
+        if let Err(err) = result {
             err.print();
             return vec![];
         }
@@ -796,10 +1078,21 @@ impl<'a> Searcher<'a> {
         // only in the print path, so they must be skipped here as well.
         // Strip only the row terminator: file names may legally end in spaces
         // or tabs, and a mangled name would silently drop the row later.
-        let result_values = sub_searcher.output_buffer.iter_values()
-            .skip(query.offset as usize)
-            .map(|s| s.trim_end_matches(['\r', '\n']).to_string())
-            .collect::<Vec<String>>();
+        let result_values = sub_searcher.output_buffer.sorted_values().and_then(|values| {
+            values
+                .enumerate()
+                .filter(|(i, value)| value.is_err() || *i >= query.offset as usize)
+                .map(|(_, value)| value.map(|s| s.trim_end_matches(['\r', '\n']).to_string()))
+                .collect::<std::io::Result<Vec<String>>>()
+        });
+        let result_values = match result_values {
+            Ok(result_values) => result_values,
+            Err(err) => {
+                spill_error(err).print();
+                self.error_count += 1;
+                return vec![];
+            }
+        };
 
         if ok_to_cache {
             self.subquery_cache.insert(query_str, result_values.clone());
@@ -890,45 +1183,78 @@ impl<'a> Searcher<'a> {
         self.visit_external_index_entries(root_dir, kept)
     }
 
+    /// Examines files listed on the standard input (`-` root) or in a file
+    /// (`@file` root) without walking any directories.
+    fn visit_path_list(&mut self, source: &str) -> Result<(), SearchError> {
+        let paths = if source == STDIN_ROOT {
+            read_path_list(std::io::stdin().lock())
+        } else {
+            let list_file = &source[PATH_LIST_ROOT_PREFIX.len_utf8()..];
+            fs::File::open(list_file).and_then(read_path_list)
+        };
+
+        let paths = match paths {
+            Ok(paths) => paths,
+            Err(err) => {
+                self.error_count += 1;
+                path_error_message(Path::new(source), err);
+                return Ok(());
+            }
+        };
+
+        let root_dir = self.current_root_dir.clone();
+        for path in paths {
+            if self.should_stop() {
+                break;
+            }
+
+            // Bare file names are resolved against the current directory
+            let path = match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => path,
+                _ => Path::new(".").join(path),
+            };
+
+            let entry = DirEntry::from_path(path);
+            match entry.metadata() {
+                Ok(_) => self.check_listed_entry(&entry, &root_dir)?,
+                Err(err) => {
+                    self.error_count += 1;
+                    path_error_message(&entry.path(), err);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Examines the given paths one by one in their order, without reading
+    /// their parent directories. Paths removed since the index was built are skipped.
     #[cfg(any(all(windows, feature = "everything"), all(unix, feature = "plocate")))]
     fn visit_external_index_entries(
         &mut self,
         root_dir: &Path,
         paths: impl Iterator<Item = PathBuf>,
     ) -> Result<(), SearchError> {
-        use std::ffi::OsString;
-
-        let mut by_parent: HashMap<PathBuf, HashSet<OsString>> = HashMap::new();
         for path in paths {
-            if let (Some(parent), Some(name)) = (path.parent(), path.file_name()) {
-                by_parent
-                    .entry(parent.to_path_buf())
-                    .or_default()
-                    .insert(name.to_os_string());
+            if self.should_stop() {
+                break;
+            }
+
+            let entry = DirEntry::from_path(path);
+            if entry.metadata().is_ok() {
+                self.check_listed_entry(&entry, root_dir)?;
             }
         }
 
-        for (parent, names) in by_parent {
-            match fs::read_dir(&parent) {
-                Ok(entries) => {
-                    for entry in entries.flatten() {
-                        if !self.is_buffered() && self.query.limit > 0 && self.query.limit <= self.found {
-                            return Ok(());
-                        }
-                        if names.contains(&entry.file_name())
-                            && let Err(err) = self.check_file(&entry, root_dir, &None, None) {
-                                if err.is_fatal() {
-                                    return Err(err);
-                                }
-                                self.handle_nonfatal_error(err, &entry.path());
-                            }
-                    }
-                }
-                Err(err) => {
-                    self.error_count += 1;
-                    path_error_message(&parent, err);
-                }
+        Ok(())
+    }
+
+    fn check_listed_entry(&mut self, entry: &DirEntry, root_dir: &Path) -> Result<(), SearchError> {
+        if let Err(err) = self.check_file(entry, root_dir, &None, None) {
+            if err.is_fatal() {
+                return Err(err);
             }
+            self.handle_nonfatal_error(err, &entry.path());
         }
 
         Ok(())
@@ -1000,19 +1326,42 @@ impl<'a> Searcher<'a> {
             }
         };
 
+        self.progress.enter_dir(dir);
+
         // Read the directory and process each entry
         let root_dir = self.current_root_dir.clone();
-        match fs::read_dir(dir) {
-            Ok(entry_list) => {
+
+        // Entries are stat'ed relative to the directory with `statx`, so the
+        // kernel doesn't walk the full path for each of them. The directory
+        // is then read from the same descriptor.
+        #[cfg(target_os = "linux")]
+        let keep_fd = self.fms.statx_mask != 0;
+        #[cfg(not(target_os = "linux"))]
+        let keep_fd = false;
+
+        match read_dir(dir, keep_fd) {
+            Ok(mut entry_list) => {
+                #[cfg(target_os = "linux")]
+                let dir_fd = entry_list.as_raw_fd();
+
+                let entry_list: Box<dyn Iterator<Item = std::io::Result<DirEntry>>> =
+                    match self.current_entry_order {
+                        EntryOrder::Unsorted => Box::new(entry_list.by_ref()),
+                        order => Box::new(sort_dir_entries(entry_list.by_ref(), order).into_iter()),
+                    };
+
                 for entry in entry_list {
-                    if !self.is_buffered() && self.query.limit > 0 && self.query.limit <= self.found
-                    {
+                    if self.should_stop() {
                         break;
                     }
 
                     match entry {
                         Ok(entry) => {
                             let mut path = entry.path();
+                            if self.is_output_archive(&path) {
+                                continue;
+                            }
+
                             let pass_ignores = if self.current_apply_gitignore || self.current_apply_hgignore || self.current_apply_dockerignore {
                                 let canonical_entry_path = PathBuf::from(canonical_path.as_ref().unwrap()).join(entry.file_name());
 
@@ -1061,7 +1410,15 @@ impl<'a> Searcher<'a> {
                                 };
 
                                 if self.current_min_depth == 0 || depth >= self.current_min_depth {
+                                    #[cfg(target_os = "linux")]
+                                    {
+                                        self.current_dir_fd = dir_fd;
+                                    }
                                     let checked = self.check_file(&entry, &root_dir, &None, file_type_hint);
+                                    #[cfg(target_os = "linux")]
+                                    {
+                                        self.current_dir_fd = None;
+                                    }
                                     if let Err(err) = checked {
                                         if err.is_fatal() {
                                             return Err(err);
@@ -1070,29 +1427,9 @@ impl<'a> Searcher<'a> {
                                         continue;
                                     }
 
-                                    if self.current_search_archives
-                                        && self.is_zip_archive(&path.to_string_lossy())
-                                        && let Ok(file) = fs::File::open(&path)
-                                            && let Ok(mut archive) = zip::ZipArchive::new(file) {
-                                                for i in 0..archive.len() {
-                                                    if !self.is_buffered() && self.query.limit > 0
-                                                        && self.query.limit <= self.found
-                                                    {
-                                                        break;
-                                                    }
-
-                                                    if let Ok(afile) = archive.by_index(i) {
-                                                        let file_info = to_file_info(&afile);
-                                                        if let Err(err) = self.check_file(&entry, &root_dir, &Some(file_info), None) {
-                                                            if err.is_fatal() {
-                                                                return Err(err);
-                                                            }
-                                                            self.handle_nonfatal_error(err, &path);
-                                                            continue;
-                                                        }
-                                                    }
-                                                }
-                                            }
+                                    if self.current_search_archives {
+                                        self.visit_archive(&entry, &root_dir, &path)?;
+                                    }
                                 }
 
                                 // Recursively visit subdirectories if we're not too deep
@@ -1175,7 +1512,7 @@ impl<'a> Searcher<'a> {
         }
 
         if self.current_traversal_mode == Bfs && process_queue {
-            while !self.dir_queue.is_empty() {
+            while !self.dir_queue.is_empty() && !self.should_stop() {
                 let (path, queued_root_depth) = self.dir_queue.pop_front().unwrap();
                 #[cfg(feature = "git")]
                 let repo;
@@ -1208,6 +1545,276 @@ impl<'a> Searcher<'a> {
         Ok(())
     }
 
+    /// Checks the entries of an archive found during the search.
+    fn visit_archive(&mut self, entry: &DirEntry, root_dir: &Path, path: &Path) -> Result<(), SearchError> {
+        if let Ok(file) = fs::File::open(path) {
+            let archive_name = path.to_string_lossy();
+            self.visit_archive_content(entry, root_dir, &archive_name, Box::new(file), "", 1)?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks the entries of an archive on disk or nested in other archives.
+    /// `prefix` names the nested archives, and `level` counts them from one.
+    /// Entries that are archives themselves are searched in turn until
+    /// `archive_depth` is reached.
+    fn visit_archive_content(
+        &mut self,
+        entry: &DirEntry,
+        root_dir: &Path,
+        archive_name: &str,
+        reader: ArchiveReader,
+        prefix: &str,
+        level: u32,
+    ) -> Result<(), SearchError> {
+        let search_nested = level < self.current_archive_depth;
+
+        #[cfg(feature = "sevenz")]
+        if self.is_7z_archive(archive_name) {
+            let Ok(mut archive) = open_7z_archive(reader) else {
+                return Ok(());
+            };
+
+            if self.reads_archived_content {
+                return self.visit_7z_archive_content(entry, root_dir, archive, prefix, level);
+            }
+
+            let mut nested_archives = HashSet::new();
+            for sevenz_entry in archive.archive().files.iter().filter(|e| !e.is_anti_item()) {
+                if self.should_stop() {
+                    break;
+                }
+
+                let mut file_info = sevenz_entry_to_file_info(sevenz_entry);
+                if search_nested && !sevenz_entry.is_directory() && self.is_nested_archive(&file_info.name) {
+                    nested_archives.insert(file_info.name.clone());
+                }
+                file_info.name.insert_str(0, prefix);
+                self.check_archive_entry(entry, root_dir, file_info, None)?;
+            }
+
+            // Decompressing a 7z archive once is cheaper than seeking the nested archives one by one.
+            // Each nested archive is searched as soon as it's decompressed, so only one is buffered at a time.
+            let mut result = Ok(());
+            if !nested_archives.is_empty() {
+                let _ = archive.for_each_entries(|sevenz_entry, content| {
+                    let name = sevenz_entry_to_file_info(sevenz_entry).name;
+                    if !nested_archives.remove(&name) {
+                        std::io::copy(content, &mut std::io::sink())?;
+                        return Ok(true);
+                    }
+                    if self.should_stop() {
+                        return Ok(false);
+                    }
+
+                    let content = buffer_archive_entry(content, sevenz_entry.size())?;
+                    result = self.visit_nested_archive(entry, root_dir, &name, content, prefix, level);
+                    Ok(result.is_ok() && !nested_archives.is_empty())
+                });
+            }
+
+            return result;
+        }
+
+        #[cfg(feature = "rar")]
+        if self.is_rar_archive(archive_name) {
+            // RAR entries are listed, but can't be read, nor searched for nested archives
+            if let Ok(rar_entries) = list_rar_entries(reader) {
+                for mut file_info in rar_entries {
+                    if self.should_stop() {
+                        break;
+                    }
+
+                    file_info.name.insert_str(0, prefix);
+                    self.check_archive_entry(entry, root_dir, file_info, None)?;
+                }
+            }
+            return Ok(());
+        }
+
+        if self.is_zip_archive(archive_name) {
+            if let Ok(mut archive) = zip::ZipArchive::new(reader) {
+                for i in 0..archive.len() {
+                    if self.should_stop() {
+                        break;
+                    }
+
+                    // Listed raw, so that encrypted entries show up too
+                    let Ok((mut file_info, is_file)) = archive.by_index_raw(i).map(|afile| (to_file_info(&afile), afile.is_file())) else {
+                        continue;
+                    };
+                    let name = file_info.name.clone();
+                    file_info.name.insert_str(0, prefix);
+                    let content = match self.reads_archived_content && is_file {
+                        true => buffer_zip_entry(&mut archive, i),
+                        false => None,
+                    };
+                    self.check_archive_entry(entry, root_dir, file_info, content)?;
+
+                    if search_nested && is_file && self.is_nested_archive(&name) {
+                        // The content read for the fields of the entry is searched in turn
+                        let content = self.fms.take_archived_content().or_else(|| buffer_zip_entry(&mut archive, i));
+                        if let Some(content) = content {
+                            self.visit_nested_archive(entry, root_dir, &name, content, prefix, level)?;
+                        }
+                    }
+                }
+            }
+        } else if self.is_tar_archive(archive_name)
+            && let Ok(mut archive) = open_tar_archive(reader)
+                && let Ok(tar_entries) = archive.entries() {
+                    // A damaged archive ends the listing at the damage
+                    for mut tar_entry in tar_entries.map_while(Result::ok) {
+                        if self.should_stop() {
+                            break;
+                        }
+
+                        let mut file_info = tar_entry_to_file_info(&tar_entry);
+                        let name = file_info.name.clone();
+                        file_info.name.insert_str(0, prefix);
+                        let is_file = tar_entry.header().entry_type().is_file();
+                        let size = tar_entry.size();
+                        let content = match self.reads_archived_content && is_file {
+                            true => buffer_archive_entry(&mut tar_entry, size).ok(),
+                            false => None,
+                        };
+                        self.check_archive_entry(entry, root_dir, file_info, content)?;
+
+                        if search_nested && is_file && self.is_nested_archive(&name) {
+                            // The content read for the fields of the entry is searched in turn
+                            let content = match self.fms.take_archived_content() {
+                                Some(content) => Some(content),
+                                None => buffer_archive_entry(&mut tar_entry, size).ok(),
+                            };
+                            if let Some(content) = content {
+                                self.visit_nested_archive(entry, root_dir, &name, content, prefix, level)?;
+                            }
+                        }
+                    }
+                }
+
+        Ok(())
+    }
+
+    /// Checks the entries of a 7z archive as they are decompressed, so that
+    /// the content of each is read once. Entries that can't be decompressed,
+    /// like the ones after a damaged block, are checked without it.
+    #[cfg(feature = "sevenz")]
+    fn visit_7z_archive_content(
+        &mut self,
+        entry: &DirEntry,
+        root_dir: &Path,
+        mut archive: sevenz_rust::SevenZReader<ArchiveReader>,
+        prefix: &str,
+        level: u32,
+    ) -> Result<(), SearchError> {
+        let search_nested = level < self.current_archive_depth;
+        let mut checked = HashSet::new();
+        let mut result = Ok(());
+        let _ = archive.for_each_entries(|sevenz_entry, content| {
+            if sevenz_entry.is_anti_item() {
+                return Ok(true);
+            }
+            if self.should_stop() {
+                return Ok(false);
+            }
+
+            let mut file_info = sevenz_entry_to_file_info(sevenz_entry);
+            let name = file_info.name.clone();
+            let is_file = !sevenz_entry.is_directory();
+            let content = match is_file {
+                true => Some(buffer_archive_entry(content, sevenz_entry.size())?),
+                false => None,
+            };
+            checked.insert(name.clone());
+            file_info.name.insert_str(0, prefix);
+            result = self.check_archive_entry(entry, root_dir, file_info, content);
+
+            if result.is_ok() && search_nested && is_file && self.is_nested_archive(&name)
+                && let Some(content) = self.fms.take_archived_content() {
+                    result = self.visit_nested_archive(entry, root_dir, &name, content, prefix, level);
+                }
+            Ok(result.is_ok())
+        });
+        result?;
+
+        for sevenz_entry in archive.archive().files.iter().filter(|e| !e.is_anti_item()) {
+            if self.should_stop() {
+                break;
+            }
+
+            let mut file_info = sevenz_entry_to_file_info(sevenz_entry);
+            if checked.contains(&file_info.name) {
+                continue;
+            }
+            file_info.name.insert_str(0, prefix);
+            self.check_archive_entry(entry, root_dir, file_info, None)?;
+        }
+
+        Ok(())
+    }
+
+    fn visit_nested_archive(
+        &mut self,
+        entry: &DirEntry,
+        root_dir: &Path,
+        name: &str,
+        content: ArchiveReader,
+        prefix: &str,
+        level: u32,
+    ) -> Result<(), SearchError> {
+        let nested_prefix = format!("{}{}{}", prefix, name, NESTED_ARCHIVE_SEPARATOR);
+        self.visit_archive_content(entry, root_dir, name, content, &nested_prefix, level + 1)
+    }
+
+    /// Checks an entry of an archive, with its content if it was read while listing the archive.
+    fn check_archive_entry(
+        &mut self,
+        entry: &DirEntry,
+        root_dir: &Path,
+        file_info: FileInfo,
+        content: Option<ArchiveReader>,
+    ) -> Result<(), SearchError> {
+        self.current_archived_content = content;
+        if let Err(err) = self.check_file(entry, root_dir, &Some(file_info), None) {
+            if err.is_fatal() {
+                return Err(err);
+            }
+            self.handle_nonfatal_error(err, &entry.path());
+        }
+        Ok(())
+    }
+
+    /// Whether a field or function of the query reads the content of the files.
+    fn query_reads_content(&self) -> bool {
+        self.query.fields.iter()
+            .chain(&self.query.ordering_fields)
+            .chain(&self.query.grouping_fields)
+            .chain(&self.query.expr)
+            .chain(&self.query.having)
+            .any(|expr| expr.reads_content())
+    }
+
+    /// Attributes the Linux `statx` fast path has to fetch for this query,
+    /// or zero when the query needs the full metadata anyway.
+    #[cfg(target_os = "linux")]
+    fn get_statx_mask(&self) -> u32 {
+        if self.use_colors && self.query.fields.iter().any(|f| f.contains_colorized()) {
+            return 0;
+        }
+
+        let mut fields = self.query.get_all_fields();
+        if let Some(ref expr) = self.query.expr {
+            fields.extend(expr.get_required_fields());
+        }
+        for grouping_expr in &self.query.grouping_fields {
+            fields.extend(grouping_expr.get_required_fields());
+        }
+
+        statx_mask_for_fields(&fields)
+    }
+
     fn handle_nonfatal_error(&mut self, mut err: SearchError, default_source: &Path) {
         self.error_count += 1;
         if err.source.is_empty() {
@@ -1278,7 +1885,7 @@ impl<'a> Searcher<'a> {
                 let entry_key = column_expr_str.split('.').nth(1).unwrap().to_string();
                 context_entry.insert(entry_key, file_map[&column_expr_str].clone());
             }
-            return Ok(Variant::from_string(&file_map[&column_expr_str]));
+            return Ok(restore_type(column_expr, &file_map[&column_expr_str]));
         }
 
         if let Some(ref subquery) = column_expr.subquery {
@@ -1315,7 +1922,7 @@ impl<'a> Searcher<'a> {
                 context_entry.insert(entry_key, raw.to_string());
                 return Ok(result);
             } else if let Some(val) = file_map.get(&field.to_string()) {
-                return Ok(apply_minus(column_expr, Variant::from_string(val)));
+                return Ok(apply_minus(column_expr, restore_type(column_expr, val)));
             } else {
                 return Ok(Variant::empty(VariantType::String));
             }
@@ -1379,7 +1986,7 @@ impl<'a> Searcher<'a> {
                 buffer_key,
                 &column_expr.val,
             );
-            Ok(Variant::from_string(&aggr_result))
+            Ok(to_numeric_variant(&aggr_result))
         } else {
             let function_arg =
                 self.get_column_expr_value(entry, file_info, root_path, file_map, accumulator, left_expr);
@@ -1391,12 +1998,14 @@ impl<'a> Searcher<'a> {
                     function_args.push(arg_value.to_string());
                 }
             }
-            let result = function::get_value(
+            let result = function::get_file_value(
                 function,
                 function_arg?.to_string(),
                 function_args,
                 entry,
                 file_info,
+                &mut self.fms,
+                self.current_decompress,
             )?;
             file_map.insert(column_expr.to_string(), result.to_string());
 
@@ -1412,6 +2021,7 @@ impl<'a> Searcher<'a> {
         root_path: &Path,
         field: &Field,
     ) -> Result<Variant, SearchError> {
+        let mut hash_cache = self.hash_cache.borrow_mut();
         let mut ctx = FieldContext {
             entry,
             file_info,
@@ -1419,7 +2029,11 @@ impl<'a> Searcher<'a> {
             fms: &mut self.fms,
             #[cfg(feature = "git")]
             git_cache: &mut self.git_cache,
+            dir_size_cache: &mut self.dir_size_cache,
+            hash_cache: &mut hash_cache,
+            checksum_files: &mut self.checksum_files,
             follow_symlinks: self.current_follow_symlinks,
+            decompress: self.current_decompress,
             config: self.config,
             default_config: self.default_config,
             #[cfg(all(unix, feature = "users"))]
@@ -1429,15 +2043,44 @@ impl<'a> Searcher<'a> {
     }
 
     fn check_file(&mut self, entry: &DirEntry, root_path: &Path, file_info: &Option<FileInfo>, file_type_hint: Option<FileType>) -> Result<(), SearchError> {
+        if file_info.is_none() && self.is_output_archive(&entry.path()) {
+            return Ok(());
+        }
+
+        self.scanned_files += 1;
+        self.progress.update(self.scanned_files, self.found);
         self.fms.clear();
+        #[cfg(target_os = "linux")]
+        self.fms.seed_dir_fd(self.current_dir_fd);
         // Reuse the file type the traversal already resolved, so is_dir /
         // is_file / is_symlink don't issue a redundant stat for this entry.
         self.fms.seed_file_type(file_type_hint);
+        self.fms.seed_archived_content(self.current_archived_content.take());
+
+        // In unique mode a physical file, reached again through an overlapping
+        // root, a followed symlink or a hardlink, is reported only once.
+        // Archive entries are told apart by their name within the archive.
+        let unique_key = match self.config.unique.unwrap_or(false) {
+            true => get_file_identity(entry, self.current_follow_symlinks)
+                .map(|identity| (identity, file_info.as_ref().map(|fi| fi.name.clone()))),
+            false => None,
+        };
+        if let Some(ref key) = unique_key
+            && self.reported_files.contains(key) {
+                return Ok(());
+            }
+        let found_before = self.found;
 
         let mut file_map = std::mem::take(&mut self.file_map);
         file_map.clear();
         let result = self.check_file_inner(entry, root_path, file_info, &mut file_map);
         self.file_map = file_map;
+
+        if let Some(key) = unique_key
+            && self.found > found_before {
+                self.reported_files.insert(key);
+            }
+
         result
     }
 
@@ -1477,6 +2120,9 @@ impl<'a> Searcher<'a> {
 
         if self.query.is_aggregated() {
             for field in self.query.get_all_fields() {
+                if self.duplicate_hash_field == Some(field) {
+                    continue;
+                }
                 file_map.insert(
                     field.to_string(),
                     self.get_field_value(entry, file_info, root_path, &field).unwrap_or(Variant::empty(VariantType::String)).to_string(),
@@ -1511,19 +2157,28 @@ impl<'a> Searcher<'a> {
             for left in &aggregate_inner_exprs {
                 self.get_column_expr_value(Some(entry), file_info, root_path, file_map, None, left)?;
             }
-            for field in self.query.grouping_fields.iter() {
-                if file_map.get(&field.to_string()).is_none() {
+            // Duplicate detection: the file is hashed after the search,
+            // and only if it may have a duplicate at all
+            if self.duplicate_hash_field.is_some() {
+                let size = match file_info {
+                    Some(_) => None,
+                    None => fs::metadata(entry.path()).ok().filter(|m| m.is_file()).map(|m| m.len()),
+                };
+                let archive_entry = file_info.as_ref().map(|file_info| file_info.name.clone());
+                self.duplicate_candidates.push(entry.path(), size, (archive_entry, std::mem::take(file_map)));
+                return Ok(());
+            }
+            for field in self.query.grouping_fields.iter() {
+                if file_map.get(&field.to_string()).is_none() {
                     self.get_column_expr_value(Some(entry), file_info, root_path, file_map, None, field)?;
                 }
             }
-            let group_key: Vec<String> = self.query.grouping_fields.iter()
-                .map(|f| file_map.get(&f.to_string()).cloned().unwrap_or_default())
-                .collect();
-            let accumulator = self.accumulators.entry(group_key).or_default();
-            accumulator.increment_count();
-            for (key, value) in file_map.iter() {
-                accumulator.push(key, value);
-            }
+            self.add_to_group(file_map);
+            return Ok(());
+        }
+
+        // Entries of archives can't be copied into the archive being created
+        if file_info.is_some() && self.query.output_format.is_archive() {
             return Ok(());
         }
 
@@ -1533,7 +2188,7 @@ impl<'a> Searcher<'a> {
             self.results_writer.write_row_separator(&mut buf)?;
         }
 
-        let mut items: Vec<(String, String)> = Vec::new();
+        let mut items: Vec<(String, Variant)> = Vec::new();
 
         if self.use_colors && self.query.fields.iter().any(|f| f.contains_colorized()) {
             self.fms.update_file_metadata(entry, self.current_follow_symlinks);
@@ -1544,15 +2199,21 @@ impl<'a> Searcher<'a> {
                 self.get_column_expr_value(Some(entry), file_info, root_path, file_map, None, field)?;
 
             let value = match self.use_colors && field.contains_colorized() {
-                true => self.colorize(&record.to_string()),
-                false => record.to_string(),
+                true => Variant::from_string(&self.colorize(&record.to_string())),
+                false if matches!(record.get_type(), VariantType::String) => restore_type(field, &record.to_string()),
+                false => record,
             };
             items.push((field.to_string(), value));
         }
 
         if file_info.is_some() {
             let archive_path = entry.path().to_string_lossy().to_string();
-            items.insert(0, (String::from("archive"), format!("[{}]", archive_path)));
+            items.insert(0, (String::from("archive"), Variant::from_string(&format!("[{}]", archive_path))));
+        }
+
+        if self.query.output_format.is_archive() {
+            items.insert(0, (String::from(ARCHIVE_ENTRY), Variant::from_string(&get_entry_name(&entry.path(), root_path))));
+            items.insert(0, (String::from(ARCHIVE_SOURCE), Variant::from_string(&entry.path().to_string_lossy().to_string())));
         }
 
         let mut criteria = vec!["".to_string(); self.query.ordering_fields.len()];
@@ -1568,7 +2229,7 @@ impl<'a> Searcher<'a> {
         self.results_writer.write_row(&mut buf, items)?;
 
         if self.is_buffered() {
-            self.output_buffer.insert(
+            self.output_buffer.insert_or_spill(
                 Criteria::new(
                     self.ordering_fields_rc.clone(),
                     criteria,
@@ -1577,13 +2238,90 @@ impl<'a> Searcher<'a> {
                 String::from(buf),
             );
         } else {
-            try_output!(write!(std::io::stdout(), "{}", String::from(buf)),
+            self.progress.clear();
+            try_output!(self.results_writer.write_rendered(&mut std::io::stdout(), &String::from(buf)),
                         Err(SearchError::broken_pipe()));
         }
 
         Ok(())
     }
 
+    fn add_to_group(&mut self, file_map: &HashMap<String, String>) {
+        let group_key: Vec<String> = self.query.grouping_fields.iter()
+            .map(|f| file_map.get(&f.to_string()).cloned().unwrap_or_default())
+            .collect();
+        let accumulator = match self.accumulators.entry(group_key) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                self.accumulators_size += entry.key().mem_size()
+                    + file_map.keys().map(|key| key.mem_size() + size_of::<function::FieldAccumulator>()).sum::<usize>();
+                entry.insert(function::GroupAccumulator::default())
+            }
+        };
+        accumulator.increment_count();
+        for (key, value) in file_map.iter() {
+            accumulator.push(key, value);
+        }
+
+        if self.max_memory > 0 && self.accumulators_size > self.max_memory {
+            self.spill_accumulators();
+        }
+    }
+
+    /// Writes the accumulators out as a run sorted by group key, to be merged
+    /// with the others when the groups are output.
+    fn spill_accumulators(&mut self) {
+        let mut groups: Vec<_> = self.accumulators.iter().collect();
+        groups.sort_by(|a, b| a.0.cmp(b.0));
+
+        match SpillRun::write(groups) {
+            Ok(run) => {
+                self.accumulator_runs.push(run);
+                self.accumulators.clear();
+                self.accumulators_size = 0;
+            }
+            Err(err) => {
+                // Carry on within memory rather than lose the results
+                error_message("spill file", &err.to_string());
+                self.max_memory = 0;
+            }
+        }
+    }
+
+    /// Last stage of a duplicate-detection query: hashes the files that
+    /// survived the size and prefix stages and puts them into their groups.
+    /// The rest would end up in groups of one file, which HAVING rejects.
+    fn group_duplicate_candidates(&mut self) {
+        let hash_field = match self.duplicate_hash_field {
+            Some(hash_field) => hash_field,
+            None => return,
+        };
+        let algorithm = match HashAlgorithm::from_field(&hash_field) {
+            Some(algorithm) => algorithm,
+            None => return,
+        };
+
+        let group_key = self.query.grouping_fields[0].to_string();
+        let candidates = std::mem::replace(&mut self.duplicate_candidates, DuplicateCandidates::new());
+        for (path, (archive_entry, mut file_map)) in candidates.into_candidates() {
+            // Files still unhashed when time runs out are left out of the groups
+            if self.is_timed_out() {
+                self.limit_reached = Some(ResourceLimit::Timeout);
+                break;
+            }
+            self.progress.update(self.scanned_files, self.found);
+            let hash = match archive_entry {
+                Some(name) => read_archive_entry(&path, &name, |reader, _| algorithm.hash_reader(reader))
+                    .unwrap_or_default(),
+                None => self.hash_cache.borrow_mut().get_or_compute(algorithm.name(), &path, |path| algorithm.hash_file(path)),
+            };
+            file_map.insert(group_key.clone(), hash);
+            self.add_to_group(&file_map);
+        }
+
+        self.progress.clear();
+    }
+
     fn colorize(&mut self, value: &str) -> String {
         let path = Path::new(value);
         let style = match self.fms.get_file_metadata() {
@@ -2000,11 +2738,48 @@ impl<'a> Searcher<'a> {
         self.check_extension(file_name, &self.config.is_zip_archive, &self.default_config.is_zip_archive)
     }
 
+    fn is_tar_archive(&self, file_name: &str) -> bool {
+        self.check_extension(file_name, &self.config.is_tar_archive, &self.default_config.is_tar_archive)
+    }
+
+    #[cfg(feature = "sevenz")]
+    fn is_7z_archive(&self, file_name: &str) -> bool {
+        self.check_extension(file_name, &self.config.is_7z_archive, &self.default_config.is_7z_archive)
+    }
+
+    #[cfg(feature = "rar")]
+    fn is_rar_archive(&self, file_name: &str) -> bool {
+        self.check_extension(file_name, &self.config.is_rar_archive, &self.default_config.is_rar_archive)
+    }
+
+    /// Whether the path is the archive being created by the query, which
+    /// is written while the search goes on and can't be added to itself.
+    fn is_output_archive(&self, path: &Path) -> bool {
+        match self.output_archive_path {
+            Some(ref output_path) => {
+                path.file_name() == output_path.file_name()
+                    && resolve_output_path(path).is_some_and(|path| &path == output_path)
+            }
+            None => false,
+        }
+    }
+
+    /// Whether an archive entry is an archive that can be read in turn.
+    fn is_nested_archive(&self, file_name: &str) -> bool {
+        #[cfg(feature = "sevenz")]
+        if self.is_7z_archive(file_name) {
+            return true;
+        }
+
+        self.is_zip_archive(file_name) || self.is_tar_archive(file_name)
+    }
+
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::io::Write;
     use crate::expr::Expr;
     use crate::field::Field;
     use crate::function::Function;
@@ -2024,6 +2799,7 @@ mod tests {
             limit: 0,
             offset: 0,
             output_format: OutputFormat::Tabs,
+            output_path: None,
             raw_query: String::new(),
         }));
 
@@ -2047,6 +2823,7 @@ mod tests {
             limit: 0,
             offset: 0,
             output_format: OutputFormat::Tabs,
+            output_path: None,
             raw_query: String::new(),
         }));
 
@@ -2073,6 +2850,7 @@ mod tests {
             limit: 0,
             offset: 0,
             output_format: OutputFormat::Tabs,
+            output_path: None,
             raw_query: String::new(),
         }));
 
@@ -2114,6 +2892,7 @@ mod tests {
             limit: 10,
             offset: 5,
             output_format: OutputFormat::Tabs,
+            output_path: None,
             raw_query: String::new(),
         }));
         let config = Box::leak(Box::new(Config::default()));
@@ -2155,6 +2934,7 @@ mod tests {
             limit: 0,
             offset: 0,
             output_format: OutputFormat::Tabs,
+            output_path: None,
             raw_query: String::new(),
         }));
         assert!(query.is_aggregated());
@@ -2187,6 +2967,7 @@ mod tests {
             limit: 0,
             offset: 0,
             output_format: OutputFormat::Tabs,
+            output_path: None,
             raw_query: String::new(),
         }));
         let config = Box::leak(Box::new(Config::default()));
@@ -2223,6 +3004,22 @@ mod tests {
         assert!(!searcher.is_zip_archive("test"));
     }
 
+    #[test]
+    fn test_is_tar_archive() {
+        let searcher = create_test_searcher();
+
+        assert!(searcher.is_tar_archive("test.tar"));
+        assert!(searcher.is_tar_archive("test.tar.gz"));
+        assert!(searcher.is_tar_archive("test.tgz"));
+        assert!(searcher.is_tar_archive("test.tar.bz2"));
+        assert!(searcher.is_tar_archive("test.tar.xz"));
+        assert!(searcher.is_tar_archive("test.tar.zst"));
+
+        assert!(!searcher.is_tar_archive("test.gz"));
+        assert!(!searcher.is_tar_archive("test.zip"));
+        assert!(!searcher.is_tar_archive("test"));
+    }
+
 
     #[test]
     fn test_bound_column_resolution_across_root_aliases() {
@@ -2374,6 +3171,7 @@ mod tests {
             limit: 0,
             offset: 0,
             output_format: OutputFormat::Tabs,
+            output_path: None,
             raw_query: String::new(),
         }));
         let config = Box::leak(Box::new(Config::default()));
@@ -2586,6 +3384,7 @@ mod tests {
             limit: 0,
             offset: 0,
             output_format: OutputFormat::Tabs,
+            output_path: None,
             raw_query: String::new(),
         }));
 
@@ -2783,6 +3582,7 @@ mod tests {
             limit: 0,
             offset: 0,
             output_format: OutputFormat::Tabs,
+            output_path: None,
             raw_query: String::new(),
         };
 
@@ -2804,7 +3604,7 @@ mod tests {
         fs::create_dir_all(&tmp).unwrap();
         fs::write(tmp.join("a.txt"), "x").unwrap();
 
-        let entry = fs::read_dir(&tmp).unwrap().next().unwrap().unwrap();
+        let entry = DirEntry::from(fs::read_dir(&tmp).unwrap().next().unwrap().unwrap());
         let mut searcher = create_test_searcher();
 
         // Build: modified = 'not-a-date'
@@ -2836,7 +3636,7 @@ mod tests {
         fs::create_dir_all(&tmp).unwrap();
         fs::write(tmp.join("a.txt"), "x").unwrap();
 
-        let entry = fs::read_dir(&tmp).unwrap().next().unwrap().unwrap();
+        let entry = DirEntry::from(fs::read_dir(&tmp).unwrap().next().unwrap().unwrap());
         let mut searcher = create_test_searcher();
 
         let expr = Expr::op(
@@ -2874,6 +3674,7 @@ mod tests {
             limit: 0,
             offset: 0,
             output_format: OutputFormat::Tabs,
+            output_path: None,
             raw_query: String::new(),
         };
         assert!(is_subquery_cacheable(&subquery));
@@ -2903,6 +3704,7 @@ mod tests {
             limit: 0,
             offset: 0,
             output_format: OutputFormat::Tabs,
+            output_path: None,
             raw_query: String::new(),
         };
 
@@ -2934,6 +3736,7 @@ mod tests {
             limit: 0,
             offset: 0,
             output_format: OutputFormat::Tabs,
+            output_path: None,
             raw_query: String::new(),
         };
 
@@ -2997,6 +3800,9 @@ mod tests {
         let mut parser = Parser::new(&mut lexer);
         let parsed = parser.parse(false).expect("parse failed");
         let parsed = Box::leak(Box::new(parsed));
+        // Keep hashes computed by tests out of the user's hash cache
+        let mut config = config;
+        config.hash_cache = Some(false);
         let config = Box::leak(Box::new(config));
         let default_config = Box::leak(Box::new(Config::default()));
 
@@ -3006,8 +3812,9 @@ mod tests {
 
         searcher
             .output_buffer
-            .iter_values()
-            .map(|s| s.trim_end().to_string())
+            .sorted_values()
+            .unwrap()
+            .map(|s| s.unwrap().trim_end().to_string())
             .collect()
     }
 
@@ -3435,4 +4242,790 @@ mod tests {
             "SELECT-list subquery must see each outer row's size via its WHERE, got: {:?}", rows
         );
     }
+
+    #[test]
+    fn path_list_root_examines_listed_files_only() {
+        let tmp = std::env::temp_dir().join("fselect_test_path_list_root");
+        let _ = fs::remove_dir_all(&tmp);
+        fs::create_dir_all(tmp.join("sub")).unwrap();
+        fs::write(tmp.join("a.txt"), "aaa").unwrap();
+        fs::write(tmp.join("b.txt"), "b").unwrap();
+        fs::write(tmp.join("sub/c.txt"), "cc").unwrap();
+        let list = format!(
+            "{}\n{}\n{}\n",
+            tmp.join("a.txt").to_string_lossy(),
+            tmp.join("sub/c.txt").to_string_lossy(),
+            tmp.join("missing.txt").to_string_lossy(),
+        );
+        fs::write(tmp.join("list.txt"), list).unwrap();
+
+        let rows = run_query_against_dir(
+            "select name, size from @__DIR__/list.txt where size > 1 order by name",
+            &tmp,
+        );
+
+        let _ = fs::remove_dir_all(&tmp);
+        assert_eq!(rows, vec!["a.txt\t3", "c.txt\t2"]);
+    }
+
+    #[test]
+    fn path_list_root_keeps_the_order_of_the_list() {
+        let tmp = std::env::temp_dir().join("fselect_test_path_list_order");
+        let _ = fs::remove_dir_all(&tmp);
+        fs::create_dir_all(tmp.join("@scope")).unwrap();
+        for name in ["c.txt", "a.txt", "b.txt", "@scope/d.txt"] {
+            fs::write(tmp.join(name), "x").unwrap();
+        }
+        let list: Vec<String> = ["c.txt", "@scope/d.txt", "a.txt", "b.txt"]
+            .iter()
+            .map(|name| tmp.join(name).to_string_lossy().to_string())
+            .collect();
+        fs::write(tmp.join("list.txt"), list.join("\n")).unwrap();
+
+        let rows = run_query_against_dir("select name from @__DIR__/list.txt", &tmp);
+
+        let _ = fs::remove_dir_all(&tmp);
+        assert_eq!(rows, vec!["c.txt", "d.txt", "a.txt", "b.txt"]);
+    }
+
+    #[test]
+    fn sorted_root_visits_entries_in_stable_order() {
+        let tmp = std::env::temp_dir().join("fselect_test_sorted_root");
+        let _ = fs::remove_dir_all(&tmp);
+        fs::create_dir_all(tmp.join("dir1")).unwrap();
+        fs::create_dir_all(tmp.join("dir10")).unwrap();
+        fs::create_dir_all(tmp.join("dir2")).unwrap();
+        for name in ["dir10/b", "dir10/a", "dir2/z", "dir1/y", "file2", "file10"] {
+            fs::write(tmp.join(name), "x").unwrap();
+        }
+
+        let bfs_by_name = run_query_against_dir("select path from __DIR__ sorted where is_file", &tmp);
+        let dfs_natural = run_query_against_dir("select path from __DIR__ natsorted dfs where is_file", &tmp);
+
+        let mut config = Config::default();
+        config.entry_order = Some(EntryOrder::Natural);
+        let bfs_config_natural = run_query_against_dir_with_config("select path from __DIR__ where is_file", &tmp, config);
+
+        let _ = fs::remove_dir_all(&tmp);
+
+        let sep = std::path::MAIN_SEPARATOR;
+        let p = |s: &str| s.replace('/', &sep.to_string());
+        assert_eq!(bfs_by_name, vec![p("file10"), p("file2"), p("dir1/y"), p("dir10/a"), p("dir10/b"), p("dir2/z")]);
+        assert_eq!(dfs_natural, vec![p("dir1/y"), p("dir2/z"), p("dir10/a"), p("dir10/b"), p("file2"), p("file10")]);
+        assert_eq!(bfs_config_natural, vec![p("file2"), p("file10"), p("dir1/y"), p("dir2/z"), p("dir10/a"), p("dir10/b")]);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn sorted_entries_with_invalid_utf8_names_keep_byte_order() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let tmp = std::env::temp_dir().join("fselect_test_sorted_invalid_utf8");
+        let _ = fs::remove_dir_all(&tmp);
+        fs::create_dir_all(&tmp).unwrap();
+        // All the names read as "x\u{FFFD}" once made valid UTF-8
+        let names: [&[u8]; 3] = [b"x\xff", b"x\xfe", b"x\xfd"];
+        for name in names {
+            fs::write(tmp.join(std::ffi::OsStr::from_bytes(name)), "x").unwrap();
+        }
+
+        let sorted_names = |order| -> Vec<Vec<u8>> {
+            sort_dir_entries(fs::read_dir(&tmp).unwrap().map(|entry| entry.map(DirEntry::from)), order)
+                .into_iter()
+                .map(|entry| entry.unwrap().file_name().as_bytes().to_vec())
+                .collect()
+        };
+        let by_name = sorted_names(EntryOrder::Name);
+        let natural = sorted_names(EntryOrder::Natural);
+        let _ = fs::remove_dir_all(&tmp);
+
+        let expected: Vec<Vec<u8>> = vec![b"x\xfd".to_vec(), b"x\xfe".to_vec(), b"x\xff".to_vec()];
+        assert_eq!(by_name, expected);
+        assert_eq!(natural, expected);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unique_mode_reports_each_physical_file_once() {
+        let tmp = std::env::temp_dir().join("fselect_test_unique_mode");
+        let _ = fs::remove_dir_all(&tmp);
+        fs::create_dir_all(tmp.join("sub")).unwrap();
+        fs::write(tmp.join("a.txt"), "aaaa").unwrap();
+        fs::write(tmp.join("sub/b.txt"), "bb").unwrap();
+        fs::hard_link(tmp.join("a.txt"), tmp.join("sub/a_link.txt")).unwrap();
+
+        let query = "select sum(size) from __DIR__, __DIR__/sub where is_file";
+        let plain = run_query_against_dir(query, &tmp);
+
+        let mut config = Config::default();
+        config.unique = Some(true);
+        let unique = run_query_against_dir_with_config(query, &tmp, config);
+
+        let _ = fs::remove_dir_all(&tmp);
+
+        // a.txt, sub/b.txt, the hardlink, then sub/b.txt and the hardlink again
+        assert_eq!(plain, vec!["16"]);
+        assert_eq!(unique, vec!["6"]);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn statx_fast_path_matches_full_metadata() {
+        let tmp = std::env::temp_dir().join("fselect_test_statx_fast_path");
+        let _ = fs::remove_dir_all(&tmp);
+        fs::create_dir_all(tmp.join("sub")).unwrap();
+        fs::write(tmp.join("a.txt"), "aaaa").unwrap();
+        fs::write(tmp.join("sub/b.txt"), "bb").unwrap();
+        std::os::unix::fs::symlink("a.txt", tmp.join("link")).unwrap();
+
+        let columns = "select path, size, allocated_size, inode, hardlinks, uid, gid, mode, modified from __DIR__ sorted";
+        // blksize is not served by statx, so the second query reads the full metadata
+        let fast = run_query_against_dir(&format!("{} where size >= 0", columns), &tmp);
+        let full = run_query_against_dir(&format!("{} where blksize > 0", columns), &tmp);
+
+        let _ = fs::remove_dir_all(&tmp);
+
+        assert_eq!(fast.len(), 4);
+        assert_eq!(fast, full);
+    }
+
+    #[test]
+    fn duplicate_query_hashes_only_possible_duplicates() {
+        let tmp = std::env::temp_dir().join("fselect_test_duplicate_query");
+        let _ = fs::remove_dir_all(&tmp);
+        fs::create_dir_all(tmp.join("sub")).unwrap();
+        fs::write(tmp.join("a.txt"), "same").unwrap();
+        fs::write(tmp.join("sub/a_copy.txt"), "same").unwrap();
+        fs::write(tmp.join("b.txt"), "diff").unwrap();
+        fs::write(tmp.join("unique.txt"), "unique size").unwrap();
+        fs::write(tmp.join("c.txt"), "twice twice").unwrap();
+        fs::write(tmp.join("sub/c.txt"), "twice twice").unwrap();
+
+        let staged = run_query_against_dir(
+            "select sha1, count(*), sum(size) from __DIR__ where is_file group by sha1 having count(*) > 1 order by sha1",
+            &tmp,
+        );
+        // Using the hash in WHERE makes every file hashed during the search
+        let plain = run_query_against_dir(
+            "select sha1, count(*), sum(size) from __DIR__ where is_file and sha1 != '' group by sha1 having count(*) > 1 order by sha1",
+            &tmp,
+        );
+
+        let _ = fs::remove_dir_all(&tmp);
+
+        assert_eq!(staged.len(), 2);
+        assert_eq!(staged, plain);
+    }
+
+    #[test]
+    fn checksum_status_against_manifest_and_sidecar() {
+        let tmp = std::env::temp_dir().join("fselect_test_checksum_status");
+        let _ = fs::remove_dir_all(&tmp);
+        fs::create_dir_all(tmp.join("sub")).unwrap();
+        fs::write(tmp.join("sub/good.txt"), "abc").unwrap();
+        fs::write(tmp.join("sub/bad.txt"), "abd").unwrap();
+        fs::write(tmp.join("sub/unlisted.txt"), "abc").unwrap();
+        fs::write(
+            tmp.join("SHA256SUMS"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad  sub/good.txt\n\
+             ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad *sub/bad.txt\n",
+        )
+        .unwrap();
+        fs::write(tmp.join("sub/unlisted.txt.md5"), "900150983CD24FB0D6963F7D28E17F72\n").unwrap();
+
+        let results = run_query_against_dir(
+            "select name, checksum_status from __DIR__/sub where name like '%.txt' order by name",
+            &tmp,
+        );
+
+        let _ = fs::remove_dir_all(&tmp);
+
+        assert_eq!(results, vec!["bad.txt\tmismatch", "good.txt\tok", "unlisted.txt\tok"]);
+    }
+
+    #[test]
+    fn similar_images_found_with_hamming_distance() {
+        use image::{DynamicImage, Rgb, RgbImage};
+
+        let tmp = std::env::temp_dir().join("fselect_test_similar_images");
+        let _ = fs::remove_dir_all(&tmp);
+        fs::create_dir_all(&tmp).unwrap();
+
+        let pattern = |inverted: bool| {
+            DynamicImage::ImageRgb8(RgbImage::from_fn(200, 200, |x, y| {
+                let value = ((x * 255 / 200) ^ (y * 255 / 200)) as u8;
+                Rgb([if inverted { 255 - value } else { value }; 3])
+            }))
+        };
+        pattern(false).save(tmp.join("photo.png")).unwrap();
+        pattern(false).thumbnail(80, 80).save(tmp.join("photo_small.jpg")).unwrap();
+        pattern(true).save(tmp.join("other.png")).unwrap();
+        fs::write(tmp.join("notes.txt"), "not an image").unwrap();
+
+        let results = run_query_against_dir(
+            "select a.name from __DIR__ as a where a.is_image and exists (\
+               select * from __DIR__ as b where b.is_image and b.name != a.name and hamming(b.dhash, a.dhash) <= 10\
+             ) order by a.name",
+            &tmp,
+        );
+        let text_hash = run_query_against_dir("select phash from __DIR__ where name = 'notes.txt'", &tmp);
+
+        let _ = fs::remove_dir_all(&tmp);
+
+        assert_eq!(results, vec!["photo.png", "photo_small.jpg"]);
+        assert_eq!(text_hash, vec![""]);
+    }
+
+    #[test]
+    fn near_duplicates_found_with_fuzzy_hash_similarity() {
+        let tmp = std::env::temp_dir().join("fselect_test_near_duplicates");
+        let _ = fs::remove_dir_all(&tmp);
+        fs::create_dir_all(tmp.join("old")).unwrap();
+        fs::create_dir_all(tmp.join("new")).unwrap();
+
+        let config: String = (0..400).map(|i| format!("option_{} = {}\n", i, i * 7919 % 1000)).collect();
+        let edited = config.replace("option_200 = 800", "option_200 = 801");
+        let unrelated: String = (0..400).map(|i| format!("{} entries in table {}\n", i * 31 % 97, i)).collect();
+        fs::write(tmp.join("old/app.conf"), &config).unwrap();
+        fs::write(tmp.join("old/db.conf"), &unrelated).unwrap();
+        fs::write(tmp.join("new/app.conf"), &edited).unwrap();
+
+        let results = run_query_against_dir(
+            "select o.name from __DIR__/old as o where exists (\
+               select * from __DIR__/new as n where similarity(n.ssdeep, o.ssdeep) >= 50\
+             )",
+            &tmp,
+        );
+
+        let _ = fs::remove_dir_all(&tmp);
+
+        assert_eq!(results, vec!["app.conf"]);
+    }
+
+    #[test]
+    fn max_files_stops_search_with_partial_results() {
+        let tmp = std::env::temp_dir().join("fselect_test_max_files");
+        let _ = fs::remove_dir_all(&tmp);
+        fs::create_dir_all(&tmp).unwrap();
+        for i in 0..10 {
+            fs::write(tmp.join(format!("file{}.txt", i)), "x").unwrap();
+        }
+
+        let mut config = Config::default();
+        config.max_files = Some(4);
+        let limited = run_query_against_dir_with_config("name from __DIR__ order by name", &tmp, config.clone());
+        let counted = run_query_against_dir_with_config("count(*) from __DIR__", &tmp, config);
+        let unlimited = run_query_against_dir("count(*) from __DIR__", &tmp);
+        let _ = fs::remove_dir_all(&tmp);
+
+        assert_eq!(limited.len(), 4);
+        assert_eq!(counted, vec!["4"]);
+        assert_eq!(unlimited, vec!["10"]);
+    }
+
+    #[test]
+    fn max_depth_default_applies_to_roots_without_depth() {
+        let tmp = std::env::temp_dir().join("fselect_test_max_depth_default");
+        let _ = fs::remove_dir_all(&tmp);
+        fs::create_dir_all(tmp.join("a/b")).unwrap();
+        fs::write(tmp.join("top.txt"), "x").unwrap();
+        fs::write(tmp.join("a/middle.txt"), "x").unwrap();
+        fs::write(tmp.join("a/b/deep.txt"), "x").unwrap();
+
+        let mut config = Config::default();
+        config.max_depth_default = Some(1);
+        let default_depth =
+            run_query_against_dir_with_config("name from __DIR__ where is_file order by name", &tmp, config.clone());
+        let explicit_depth =
+            run_query_against_dir_with_config("name from __DIR__ depth 2 where is_file order by name", &tmp, config);
+        let _ = fs::remove_dir_all(&tmp);
+
+        assert_eq!(default_depth, vec!["top.txt"]);
+        assert_eq!(explicit_depth, vec!["middle.txt", "top.txt"]);
+    }
+
+    #[test]
+    fn max_memory_spills_without_changing_results() {
+        let tmp = std::env::temp_dir().join("fselect_test_max_memory");
+        let _ = fs::remove_dir_all(&tmp);
+        for dir in ["a", "b", "c"] {
+            fs::create_dir_all(tmp.join(dir)).unwrap();
+            for i in 0..20 {
+                fs::write(tmp.join(dir).join(format!("file{:02}.txt", i)), "x".repeat(i)).unwrap();
+            }
+        }
+
+        let queries = [
+            "name, size from __DIR__ where is_file order by size desc, name",
+            "name, size from __DIR__ where is_file order by size desc, name offset 5",
+            "size, count(*), min(name), avg(size) from __DIR__ where is_file group by size order by size desc",
+            "count(*), sum(size), stddev_pop(size) from __DIR__ where is_file",
+        ];
+
+        let mut config = Config::default();
+        config.max_memory = Some(String::from("1k"));
+        let spilled: Vec<Vec<String>> = queries
+            .iter()
+            .map(|query| run_query_against_dir_with_config(query, &tmp, config.clone()))
+            .collect();
+        let in_memory: Vec<Vec<String>> = queries.iter().map(|query| run_query_against_dir(query, &tmp)).collect();
+        let _ = fs::remove_dir_all(&tmp);
+
+        assert_eq!(spilled[0].len(), 60);
+        assert_eq!(spilled, in_memory);
+    }
+
+    fn write_tar_archive<W: std::io::Write>(writer: W) -> W {
+        let mut builder = tar::Builder::new(writer);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(5);
+        header.set_mode(0o640);
+        header.set_uid(1234);
+        header.set_gid(5678);
+        header.set_mtime(1_600_000_000);
+        header.set_cksum();
+        builder.append_data(&mut header, "docs/hello.txt", &b"hello"[..]).unwrap();
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn tar_archives_are_searched_in_every_compression() {
+        let tmp = std::env::temp_dir().join("fselect_test_tar_archives");
+        let _ = fs::remove_dir_all(&tmp);
+        fs::create_dir_all(&tmp).unwrap();
+
+        let plain = write_tar_archive(Vec::new());
+        fs::write(tmp.join("plain.tar"), &plain).unwrap();
+
+        let gz = write_tar_archive(flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default()));
+        fs::write(tmp.join("gzip.tgz"), gz.finish().unwrap()).unwrap();
+
+        let bz2 = write_tar_archive(bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default()));
+        fs::write(tmp.join("bzip2.tar.bz2"), bz2.finish().unwrap()).unwrap();
+
+        let xz = write_tar_archive(lzma_rust2::XzWriter::new(Vec::new(), lzma_rust2::XzOptions::default()).unwrap());
+        fs::write(tmp.join("xz.tar.xz"), xz.finish().unwrap()).unwrap();
+
+        let zst = write_tar_archive(zstd::stream::write::Encoder::new(Vec::new(), 0).unwrap());
+        fs::write(tmp.join("zstd.tar.zst"), zst.finish().unwrap()).unwrap();
+
+        // Not an archive by its extension, so never opened
+        fs::write(tmp.join("plain.dat"), &plain).unwrap();
+
+        let rows = run_query_against_dir(
+            "name, size, uid, gid, mode from __DIR__ archives where name like '%hello%'",
+            &tmp,
+        );
+        let _ = fs::remove_dir_all(&tmp);
+
+        assert_eq!(rows.len(), 5);
+        for archive in ["plain.tar", "gzip.tgz", "bzip2.tar.bz2", "xz.tar.xz", "zstd.tar.zst"] {
+            let row = rows.iter().find(|row| row.contains(archive)).expect(archive);
+            assert!(row.ends_with("hello.txt\t5\t1234\t5678\t-rw-r-----"), "{}", row);
+        }
+    }
+
+    fn write_zip_archive(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        for (name, content) in files {
+            writer.start_file(*name, zip::write::SimpleFileOptions::default()).unwrap();
+            writer.write_all(content).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn nested_archives_are_searched_up_to_archive_depth() {
+        let tmp = std::env::temp_dir().join("fselect_test_nested_archives");
+        let _ = fs::remove_dir_all(&tmp);
+        fs::create_dir_all(&tmp).unwrap();
+
+        let innermost = write_zip_archive(&[("deepest.txt", b"deepest")]);
+        let inner_jar = write_zip_archive(&[("com/x/Y.class", b"class file"), ("nested.zip", &innermost)]);
+        let mut tarball = tar::Builder::new(flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default()));
+        let mut header = tar::Header::new_gnu();
+        header.set_size(inner_jar.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        tarball.append_data(&mut header, "lib/inner.jar", &inner_jar[..]).unwrap();
+        let tarball = tarball.into_inner().unwrap().finish().unwrap();
+        fs::write(tmp.join("outer.zip"), write_zip_archive(&[("bundle.tgz", &tarball), ("top.txt", b"top")])).unwrap();
+
+        let query = "path, sha1 from __DIR__ __OPTIONS__ where is_file order by path";
+        let one_level = run_query_against_dir(&query.replace("__OPTIONS__", "archives"), &tmp);
+        let three_levels = run_query_against_dir(&query.replace("__OPTIONS__", "archive_depth 3"), &tmp);
+        let four_levels = run_query_against_dir(&query.replace("__OPTIONS__", "archive_depth 4"), &tmp);
+        let _ = fs::remove_dir_all(&tmp);
+
+        let paths = |rows: &[String]| -> Vec<String> {
+            // Entries come after the archive column, the outer archive itself has none
+            rows.iter()
+                .filter(|row| row.starts_with('['))
+                .filter_map(|row| row.split('\t').nth(1).map(String::from))
+                .collect()
+        };
+        assert_eq!(paths(&one_level), vec!["bundle.tgz", "top.txt"]);
+        assert_eq!(
+            paths(&three_levels),
+            vec![
+                "bundle.tgz",
+                "bundle.tgz!/lib/inner.jar",
+                "bundle.tgz!/lib/inner.jar!/com/x/Y.class",
+                "bundle.tgz!/lib/inner.jar!/nested.zip",
+                "top.txt",
+            ]
+        );
+        assert_eq!(paths(&four_levels)[4], "bundle.tgz!/lib/inner.jar!/nested.zip!/deepest.txt");
+
+        // The content of nested entries is read through the outer archives
+        let deepest = four_levels.iter().find(|row| row.contains("deepest.txt")).unwrap();
+        assert!(deepest.ends_with("\t84f20261c152aa43d1217f32567ef7bcef32dcfd"), "{}", deepest);
+    }
+
+    #[test]
+    fn archive_entry_fields() {
+        use zip::write::SimpleFileOptions;
+        use zip::CompressionMethod;
+
+        let tmp = std::env::temp_dir().join("fselect_test_archive_entry_fields");
+        let _ = fs::remove_dir_all(&tmp);
+        fs::create_dir_all(tmp.join("sub")).unwrap();
+
+        let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        writer.start_file("docs/zeros.txt", SimpleFileOptions::default().compression_method(CompressionMethod::Deflated)).unwrap();
+        writer.write_all(&[b'0'; 10000]).unwrap();
+        writer.start_file("stored.txt", SimpleFileOptions::default().compression_method(CompressionMethod::Stored)).unwrap();
+        writer.write_all(b"stored").unwrap();
+        writer
+            .start_file("secret.txt", SimpleFileOptions::default().with_aes_encryption(zip::AesMode::Aes256, "password"))
+            .unwrap();
+        writer.write_all(b"secret").unwrap();
+        writer.start_file("inner.zip", SimpleFileOptions::default()).unwrap();
+        writer.write_all(&write_zip_archive(&[("deep/file.txt", b"deep")])).unwrap();
+        fs::write(tmp.join("sub/bundle.zip"), writer.finish().unwrap().into_inner()).unwrap();
+
+        let entries = run_query_against_dir(
+            "archive_path, entry_path, compressed_size, compression_ratio, compression_method, is_encrypted, crc32 \
+             from __DIR__ archive_depth 2 where is_file order by entry_path",
+            &tmp,
+        );
+        let archives = run_query_against_dir(
+            "name, archive_entry_count, archive_uncompressed_size from __DIR__ where is_file",
+            &tmp,
+        );
+        let _ = fs::remove_dir_all(&tmp);
+
+        // Leave out the archive itself and the archive column
+        let entries: Vec<&str> = entries
+            .iter()
+            .filter(|row| row.starts_with('['))
+            .filter_map(|row| row.split_once('\t').map(|(_, columns)| columns))
+            .collect();
+        assert_eq!(entries.len(), 5);
+        assert!(entries[0].starts_with("sub/bundle.zip!/inner.zip\tdeep/file.txt\t"), "{}", entries[0]);
+        assert!(entries[1].starts_with("sub/bundle.zip\tdocs/zeros.txt\t"), "{}", entries[1]);
+        let zeros: Vec<&str> = entries[1].split('\t').collect();
+        assert!(zeros[3].parse::<f64>().unwrap() > 100.0, "{}", entries[1]);
+        assert_eq!(zeros[4..6], ["deflated", "false"]);
+        assert!(entries[3].starts_with("sub/bundle.zip\tsecret.txt\t"), "{}", entries[3]);
+        assert_eq!(entries[3].split('\t').nth(5), Some("true"));
+        // The stored checksum is the one computed from the content
+        assert_eq!(entries[4], "sub/bundle.zip\tstored.txt\t6\t1\tstored\tfalse\t5643f90b");
+
+        let archive: Vec<&str> = archives[0].split('\t').collect();
+        assert_eq!(archive[..2], ["bundle.zip", "4"]);
+        assert!(archive[2].parse::<u64>().unwrap() > 10012, "{}", archive[2]);
+    }
+
+    #[test]
+    fn content_of_archive_entries_is_read() {
+        let tmp = std::env::temp_dir().join("fselect_test_archive_entry_content");
+        let _ = fs::remove_dir_all(&tmp);
+        fs::create_dir_all(&tmp).unwrap();
+
+        let zip = write_zip_archive(&[
+            ("logs/app.log", b"started\nERROR disk full\nstopped\n"),
+            ("logs/image.png", b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"),
+            ("logs/quiet.log", b"nothing to see\n"),
+        ]);
+        fs::write(tmp.join("logs.zip"), zip).unwrap();
+
+        let rows = run_query_against_dir(
+            "name, line_count, word_count, mime, is_text, contains(ERROR) from __DIR__ archives \
+             where is_file order by name",
+            &tmp,
+        );
+        let matches = run_query_against_dir(
+            "name from __DIR__ archives where contains('disk full') and line_count = 3",
+            &tmp,
+        );
+        let _ = fs::remove_dir_all(&tmp);
+
+        // Leave out the archive itself and the archive column
+        let rows: Vec<&str> = rows
+            .iter()
+            .filter(|row| row.starts_with('['))
+            .filter_map(|row| row.split_once('\t').map(|(_, columns)| columns))
+            .collect();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0], "app.log\t3\t5\ttext/plain\ttrue\ttrue");
+        let image: Vec<&str> = rows[1].split('\t').collect();
+        assert_eq!(image[3..], ["image/png", "false", "false"]);
+        assert_eq!(rows[2], "quiet.log\t1\t3\ttext/plain\ttrue\tfalse");
+
+        assert_eq!(matches.len(), 1);
+        assert!(matches[0].ends_with("\tapp.log"), "{}", matches[0]);
+    }
+
+    #[test]
+    fn content_of_tar_entries_is_read_as_they_are_listed() {
+        let tmp = std::env::temp_dir().join("fselect_test_tar_entry_content");
+        let _ = fs::remove_dir_all(&tmp);
+        fs::create_dir_all(&tmp).unwrap();
+
+        let nested = write_zip_archive(&[("inner.log", b"ERROR in nested\n")]);
+        let mut tarball = tar::Builder::new(flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default()));
+        for (name, content) in [("app.log", &b"started\nERROR disk full\n"[..]), ("nested.zip", &nested), ("quiet.log", b"ok\n")] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            tarball.append_data(&mut header, name, content).unwrap();
+        }
+        fs::write(tmp.join("logs.tgz"), tarball.into_inner().unwrap().finish().unwrap()).unwrap();
+
+        let rows = run_query_against_dir(
+            "path, line_count, sha1, hash(md5), contains(ERROR) from __DIR__ archive_depth 2 \
+             where name like '%.log' order by path",
+            &tmp,
+        );
+        let _ = fs::remove_dir_all(&tmp);
+
+        let rows: Vec<&str> = rows.iter().filter_map(|row| row.split_once('\t').map(|(_, columns)| columns)).collect();
+        assert_eq!(
+            rows,
+            vec![
+                "app.log\t2\tff8ef1659bce998dc2662e1f784bebf7c3f452f4\t674d8cd913bb3f115e93cc8a303c6497\ttrue",
+                "nested.zip!/inner.log\t1\t75408b785822ca41b360616faabeece91e61039b\tcb73194faf55f2780ac41025b7997d7d\ttrue",
+                "quiet.log\t1\t92a949fd41844e1bb8c6812cdea102708fde23a4\teff5bc1ef8ec9d03e640fc4370f5eacd\tfalse",
+            ]
+        );
+    }
+
+    #[test]
+    fn compressed_files_are_read_with_decompress() {
+        let tmp = std::env::temp_dir().join("fselect_test_decompress");
+        let _ = fs::remove_dir_all(&tmp);
+        fs::create_dir_all(&tmp).unwrap();
+
+        let log = "started\nERROR disk full\nstopped\n";
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(log.as_bytes()).unwrap();
+        fs::write(tmp.join("app.log.1.gz"), encoder.finish().unwrap()).unwrap();
+        fs::write(tmp.join("app.log.2.zst"), zstd::encode_all(log.as_bytes(), 0).unwrap()).unwrap();
+        fs::write(tmp.join("app.log"), "plain\n").unwrap();
+
+        let decompressed = run_query_against_dir(
+            "name, line_count, encoding, contains('disk full'), uncompressed_size from __DIR__ decompress order by name",
+            &tmp,
+        );
+        let raw = run_query_against_dir(
+            "name, contains('disk full'), uncompressed_size from __DIR__ where name like '%.gz'",
+            &tmp,
+        );
+        let _ = fs::remove_dir_all(&tmp);
+
+        assert_eq!(
+            decompressed,
+            vec![
+                "app.log\t1\tASCII\tfalse\t6",
+                "app.log.1.gz\t3\tASCII\ttrue\t32",
+                "app.log.2.zst\t3\tASCII\ttrue\t32",
+            ]
+        );
+        // Without the option only the size sees through the compression
+        assert_eq!(raw, vec!["app.log.1.gz\tfalse\t32"]);
+    }
+
+    #[test]
+    fn archive_output_rows_carry_the_files() {
+        let tmp = std::env::temp_dir().join("fselect_test_archive_output");
+        let _ = fs::remove_dir_all(&tmp);
+        fs::create_dir_all(tmp.join("logs")).unwrap();
+        fs::write(tmp.join("logs/app.log"), "log line").unwrap();
+        fs::write(tmp.join("bundle.zip"), write_zip_archive(&[("old.log", b"old")])).unwrap();
+
+        let rows = run_query_against_dir(
+            "name, size from __DIR__ archives where name like '%.log' order by name into zip 'out.zip'",
+            &tmp,
+        );
+        let _ = fs::remove_dir_all(&tmp);
+
+        // Entries of archives are left out
+        let source = tmp.join("logs").join("app.log");
+        assert_eq!(rows, vec![format!("{}\0logs/app.log\0app.log\t8", source.to_string_lossy())]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn subquery_hashes_go_to_the_shared_hash_cache() {
+        use crate::lexer::Lexer;
+        use crate::parser::Parser;
+        use crate::util::hash_cache::HashCache;
+
+        let tmp = std::env::temp_dir().join("fselect_test_shared_hash_cache");
+        let _ = fs::remove_dir_all(&tmp);
+        fs::create_dir_all(tmp.join("files")).unwrap();
+        fs::write(tmp.join("files").join("a.txt"), "a").unwrap();
+        fs::write(tmp.join("files").join("b.txt"), "b").unwrap();
+        let cache_file = tmp.join("hash_cache.tsv");
+
+        let query_string = "select name from __DIR__ where sha1 in (select sha1 from __DIR__ where name = 'a.txt')"
+            .replace("__DIR__", &tmp.join("files").to_string_lossy());
+        let mut lexer = Lexer::new(vec![query_string]);
+        let mut parser = Parser::new(&mut lexer);
+        let parsed = parser.parse(false).expect("parse failed");
+        let config = Config::default();
+        let default_config = Config::default();
+
+        let mut searcher = Searcher::new(&parsed, &config, &default_config, false);
+        searcher.silent_mode = true;
+        searcher.hash_cache = Rc::new(RefCell::new(HashCache::with_file(Some(cache_file.clone()))));
+        searcher.list_search_results().expect("search failed");
+        let rows: Vec<String> = searcher.output_buffer.sorted_values().unwrap().map(|s| s.unwrap().trim_end().to_string()).collect();
+        assert_eq!(rows, vec!["a.txt"]);
+
+        // The subquery's searcher is gone, but nothing is saved before the outer one is
+        assert!(!cache_file.exists());
+        drop(searcher);
+        let saved = fs::read_to_string(&cache_file).unwrap();
+        assert_eq!(saved.lines().count(), 2);
+
+        let _ = fs::remove_dir_all(&tmp);
+    }
+
+    #[test]
+    fn json_lines_output_keeps_value_types() {
+        let tmp = std::env::temp_dir().join("fselect_test_json_lines_output");
+        let _ = fs::remove_dir_all(&tmp);
+        fs::create_dir_all(&tmp).unwrap();
+        fs::write(tmp.join("a.txt"), "abc").unwrap();
+        fs::write(tmp.join("b.txt"), "abcdef").unwrap();
+        fs::write(tmp.join("c.log"), "").unwrap();
+
+        let rows = run_query_against_dir(
+            "name, size, is_dir, width from __DIR__ where name like '%.txt' order by name into jsonl",
+            &tmp,
+        );
+        let grouped = run_query_against_dir(
+            "ext, count(*) as n, sum(size) from __DIR__ group by ext order by ext into jsonl",
+            &tmp,
+        );
+        let _ = fs::remove_dir_all(&tmp);
+
+        assert_eq!(
+            rows,
+            vec![
+                r#"{"Name":"a.txt","Size":3,"IsDir":false,"Width":null}"#,
+                r#"{"Name":"b.txt","Size":6,"IsDir":false,"Width":null}"#,
+            ]
+        );
+        assert_eq!(
+            grouped,
+            vec![
+                r#"{"extension":"log","n":1,"sum(size)":0}"#,
+                r#"{"extension":"txt","n":2,"sum(size)":9}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn output_archive_under_the_searched_root_is_left_out() {
+        let tmp = std::env::temp_dir().join("fselect_test_archive_output_in_root");
+        let _ = fs::remove_dir_all(&tmp);
+        fs::create_dir_all(tmp.join("logs")).unwrap();
+        fs::write(tmp.join("logs/app.log"), "log line").unwrap();
+        // As left by the first file added while the search goes on
+        fs::write(tmp.join("out.zip"), write_zip_archive(&[("logs/app.log", b"log line")])).unwrap();
+
+        let rows = run_query_against_dir(
+            "name from __DIR__ archives order by name into zip '__DIR__/logs/../out.zip'",
+            &tmp,
+        );
+        let _ = fs::remove_dir_all(&tmp);
+
+        let names: Vec<&str> = rows.iter().filter_map(|row| row.rsplit('\0').next()).collect();
+        assert_eq!(names, vec!["app.log", "logs"]);
+    }
+
+    #[cfg(feature = "sevenz")]
+    #[test]
+    fn sevenz_archives_are_listed_and_read() {
+        let tmp = std::env::temp_dir().join("fselect_test_7z_archives");
+        let _ = fs::remove_dir_all(&tmp);
+        fs::create_dir_all(tmp.join("src/docs")).unwrap();
+        fs::write(tmp.join("src/docs/hello.txt"), "hello").unwrap();
+        fs::write(tmp.join("src/other.txt"), "other file").unwrap();
+        sevenz_rust::compress_to_path(tmp.join("src"), tmp.join("packed.7z")).unwrap();
+        fs::remove_dir_all(tmp.join("src")).unwrap();
+
+        let rows = run_query_against_dir(
+            "name, size, sha1 from __DIR__ archives where is_file and name like '%.txt' order by name",
+            &tmp,
+        );
+        let _ = fs::remove_dir_all(&tmp);
+
+        assert_eq!(rows.len(), 2);
+        assert!(rows[0].ends_with("hello.txt\t5\taaf4c61ddcc5e8a2dabede0f3b482cd9aea9434d"), "{}", rows[0]);
+        assert!(rows[1].ends_with("other.txt\t10\tb0f86a9851cb0f7a10f26c044ebff54763718015"), "{}", rows[1]);
+    }
+
+    #[cfg(feature = "sevenz")]
+    #[test]
+    fn archives_nested_in_7z_archives_are_searched() {
+        let tmp = std::env::temp_dir().join("fselect_test_7z_nested_archives");
+        let _ = fs::remove_dir_all(&tmp);
+        fs::create_dir_all(tmp.join("src")).unwrap();
+        fs::write(tmp.join("src/first.zip"), write_zip_archive(&[("one.txt", b"one")])).unwrap();
+        fs::write(tmp.join("src/plain.txt"), "plain").unwrap();
+        fs::write(tmp.join("src/second.zip"), write_zip_archive(&[("two.txt", b"two")])).unwrap();
+        sevenz_rust::compress_to_path(tmp.join("src"), tmp.join("packed.7z")).unwrap();
+        fs::remove_dir_all(tmp.join("src")).unwrap();
+
+        let rows = run_query_against_dir("path from __DIR__ archive_depth 2 where name like '%.txt' order by path", &tmp);
+        let _ = fs::remove_dir_all(&tmp);
+
+        let paths: Vec<&str> = rows.iter().filter_map(|row| row.split('\t').nth(1)).collect();
+        assert_eq!(paths, vec!["first.zip!/one.txt", "plain.txt", "second.zip!/two.txt"]);
+    }
+
+    #[cfg(feature = "sevenz")]
+    #[test]
+    fn content_of_7z_entries_is_read_as_they_are_decompressed() {
+        let tmp = std::env::temp_dir().join("fselect_test_7z_entry_content");
+        let _ = fs::remove_dir_all(&tmp);
+        fs::create_dir_all(tmp.join("src/logs")).unwrap();
+        fs::write(tmp.join("src/logs/app.log"), "started\nERROR disk full\n").unwrap();
+        fs::write(tmp.join("src/logs/quiet.log"), "ok\n").unwrap();
+        fs::write(tmp.join("src/nested.zip"), write_zip_archive(&[("inner.log", b"ERROR in nested\n")])).unwrap();
+        sevenz_rust::compress_to_path(tmp.join("src"), tmp.join("packed.7z")).unwrap();
+        fs::remove_dir_all(tmp.join("src")).unwrap();
+
+        let rows = run_query_against_dir(
+            "path, line_count, sha1, contains(ERROR) from __DIR__ archive_depth 2 \
+             where name like '%.log' order by path",
+            &tmp,
+        );
+        let _ = fs::remove_dir_all(&tmp);
+
+        let rows: Vec<&str> = rows.iter().filter_map(|row| row.split_once('\t').map(|(_, columns)| columns)).collect();
+        assert_eq!(
+            rows,
+            vec![
+                "logs/app.log\t2\tff8ef1659bce998dc2662e1f784bebf7c3f452f4\ttrue",
+                "logs/quiet.log\t1\t92a949fd41844e1bb8c6812cdea102708fde23a4\tfalse",
+                "nested.zip!/inner.log\t1\t75408b785822ca41b360616faabeece91e61039b\ttrue",
+            ]
+        );
+    }
 }
@@ -0,0 +1,275 @@
+//! Checksum files backing the `checksum_status` field: sidecars such as
+//! `file.iso.sha256` and manifests such as `SHA256SUMS` in the directory of
+//! the file or any of its parents, in the formats written by coreutils.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::util::hashes::HashAlgorithm;
+
+/// Digest a checksum file lists for a file
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExpectedChecksum {
+    pub algorithm: HashAlgorithm,
+    /// Lowercase hex digest
+    pub hash: String,
+}
+
+/// Entries of a single checksum file, by path relative to its directory
+type Manifest = HashMap<String, ExpectedChecksum>;
+
+/// Caches the manifests found in every directory looked at during a search,
+/// so each of them is read only once.
+#[derive(Default)]
+pub struct ChecksumFiles {
+    manifests: HashMap<PathBuf, Vec<Manifest>>,
+}
+
+impl ChecksumFiles {
+    pub fn new() -> ChecksumFiles {
+        ChecksumFiles { manifests: HashMap::new() }
+    }
+
+    /// Finds the checksum of the file: a sidecar next to it wins over
+    /// manifests, and manifests closer to the file win over those further up.
+    pub fn find_checksum(&mut self, path: &Path) -> Option<ExpectedChecksum> {
+        let path = std::path::absolute(path).ok()?;
+        let file_name = path.file_name()?.to_string_lossy().to_string();
+
+        if let Some(checksum) = find_sidecar_checksum(&path, &file_name) {
+            return Some(checksum);
+        }
+
+        let mut relative_path = file_name;
+        let mut dir = path.parent();
+        while let Some(current_dir) = dir {
+            let manifests = self
+                .manifests
+                .entry(current_dir.to_path_buf())
+                .or_insert_with(|| read_manifests(current_dir));
+
+            if let Some(checksum) = manifests.iter().find_map(|manifest| manifest.get(&relative_path)) {
+                return Some(checksum.clone());
+            }
+
+            if let Some(dir_name) = current_dir.file_name() {
+                relative_path = format!("{}/{}", dir_name.to_string_lossy(), relative_path);
+            }
+            dir = current_dir.parent();
+        }
+
+        None
+    }
+}
+
+/// Algorithm named by a manifest such as `SHA256SUMS` or `md5sums.txt`.
+fn get_checksum_file_algorithm(name: &str) -> Option<HashAlgorithm> {
+    let name = name.to_lowercase();
+    let name = name.strip_suffix(".txt").unwrap_or(&name);
+    let name = name
+        .strip_suffix("sums")
+        .or_else(|| name.strip_suffix("sum"))
+        .unwrap_or(name);
+
+    HashAlgorithm::from_name(name)
+}
+
+fn find_sidecar_checksum(path: &Path, file_name: &str) -> Option<ExpectedChecksum> {
+    const SIDECAR_EXTENSIONS: [(&str, HashAlgorithm); 10] = [
+        ("sha256", HashAlgorithm::Sha256),
+        ("sha512", HashAlgorithm::Sha512),
+        ("sha1", HashAlgorithm::Sha1),
+        ("md5", HashAlgorithm::Md5),
+        ("sha256sum", HashAlgorithm::Sha256),
+        ("sha512sum", HashAlgorithm::Sha512),
+        ("sha1sum", HashAlgorithm::Sha1),
+        ("md5sum", HashAlgorithm::Md5),
+        ("blake3", HashAlgorithm::Blake3),
+        ("b3sum", HashAlgorithm::Blake3),
+    ];
+
+    for (ext, algorithm) in SIDECAR_EXTENSIONS {
+        let mut sidecar = path.as_os_str().to_os_string();
+        sidecar.push(".");
+        sidecar.push(ext);
+
+        let content = match fs::read_to_string(&sidecar) {
+            Ok(content) => content,
+            Err(_) => continue,
+        };
+
+        // Usually the sidecar names the file, but it may be just the digest,
+        // or name the file as it was called when the digest was made
+        let entries: Vec<(String, ExpectedChecksum)> = content
+            .lines()
+            .filter_map(|line| parse_checksum_line(line, algorithm))
+            .collect();
+        let matching = entries
+            .iter()
+            .find(|(name, _)| name.is_empty() || Path::new(name).file_name().is_some_and(|n| n == file_name));
+
+        match (matching, entries.len()) {
+            (Some((_, checksum)), _) => return Some(checksum.clone()),
+            (None, 1) => return Some(entries[0].1.clone()),
+            _ => continue,
+        }
+    }
+
+    None
+}
+
+fn read_manifests(dir: &Path) -> Vec<Manifest> {
+    let mut files: Vec<(String, HashAlgorithm)> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .flatten()
+            .filter_map(|entry| {
+                let name = entry.file_name().to_string_lossy().to_string();
+                let lowercase_name = name.to_lowercase();
+                if !lowercase_name.ends_with("sums") && !lowercase_name.ends_with("sums.txt") {
+                    return None;
+                }
+                get_checksum_file_algorithm(&name).map(|algorithm| (name, algorithm))
+            })
+            .collect(),
+        Err(_) => return vec![],
+    };
+    files.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    files
+        .into_iter()
+        .filter_map(|(name, algorithm)| fs::read_to_string(dir.join(name)).ok().map(|content| (content, algorithm)))
+        .map(|(content, algorithm)| {
+            content
+                .lines()
+                .filter_map(|line| parse_checksum_line(line, algorithm))
+                .filter(|(name, _)| !name.is_empty())
+                .map(|(name, checksum)| (name.strip_prefix("./").map(str::to_string).unwrap_or(name), checksum))
+                .collect()
+        })
+        .collect()
+}
+
+/// Parses a line of `sha256sum` output: `<digest>  <name>`, with `*` instead
+/// of the second space in binary mode, or the BSD style `SHA256 (<name>) = <digest>`
+/// produced by `--tag`. A line with just a digest yields an empty name.
+/// Names with a backslash or a newline are escaped, and the line starts with a backslash.
+fn parse_checksum_line(line: &str, algorithm: HashAlgorithm) -> Option<(String, ExpectedChecksum)> {
+    let line = line.trim_end_matches('\r');
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+
+    let (escaped, line) = match line.strip_prefix('\\') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+
+    let (algorithm, hash, name) = match line.split_once(" (") {
+        Some((tag, rest)) if !is_hex(tag) => {
+            let (name, hash) = rest.rsplit_once(") = ")?;
+            (HashAlgorithm::from_name(&tag.replace('-', "_"))?, hash, name)
+        }
+        _ => match line.split_once(' ') {
+            Some((hash, name)) => (algorithm, hash, name.strip_prefix([' ', '*'])?),
+            None => (algorithm, line.trim(), ""),
+        },
+    };
+
+    if !is_hex(hash) {
+        return None;
+    }
+
+    let name = match escaped {
+        true => unescape_name(name),
+        false => name.to_string(),
+    };
+
+    Some((name, ExpectedChecksum { algorithm, hash: hash.to_lowercase() }))
+}
+
+fn is_hex(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn unescape_name(name: &str) -> String {
+    let mut result = String::with_capacity(name.len());
+    let mut chars = name.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('r') => result.push('\r'),
+            Some(other) => result.push(other),
+            None => result.push('\\'),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_checksum_line() {
+        let sha256 = HashAlgorithm::Sha256;
+        let checksum = |algorithm, hash: &str| ExpectedChecksum { algorithm, hash: hash.to_string() };
+
+        assert_eq!(
+            parse_checksum_line("ABCD  file name.iso", sha256),
+            Some((String::from("file name.iso"), checksum(sha256, "abcd")))
+        );
+        assert_eq!(
+            parse_checksum_line("abcd *bin/tool", sha256),
+            Some((String::from("bin/tool"), checksum(sha256, "abcd")))
+        );
+        assert_eq!(
+            parse_checksum_line("MD5 (a (1).txt) = 0123", sha256),
+            Some((String::from("a (1).txt"), checksum(HashAlgorithm::Md5, "0123")))
+        );
+        assert_eq!(
+            parse_checksum_line("\\abcd  new\\nline\\\\", sha256),
+            Some((String::from("new\nline\\"), checksum(sha256, "abcd")))
+        );
+        assert_eq!(parse_checksum_line("abcd", sha256), Some((String::new(), checksum(sha256, "abcd"))));
+        assert_eq!(parse_checksum_line("# comment", sha256), None);
+        assert_eq!(parse_checksum_line("not a checksum", sha256), None);
+    }
+
+    #[test]
+    fn test_get_checksum_file_algorithm() {
+        assert_eq!(get_checksum_file_algorithm("SHA256SUMS"), Some(HashAlgorithm::Sha256));
+        assert_eq!(get_checksum_file_algorithm("md5sums.txt"), Some(HashAlgorithm::Md5));
+        assert_eq!(get_checksum_file_algorithm("sha1sum"), Some(HashAlgorithm::Sha1));
+        assert_eq!(get_checksum_file_algorithm("B2SUMS"), None);
+    }
+
+    #[test]
+    fn test_find_checksum() {
+        let tmp = std::env::temp_dir().join("fselect_test_find_checksum");
+        let _ = fs::remove_dir_all(&tmp);
+        fs::create_dir_all(tmp.join("release/sub")).unwrap();
+        fs::write(tmp.join("SHA256SUMS"), "aaaa  release/sub/deep.bin\nbbbb  release/top.bin\n").unwrap();
+        fs::write(tmp.join("release/MD5SUMS"), "cccc  ./top.bin\n").unwrap();
+        fs::write(tmp.join("release/sub/deep.bin.sha1"), "dddd  deep-renamed.bin\n").unwrap();
+
+        let mut checksum_files = ChecksumFiles::new();
+        let found = |files: &mut ChecksumFiles, path: &str| {
+            files.find_checksum(&tmp.join(path)).map(|checksum| (checksum.algorithm, checksum.hash))
+        };
+
+        assert_eq!(found(&mut checksum_files, "release/top.bin"), Some((HashAlgorithm::Md5, String::from("cccc"))));
+        assert_eq!(found(&mut checksum_files, "release/sub/deep.bin"), Some((HashAlgorithm::Sha1, String::from("dddd"))));
+        assert_eq!(found(&mut checksum_files, "release/other.bin"), None);
+
+        fs::remove_file(tmp.join("release/sub/deep.bin.sha1")).unwrap();
+        assert_eq!(found(&mut checksum_files, "release/sub/deep.bin"), Some((HashAlgorithm::Sha256, String::from("aaaa"))));
+
+        let _ = fs::remove_dir_all(&tmp);
+    }
+}
@@ -1,7 +1,7 @@
 use std::sync::{LazyLock, Mutex};
 use std::time::{SystemTime, UNIX_EPOCH};
 
-use chrono::{DateTime, Duration, Local, NaiveDate, NaiveDateTime, NaiveTime, Timelike, Utc};
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, NaiveDateTime, NaiveTime, Timelike, Utc};
 use chrono_english::{parse_date_string, Dialect};
 use regex::Regex;
 
@@ -170,6 +170,19 @@ pub fn to_local_datetime(dt: &zip::DateTime) -> NaiveDateTime {
     NaiveDateTime::new(date, time)
 }
 
+/// Zip keeps the local time, from 1980 to 2107, with a precision of two seconds.
+pub fn to_zip_datetime(dt: &NaiveDateTime) -> Option<zip::DateTime> {
+    zip::DateTime::from_date_and_time(
+        u16::try_from(dt.year()).ok()?,
+        dt.month() as u8,
+        dt.day() as u8,
+        dt.hour() as u8,
+        dt.minute() as u8,
+        dt.second() as u8,
+    )
+    .ok()
+}
+
 pub fn format_datetime(dt: &NaiveDateTime) -> String {
     format!("{}", dt.format("%Y-%m-%d %H:%M:%S"))
 }
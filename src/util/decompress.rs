@@ -0,0 +1,87 @@
+//! Decoders for content compressed as a whole with gzip, bzip2, xz or zstd,
+//! like rotated logs or compressed tar archives. The compression is told by
+//! the magic bytes rather than the extension.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compression {
+    Gzip,
+    Bzip2,
+    Xz,
+    Zstd,
+}
+
+impl Compression {
+    pub fn detect(magic: &[u8]) -> Option<Compression> {
+        if magic.starts_with(&[0x1f, 0x8b]) {
+            Some(Compression::Gzip)
+        } else if magic.starts_with(b"BZh") {
+            Some(Compression::Bzip2)
+        } else if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(Compression::Xz)
+        } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Compression::Zstd)
+        } else {
+            None
+        }
+    }
+}
+
+/// Decompresses the content on the fly, or passes it through as is if it's not compressed.
+pub fn decompress<'a>(mut reader: impl BufRead + 'a) -> io::Result<Box<dyn Read + 'a>> {
+    let decompressed: Box<dyn Read + 'a> = match Compression::detect(reader.fill_buf()?) {
+        Some(Compression::Gzip) => Box::new(flate2::bufread::MultiGzDecoder::new(reader)),
+        Some(Compression::Bzip2) => Box::new(bzip2::bufread::MultiBzDecoder::new(reader)),
+        Some(Compression::Xz) => Box::new(lzma_rust2::XzReader::new(reader, true)),
+        Some(Compression::Zstd) => Box::new(zstd::stream::read::Decoder::with_buffer(reader)?),
+        None => Box::new(reader),
+    };
+
+    Ok(decompressed)
+}
+
+/// Size of the file's content once decompressed. Compressed files are read
+/// through, since the sizes some formats record are optional or truncated.
+pub fn get_uncompressed_size(path: &Path) -> Option<u64> {
+    let mut reader = BufReader::new(File::open(path).ok()?);
+    if Compression::detect(reader.fill_buf().ok()?).is_none() {
+        return reader.get_ref().metadata().ok().map(|metadata| metadata.len());
+    }
+
+    io::copy(&mut decompress(reader).ok()?, &mut io::sink()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use super::*;
+
+    #[test]
+    fn test_detect_compression() {
+        assert_eq!(Compression::detect(&[0x1f, 0x8b, 0x08]), Some(Compression::Gzip));
+        assert_eq!(Compression::detect(b"BZh91AY"), Some(Compression::Bzip2));
+        assert_eq!(Compression::detect(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]), Some(Compression::Xz));
+        assert_eq!(Compression::detect(&[0x28, 0xb5, 0x2f, 0xfd]), Some(Compression::Zstd));
+        assert_eq!(Compression::detect(b"plain text"), None);
+        assert_eq!(Compression::detect(b""), None);
+    }
+
+    #[test]
+    fn test_decompress() {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(b"line 1\nline 2\n").unwrap();
+        let gzipped = encoder.finish().unwrap();
+
+        let mut content = String::new();
+        decompress(Cursor::new(gzipped)).unwrap().read_to_string(&mut content).unwrap();
+        assert_eq!(content, "line 1\nline 2\n");
+
+        let mut content = String::new();
+        decompress(Cursor::new(b"not compressed")).unwrap().read_to_string(&mut content).unwrap();
+        assert_eq!(content, "not compressed");
+    }
+}
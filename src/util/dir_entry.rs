@@ -0,0 +1,310 @@
+//! Entry of a directory being examined. Entries come either from reading a
+//! directory, or from a path given directly, like the ones of a list of files,
+//! which is then examined without reading its whole parent directory.
+
+use std::ffi::OsString;
+use std::fs::{self, Metadata};
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub struct DirEntry {
+    inner: Inner,
+}
+
+enum Inner {
+    Read(fs::DirEntry),
+    Path(PathBuf),
+    /// Read from an open directory descriptor, with the type if the file system reports it
+    #[cfg(target_os = "linux")]
+    Listed(PathBuf, Option<FileType>),
+}
+
+impl DirEntry {
+    /// Entry of the file at the path, which is not resolved if it's a symlink.
+    pub fn from_path(path: PathBuf) -> DirEntry {
+        DirEntry { inner: Inner::Path(path) }
+    }
+
+    pub fn path(&self) -> PathBuf {
+        match &self.inner {
+            Inner::Read(entry) => entry.path(),
+            Inner::Path(path) => path.clone(),
+            #[cfg(target_os = "linux")]
+            Inner::Listed(path, _) => path.clone(),
+        }
+    }
+
+    pub fn file_name(&self) -> OsString {
+        match &self.inner {
+            Inner::Read(entry) => entry.file_name(),
+            Inner::Path(path) => get_file_name(path),
+            #[cfg(target_os = "linux")]
+            Inner::Listed(path, _) => get_file_name(path),
+        }
+    }
+
+    /// Metadata of the entry itself, not of the file a symlink points to.
+    pub fn metadata(&self) -> io::Result<Metadata> {
+        match &self.inner {
+            Inner::Read(entry) => entry.metadata(),
+            Inner::Path(path) => fs::symlink_metadata(path),
+            #[cfg(target_os = "linux")]
+            Inner::Listed(path, _) => fs::symlink_metadata(path),
+        }
+    }
+
+    pub fn file_type(&self) -> io::Result<FileType> {
+        match &self.inner {
+            Inner::Read(entry) => entry.file_type().map(FileType::from),
+            #[cfg(target_os = "linux")]
+            Inner::Listed(_, Some(file_type)) => Ok(*file_type),
+            _ => self.metadata().map(|metadata| FileType::from(metadata.file_type())),
+        }
+    }
+}
+
+impl From<fs::DirEntry> for DirEntry {
+    fn from(entry: fs::DirEntry) -> Self {
+        DirEntry { inner: Inner::Read(entry) }
+    }
+}
+
+fn get_file_name(path: &Path) -> OsString {
+    path.file_name()
+        .map(|name| name.to_os_string())
+        .unwrap_or_else(|| path.as_os_str().to_os_string())
+}
+
+/// Type of the entry as told by the directory listing, symlinks are not followed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FileType {
+    kind: FileKind,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FileKind {
+    Dir,
+    File,
+    Symlink,
+    Other,
+}
+
+impl FileType {
+    pub fn is_dir(&self) -> bool {
+        self.kind == FileKind::Dir
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.kind == FileKind::File
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.kind == FileKind::Symlink
+    }
+}
+
+impl From<fs::FileType> for FileType {
+    fn from(file_type: fs::FileType) -> Self {
+        let kind = if file_type.is_dir() {
+            FileKind::Dir
+        } else if file_type.is_file() {
+            FileKind::File
+        } else if file_type.is_symlink() {
+            FileKind::Symlink
+        } else {
+            FileKind::Other
+        };
+
+        FileType { kind }
+    }
+}
+
+/// Entries of a directory being read.
+pub enum ReadDir {
+    Std(fs::ReadDir),
+    #[cfg(target_os = "linux")]
+    Fd(fd::DirReader),
+}
+
+/// Reads the entries of the directory. With `keep_fd` the directory is read
+/// from a descriptor that stays open while it's being read, so that its
+/// entries can be stat'ed relative to it (see `ReadDir::as_raw_fd`).
+pub fn read_dir(path: &Path, keep_fd: bool) -> io::Result<ReadDir> {
+    #[cfg(target_os = "linux")]
+    if keep_fd {
+        return fd::DirReader::open(path).map(ReadDir::Fd);
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    let _ = keep_fd;
+
+    fs::read_dir(path).map(ReadDir::Std)
+}
+
+impl ReadDir {
+    /// Descriptor of the directory, valid while it's being read.
+    #[cfg(target_os = "linux")]
+    pub fn as_raw_fd(&self) -> Option<std::os::unix::io::RawFd> {
+        match self {
+            ReadDir::Std(_) => None,
+            ReadDir::Fd(reader) => Some(reader.as_raw_fd()),
+        }
+    }
+}
+
+impl Iterator for ReadDir {
+    type Item = io::Result<DirEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            ReadDir::Std(entries) => entries.next().map(|entry| entry.map(DirEntry::from)),
+            #[cfg(target_os = "linux")]
+            ReadDir::Fd(reader) => reader.next(),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod fd {
+    use std::ffi::{CStr, CString, OsStr};
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::io::RawFd;
+    use std::path::{Path, PathBuf};
+
+    #[cfg(target_env = "gnu")]
+    use libc::readdir64 as readdir;
+    #[cfg(not(target_env = "gnu"))]
+    use libc::readdir;
+
+    use super::{DirEntry, FileKind, FileType, Inner};
+
+    /// Directory opened once, both to list its entries and to stat them.
+    pub struct DirReader {
+        dir: *mut libc::DIR,
+        path: PathBuf,
+        done: bool,
+    }
+
+    impl DirReader {
+        pub fn open(path: &Path) -> io::Result<DirReader> {
+            let c_path = CString::new(path.as_os_str().as_bytes())?;
+            let fd = unsafe { libc::open(c_path.as_ptr(), libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC) };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let dir = unsafe { libc::fdopendir(fd) };
+            if dir.is_null() {
+                let err = io::Error::last_os_error();
+                unsafe { libc::close(fd) };
+                return Err(err);
+            }
+
+            Ok(DirReader { dir, path: path.to_path_buf(), done: false })
+        }
+
+        pub fn as_raw_fd(&self) -> RawFd {
+            unsafe { libc::dirfd(self.dir) }
+        }
+    }
+
+    impl Iterator for DirReader {
+        type Item = io::Result<DirEntry>;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            while !self.done {
+                // `readdir` tells the end of the directory from an error only by errno
+                unsafe { *libc::__errno_location() = 0 };
+                let entry = unsafe { readdir(self.dir) };
+                if entry.is_null() {
+                    self.done = true;
+                    let err = io::Error::last_os_error();
+                    return match err.raw_os_error() {
+                        Some(0) | None => None,
+                        _ => Some(Err(err)),
+                    };
+                }
+
+                let (name, d_type) = unsafe { (CStr::from_ptr((*entry).d_name.as_ptr()), (*entry).d_type) };
+                let name = name.to_bytes();
+                if name == b"." || name == b".." {
+                    continue;
+                }
+
+                let kind = match d_type {
+                    libc::DT_DIR => Some(FileKind::Dir),
+                    libc::DT_REG => Some(FileKind::File),
+                    libc::DT_LNK => Some(FileKind::Symlink),
+                    libc::DT_UNKNOWN => None,
+                    _ => Some(FileKind::Other),
+                };
+                let path = self.path.join(OsStr::from_bytes(name));
+                let inner = Inner::Listed(path, kind.map(|kind| FileType { kind }));
+                return Some(Ok(DirEntry { inner }));
+            }
+
+            None
+        }
+    }
+
+    impl Drop for DirReader {
+        fn drop(&mut self) {
+            unsafe { libc::closedir(self.dir) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entry_from_path() {
+        let dir = std::env::temp_dir().join("fselect_test_dir_entry_from_path");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.txt"), "abc").unwrap();
+
+        let read = DirEntry::from(fs::read_dir(&dir).unwrap().next().unwrap().unwrap());
+        let direct = DirEntry::from_path(dir.join("a.txt"));
+        let missing = DirEntry::from_path(dir.join("missing.txt"));
+
+        assert_eq!(direct.path(), read.path());
+        assert_eq!(direct.file_name(), read.file_name());
+        assert_eq!(direct.metadata().unwrap().len(), 3);
+        assert!(direct.file_type().unwrap().is_file());
+        assert!(missing.metadata().is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_read_dir() {
+        let dir = std::env::temp_dir().join("fselect_test_dir_entry_read_dir");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("a.txt"), "abc").unwrap();
+
+        for keep_fd in [false, true] {
+            let entries = read_dir(&dir, keep_fd).unwrap();
+            #[cfg(target_os = "linux")]
+            assert_eq!(entries.as_raw_fd().is_some(), keep_fd);
+
+            let mut entries: Vec<(OsString, bool, bool)> = entries
+                .map(|entry| entry.unwrap())
+                .map(|entry| {
+                    let file_type = entry.file_type().unwrap();
+                    (entry.file_name(), file_type.is_dir(), file_type.is_file())
+                })
+                .collect();
+            entries.sort();
+            assert_eq!(
+                entries,
+                vec![(OsString::from("a.txt"), false, true), (OsString::from("sub"), true, false)]
+            );
+        }
+
+        assert!(read_dir(&dir.join("missing"), true).is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
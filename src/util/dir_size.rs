@@ -0,0 +1,145 @@
+//! Recursive directory totals backing the `dir_size` and `dir_disk_usage` fields.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+
+/// Totals of all descendants of a directory.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DirUsage {
+    /// Sum of apparent file sizes in bytes
+    pub size: u64,
+    /// Sum of space actually allocated on disk in bytes
+    pub allocated: u64,
+}
+
+/// Space the file occupies on disk: the number of 512-byte blocks on Unix.
+/// Sparse, compressed or deduplicated files may occupy less than their
+/// apparent size. Elsewhere the apparent size is all we know.
+#[cfg(unix)]
+pub fn get_allocated_size(metadata: &Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    metadata.blocks() * 512
+}
+
+#[cfg(not(unix))]
+pub fn get_allocated_size(metadata: &Metadata) -> u64 {
+    metadata.len()
+}
+
+/// Device and inode of a file with more than one hard link, which like in
+/// `du` is counted only the first time it comes up.
+#[cfg(unix)]
+fn get_hard_link_id(metadata: &Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    (metadata.nlink() > 1).then(|| (metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn get_hard_link_id(_metadata: &Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// Caches totals of every directory walked during a search. Computing the
+/// total of a directory stores the totals of all its subdirectories too,
+/// so as the traversal descends each directory tree is walked only once.
+#[derive(Default)]
+pub struct DirSizeCache {
+    totals: HashMap<PathBuf, DirUsage>,
+}
+
+impl DirSizeCache {
+    pub fn new() -> DirSizeCache {
+        DirSizeCache { totals: HashMap::new() }
+    }
+
+    /// Returns the totals of the directory, walking it unless already known.
+    /// Symlinks are not followed, and files hard linked more than once in the
+    /// directory are counted once. Unreadable subdirectories count as empty;
+    /// `None` is returned only if the directory itself can't be read.
+    pub fn get_dir_usage(&mut self, dir: &Path) -> Option<DirUsage> {
+        self.walk(dir, &mut HashSet::new())
+    }
+
+    fn walk(&mut self, dir: &Path, hard_links: &mut HashSet<(u64, u64)>) -> Option<DirUsage> {
+        if let Some(usage) = self.totals.get(dir) {
+            return Some(*usage);
+        }
+
+        let entries = fs::read_dir(dir).ok()?;
+        let mut usage = DirUsage::default();
+
+        for entry in entries.flatten() {
+            let metadata = match entry.metadata() {
+                Ok(metadata) => metadata,
+                Err(_) => continue,
+            };
+
+            if metadata.is_dir() {
+                if let Some(sub) = self.walk(&entry.path(), hard_links) {
+                    usage.size += sub.size;
+                    usage.allocated += sub.allocated;
+                }
+                usage.allocated += get_allocated_size(&metadata);
+            } else if get_hard_link_id(&metadata).is_none_or(|id| hard_links.insert(id)) {
+                usage.size += metadata.len();
+                usage.allocated += get_allocated_size(&metadata);
+            }
+        }
+
+        self.totals.insert(dir.to_path_buf(), usage);
+
+        Some(usage)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dir_usage_sums_descendants() {
+        let tmp = std::env::temp_dir().join("fselect_test_dir_size_cache");
+        let _ = fs::remove_dir_all(&tmp);
+        fs::create_dir_all(tmp.join("a/b")).unwrap();
+        fs::write(tmp.join("one"), vec![0u8; 100]).unwrap();
+        fs::write(tmp.join("a/two"), vec![0u8; 20]).unwrap();
+        fs::write(tmp.join("a/b/three"), vec![0u8; 3]).unwrap();
+
+        let mut cache = DirSizeCache::new();
+        let total = cache.get_dir_usage(&tmp).unwrap();
+        assert_eq!(total.size, 123);
+
+        // Subdirectories are already known after walking their parent
+        assert_eq!(cache.totals.get(&tmp.join("a")).map(|u| u.size), Some(23));
+        assert_eq!(cache.totals.get(&tmp.join("a/b")).map(|u| u.size), Some(3));
+
+        let _ = fs::remove_dir_all(&tmp);
+
+        // Cached values survive the directory going away
+        assert_eq!(cache.get_dir_usage(&tmp.join("a")).unwrap().size, 23);
+        assert!(cache.get_dir_usage(&tmp.join("missing")).is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_hard_links_are_counted_once() {
+        let tmp = std::env::temp_dir().join("fselect_test_dir_size_hard_links");
+        let _ = fs::remove_dir_all(&tmp);
+        fs::create_dir_all(tmp.join("backup.1")).unwrap();
+        fs::create_dir_all(tmp.join("backup.2")).unwrap();
+        fs::write(tmp.join("backup.1/data"), vec![0u8; 100]).unwrap();
+        fs::hard_link(tmp.join("backup.1/data"), tmp.join("backup.2/data")).unwrap();
+        fs::write(tmp.join("backup.2/new"), vec![0u8; 10]).unwrap();
+
+        let mut cache = DirSizeCache::new();
+        let total = cache.get_dir_usage(&tmp).unwrap();
+        let single = DirSizeCache::new().get_dir_usage(&tmp.join("backup.2")).unwrap();
+        let _ = fs::remove_dir_all(&tmp);
+
+        assert_eq!(total.size, 110);
+        // Each backup on its own still holds the file
+        assert_eq!(single.size, 110);
+    }
+}
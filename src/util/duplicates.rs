@@ -0,0 +1,129 @@
+//! Cheap pre-filtering stages for duplicate-detection queries: only files
+//! sharing their size and then the hash of their first bytes with another
+//! file can have a duplicate, so only those need a full content hash.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::hash::{DefaultHasher, Hasher};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// How much of each file the second stage reads
+const PREFIX_LEN: u64 = 64 * 1024;
+
+/// Files collected during the search together with the rest of their
+/// values, waiting to be narrowed down to possible duplicates.
+pub struct DuplicateCandidates<T> {
+    by_size: HashMap<u64, Vec<(PathBuf, T)>>,
+    /// Directories, special and unreadable files: not filtered, so they end
+    /// up with the same hash as without the staged pipeline
+    unfiltered: Vec<(PathBuf, T)>,
+}
+
+impl<T> DuplicateCandidates<T> {
+    pub fn new() -> DuplicateCandidates<T> {
+        DuplicateCandidates {
+            by_size: HashMap::new(),
+            unfiltered: vec![],
+        }
+    }
+
+    /// Adds a file, with its size if it's a regular file.
+    pub fn push(&mut self, path: PathBuf, size: Option<u64>, item: T) {
+        match size {
+            Some(size) => self.by_size.entry(size).or_default().push((path, item)),
+            None => self.unfiltered.push((path, item)),
+        }
+    }
+
+    /// Drops files with a unique size, then files with a unique hash of
+    /// the first 64 KiB among the files of the same size. What's left has
+    /// to be hashed fully.
+    pub fn into_candidates(self) -> Vec<(PathBuf, T)> {
+        let mut result = self.unfiltered;
+
+        for (size, files) in self.by_size {
+            if files.len() < 2 {
+                continue;
+            }
+
+            // Empty files are all the same
+            if size == 0 {
+                result.extend(files);
+                continue;
+            }
+
+            let mut by_prefix: HashMap<Option<u64>, Vec<(PathBuf, T)>> = HashMap::new();
+            for (path, item) in files {
+                by_prefix.entry(get_prefix_hash(&path)).or_default().push((path, item));
+            }
+
+            for (prefix_hash, files) in by_prefix {
+                // Files that couldn't be read are left to the full hash
+                if files.len() > 1 || prefix_hash.is_none() {
+                    result.extend(files);
+                }
+            }
+        }
+
+        result
+    }
+}
+
+/// Non-cryptographic hash of the first bytes of the file: a collision
+/// only costs an extra full hash.
+fn get_prefix_hash(path: &Path) -> Option<u64> {
+    let file = File::open(path).ok()?;
+    let mut buf = Vec::with_capacity(PREFIX_LEN as usize);
+    file.take(PREFIX_LEN).read_to_end(&mut buf).ok()?;
+
+    let mut hasher = DefaultHasher::new();
+    hasher.write(&buf);
+    Some(hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_candidates_share_size_and_prefix() {
+        let tmp = std::env::temp_dir().join("fselect_test_duplicate_candidates");
+        let _ = fs::remove_dir_all(&tmp);
+        fs::create_dir_all(&tmp).unwrap();
+
+        let mut long_a = vec![1u8; 100 * 1024];
+        let long_b = long_a.clone();
+        long_a[99 * 1024] = 2;
+        let mut other_prefix = long_a.clone();
+        other_prefix[0] = 3;
+
+        let files: Vec<(&str, Vec<u8>)> = vec![
+            ("unique_size", vec![0u8; 10]),
+            ("same_1", b"abcd".to_vec()),
+            ("same_2", b"abcd".to_vec()),
+            ("same_size_other_content", b"wxyz".to_vec()),
+            ("long_a", long_a),
+            ("long_b", long_b),
+            ("other_prefix", other_prefix),
+            ("empty_1", vec![]),
+            ("empty_2", vec![]),
+        ];
+
+        let mut candidates = DuplicateCandidates::new();
+        for (name, content) in &files {
+            fs::write(tmp.join(name), content).unwrap();
+            candidates.push(tmp.join(name), Some(content.len() as u64), name.to_string());
+        }
+        candidates.push(tmp.clone(), None, String::from("dir"));
+
+        let mut names: Vec<String> = candidates.into_candidates().into_iter().map(|(_, name)| name).collect();
+        names.sort();
+
+        let _ = fs::remove_dir_all(&tmp);
+
+        // long_a and long_b only differ past the prefix, so both need the full hash
+        assert_eq!(names, vec!["dir", "empty_1", "empty_2", "long_a", "long_b", "same_1", "same_2"]);
+    }
+}
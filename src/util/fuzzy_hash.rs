@@ -0,0 +1,406 @@
+//! Context-triggered piecewise hashing in the format of ssdeep, backing the
+//! `ssdeep` field and the `similarity()` function. The content is cut into
+//! pieces where a rolling hash of the last few bytes hits a trigger value,
+//! and each piece contributes one character. Files differing in a few bytes
+//! differ in a few pieces only, so their hashes stay comparable.
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use crate::util::progress::add_hashed_bytes;
+
+const ROLLING_WINDOW: usize = 7;
+const MIN_BLOCKSIZE: u64 = 3;
+const SPAMSUM_LENGTH: usize = 64;
+const NUM_BLOCKHASHES: usize = 31;
+const HASH_PRIME: u32 = 0x01000193;
+/// Only the lowest six bits of the piece hashes are ever used
+const HASH_INIT: u8 = 0x27;
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn block_size(index: usize) -> u64 {
+    MIN_BLOCKSIZE << index
+}
+
+fn sum_hash(c: u8, h: u8) -> u8 {
+    ((h as u32).wrapping_mul(HASH_PRIME) ^ c as u32) as u8 & 0x3f
+}
+
+#[derive(Default)]
+struct RollingHash {
+    window: [u8; ROLLING_WINDOW],
+    h1: u32,
+    h2: u32,
+    h3: u32,
+    n: usize,
+}
+
+impl RollingHash {
+    fn update(&mut self, c: u8) {
+        self.h2 = self.h2.wrapping_sub(self.h1).wrapping_add(ROLLING_WINDOW as u32 * c as u32);
+        self.h1 = self.h1.wrapping_add(c as u32).wrapping_sub(self.window[self.n] as u32);
+        self.window[self.n] = c;
+        self.n = (self.n + 1) % ROLLING_WINDOW;
+        self.h3 = (self.h3 << 5) ^ c as u32;
+    }
+
+    fn sum(&self) -> u32 {
+        self.h1.wrapping_add(self.h2).wrapping_add(self.h3)
+    }
+}
+
+/// Pieces of the content for one block size. When the digest is full, the
+/// last character keeps covering everything that follows.
+#[derive(Clone)]
+struct BlockHash {
+    h: u8,
+    half_h: u8,
+    digest: [u8; SPAMSUM_LENGTH],
+    len: usize,
+    half_digest: Option<u8>,
+}
+
+impl BlockHash {
+    fn new() -> BlockHash {
+        BlockHash {
+            h: HASH_INIT,
+            half_h: HASH_INIT,
+            digest: [0; SPAMSUM_LENGTH],
+            len: 0,
+            half_digest: None,
+        }
+    }
+}
+
+/// Hashes all block sizes at once, in a single pass over the content.
+struct FuzzyHasher {
+    blocks: Vec<BlockHash>,
+    start: usize,
+    roll: RollingHash,
+    total_size: u64,
+}
+
+impl FuzzyHasher {
+    fn new(total_size: u64) -> FuzzyHasher {
+        FuzzyHasher {
+            blocks: vec![BlockHash::new()],
+            start: 0,
+            roll: RollingHash::default(),
+            total_size,
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        for &c in data {
+            self.roll.update(c);
+            for block in &mut self.blocks[self.start..] {
+                block.h = sum_hash(c, block.h);
+                block.half_h = sum_hash(c, block.half_h);
+            }
+
+            let sum = self.roll.sum() as u64;
+            let mut i = self.start;
+            while i < self.blocks.len() {
+                if sum % block_size(i) != block_size(i) - 1 {
+                    break;
+                }
+
+                if self.blocks[i].len == 0 {
+                    self.fork_last_block();
+                }
+
+                let block = &mut self.blocks[i];
+                block.digest[block.len] = BASE64[block.h as usize];
+                block.half_digest = Some(BASE64[block.half_h as usize]);
+                if block.len < SPAMSUM_LENGTH - 1 {
+                    block.len += 1;
+                    block.h = HASH_INIT;
+                    if block.len < SPAMSUM_LENGTH / 2 {
+                        block.half_h = HASH_INIT;
+                        block.half_digest = None;
+                    }
+                } else {
+                    self.drop_first_block();
+                }
+
+                i += 1;
+            }
+        }
+    }
+
+    /// Starts the next block size once the largest one gets its first piece.
+    fn fork_last_block(&mut self) {
+        if self.blocks.len() < NUM_BLOCKHASHES {
+            let last = self.blocks.last().unwrap();
+            let mut next = BlockHash::new();
+            next.h = last.h;
+            next.half_h = last.half_h;
+            self.blocks.push(next);
+        }
+    }
+
+    /// Stops hashing the smallest block size once it's too small for the
+    /// content and the next one has enough pieces to be used instead.
+    fn drop_first_block(&mut self) {
+        if self.blocks.len() - self.start < 2
+            || block_size(self.start) * SPAMSUM_LENGTH as u64 >= self.total_size
+            || self.blocks[self.start + 1].len < SPAMSUM_LENGTH / 2
+        {
+            return;
+        }
+        self.start += 1;
+    }
+
+    /// `<block size>:<pieces>:<pieces of twice the block size>`
+    fn finish(&self) -> String {
+        let end = self.blocks.len();
+        let mut bi = self.start;
+        while bi + 1 < end && block_size(bi) * (SPAMSUM_LENGTH as u64) < self.total_size {
+            bi += 1;
+        }
+        while bi > self.start && self.blocks[bi].len < SPAMSUM_LENGTH / 2 {
+            bi -= 1;
+        }
+
+        let rolling = self.roll.sum();
+        let block = &self.blocks[bi];
+        let mut result = block.digest[..block.len].to_vec();
+        if rolling != 0 {
+            result.push(BASE64[block.h as usize]);
+        } else if block.digest[block.len] != 0 {
+            result.push(block.digest[block.len]);
+        }
+        result.push(b':');
+
+        if bi + 1 < end {
+            let next = &self.blocks[bi + 1];
+            result.extend_from_slice(&next.digest[..next.len.min(SPAMSUM_LENGTH / 2 - 1)]);
+            if rolling != 0 {
+                result.push(BASE64[next.half_h as usize]);
+            } else if let Some(half_digest) = next.half_digest {
+                result.push(half_digest);
+            }
+        } else if rolling != 0 {
+            result.push(BASE64[block.h as usize]);
+        }
+
+        format!("{}:{}", block_size(bi), String::from_utf8_lossy(&result))
+    }
+}
+
+/// Hashes `size` bytes the reader yields.
+pub fn fuzzy_hash_reader(mut reader: impl Read, size: u64) -> Option<String> {
+    let mut hasher = FuzzyHasher::new(size);
+    let mut buf = [0u8; 8192];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                hasher.update(&buf[..n]);
+                add_hashed_bytes(n);
+            }
+            Err(_) => return None,
+        }
+    }
+
+    Some(hasher.finish())
+}
+
+/// Hashes the file, or returns an empty string if it can't be read.
+pub fn fuzzy_hash_file(path: &Path) -> String {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(_) => return String::new(),
+    };
+
+    match file.metadata() {
+        Ok(metadata) if metadata.is_file() => fuzzy_hash_reader(file, metadata.len()).unwrap_or_default(),
+        _ => String::new(),
+    }
+}
+
+struct ParsedHash {
+    block_size: u64,
+    first: Vec<u8>,
+    second: Vec<u8>,
+}
+
+fn parse_fuzzy_hash(hash: &str) -> Option<ParsedHash> {
+    let mut parts = hash.trim().splitn(3, ':');
+    let block_size = parts.next()?.parse().ok()?;
+    let first = eliminate_sequences(parts.next()?.as_bytes());
+    // ssdeep output may be followed by a quoted file name
+    let second = parts.next()?.split(',').next()?;
+
+    Some(ParsedHash {
+        block_size,
+        first,
+        second: eliminate_sequences(second.as_bytes()),
+    })
+}
+
+/// Runs of more than three identical pieces carry little information and
+/// are shortened to three.
+fn eliminate_sequences(s: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(s.len());
+    for (i, &c) in s.iter().enumerate() {
+        if i < 3 || c != s[i - 1] || c != s[i - 2] || c != s[i - 3] {
+            result.push(c);
+        }
+    }
+    result
+}
+
+fn has_common_substring(s1: &[u8], s2: &[u8]) -> bool {
+    if s1.len() < ROLLING_WINDOW || s2.len() < ROLLING_WINDOW {
+        return false;
+    }
+
+    let windows: HashSet<&[u8]> = s1.windows(ROLLING_WINDOW).collect();
+    s2.windows(ROLLING_WINDOW).any(|window| windows.contains(window))
+}
+
+/// Edit distance where a substitution costs as much as a deletion and an insertion.
+fn edit_distance(s1: &[u8], s2: &[u8]) -> usize {
+    let mut previous: Vec<usize> = (0..=s2.len()).collect();
+    let mut current = vec![0; s2.len() + 1];
+
+    for (i, &c1) in s1.iter().enumerate() {
+        current[0] = i + 1;
+        for (j, &c2) in s2.iter().enumerate() {
+            let substitution = previous[j] + if c1 == c2 { 0 } else { 2 };
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+
+    previous[s2.len()]
+}
+
+fn score_strings(s1: &[u8], s2: &[u8], block_size: u64) -> u32 {
+    if !has_common_substring(s1, s2) {
+        return 0;
+    }
+
+    let distance = edit_distance(s1, s2) * SPAMSUM_LENGTH / (s1.len() + s2.len());
+    let distance = (100 * distance / SPAMSUM_LENGTH) as u32;
+    if distance >= 100 {
+        return 0;
+    }
+    let score = 100 - distance;
+
+    // Small block sizes can't claim a match bigger than the pieces they cover
+    let cap_block_size = (99 + ROLLING_WINDOW as u64) / ROLLING_WINDOW as u64 * MIN_BLOCKSIZE;
+    if block_size >= cap_block_size {
+        return score;
+    }
+    let cap = (block_size / MIN_BLOCKSIZE) as u32 * s1.len().min(s2.len()) as u32;
+    score.min(cap)
+}
+
+/// Similarity of two hashes from 0 (nothing in common) to 100 (identical),
+/// or `None` if either isn't a fuzzy hash. Hashes can only be compared when
+/// their block sizes are equal or differ by a factor of two.
+pub fn fuzzy_similarity(hash1: &str, hash2: &str) -> Option<u32> {
+    let h1 = parse_fuzzy_hash(hash1)?;
+    let h2 = parse_fuzzy_hash(hash2)?;
+
+    let score = if h1.block_size == h2.block_size {
+        if h1.first == h2.first && h1.second == h2.second {
+            return Some(100);
+        }
+        score_strings(&h1.first, &h2.first, h1.block_size)
+            .max(score_strings(&h1.second, &h2.second, h1.block_size * 2))
+    } else if h1.block_size == h2.block_size * 2 {
+        score_strings(&h1.first, &h2.second, h1.block_size)
+    } else if h2.block_size == h1.block_size * 2 {
+        score_strings(&h1.second, &h2.first, h2.block_size)
+    } else {
+        0
+    };
+
+    Some(score)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Text-like content that doesn't repeat, so pieces are cut all over it
+    fn test_content(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                b"abcdefghijklmnopqrstuvwxyz \n"[(state >> 33) as usize % 28]
+            })
+            .collect()
+    }
+
+    fn hash(content: &[u8]) -> String {
+        fuzzy_hash_reader(content, content.len() as u64).unwrap()
+    }
+
+    #[test]
+    fn test_empty_and_small_content() {
+        assert_eq!(hash(b""), "3::");
+        assert!(hash(b"a").starts_with("3:"));
+    }
+
+    #[test]
+    fn test_hash_format() {
+        let content = test_content(100_000, 1);
+        let digest = hash(&content);
+        let parts: Vec<&str> = digest.split(':').collect();
+
+        assert_eq!(parts.len(), 3);
+        let block_size: u64 = parts[0].parse().unwrap();
+        assert_eq!(block_size % MIN_BLOCKSIZE, 0);
+        assert!(parts[1].len() >= SPAMSUM_LENGTH / 2 && parts[1].len() <= SPAMSUM_LENGTH);
+        assert!(parts[2].len() <= SPAMSUM_LENGTH / 2);
+        assert_eq!(digest, hash(&content));
+    }
+
+    #[test]
+    fn test_similar_content_scores_high() {
+        let original = test_content(50_000, 2);
+        let mut edited = original.clone();
+        edited[25_000..25_010].copy_from_slice(b"0123456789");
+        let unrelated = test_content(50_000, 3);
+
+        let original_hash = hash(&original);
+        assert_eq!(fuzzy_similarity(&original_hash, &original_hash), Some(100));
+
+        let similar = fuzzy_similarity(&original_hash, &hash(&edited)).unwrap();
+        assert!((80..100).contains(&similar), "similarity of edited content: {}", similar);
+        assert_eq!(fuzzy_similarity(&original_hash, &hash(&unrelated)), Some(0));
+    }
+
+    /// Digests and scores produced by the reference ssdeep implementation
+    #[test]
+    fn test_reference_vectors() {
+        assert_eq!(hash(b"Hello there!"), "3:aNRn:aNRn");
+
+        let hash1 = hash(b"Also called fuzzy hashes, Ctph can match inputs that have homologies.");
+        let hash2 = hash(b"Also called fuzzy hashes, CTPH can match inputs that have homologies.");
+        assert_eq!(hash1, "3:AXGBicFlgVNhBGcL6wCrFQEv:AXGHsNhxLsr2C");
+        assert_eq!(hash2, "3:AXGBicFlIHBGcL6wCrFQEv:AXGH6xLsr2C");
+        assert_eq!(fuzzy_similarity(&hash1, &hash2), Some(22));
+    }
+
+    #[test]
+    fn test_fuzzy_similarity_input() {
+        assert_eq!(fuzzy_similarity("3:abc:de", "not a hash"), None);
+        // Block sizes too far apart
+        assert_eq!(fuzzy_similarity("3:abcdefgh:ab", "12:abcdefgh:ab"), Some(0));
+        // ssdeep output lines carry a file name
+        assert_eq!(fuzzy_similarity("3:abcdefgh:ab,\"file.txt\"", "3:abcdefgh:ab"), Some(100));
+    }
+
+    #[test]
+    fn test_eliminate_sequences() {
+        assert_eq!(eliminate_sequences(b"aaaaabccccd"), b"aaabcccd".to_vec());
+    }
+}
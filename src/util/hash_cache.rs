@@ -0,0 +1,318 @@
+//! Persistent cache of file hashes, so that repeated searches over the same
+//! files don't read them again. Files are identified by device and inode,
+//! and a cached hash is only trusted while the size and modification time
+//! stay the same.
+
+use std::collections::HashMap;
+use std::fs;
+use std::fs::Metadata;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+const HASH_CACHE_FILE: &str = "hash_cache.tsv";
+
+static TEMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Identity of a file on disk: algorithm, device and inode
+type CacheKey = (String, u64, u64);
+
+/// What the cached hash was computed for
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct FileStamp {
+    size: u64,
+    mtime: i64,
+    mtime_nsec: i64,
+}
+
+#[derive(Clone, Debug)]
+struct CachedHash {
+    stamp: FileStamp,
+    hash: String,
+    path: PathBuf,
+}
+
+pub struct HashCache {
+    file: Option<PathBuf>,
+    entries: Option<HashMap<CacheKey, CachedHash>>,
+    updated: HashMap<CacheKey, CachedHash>,
+}
+
+impl HashCache {
+    /// Cache stored in the application directory, or a no-op cache when
+    /// disabled or there's no application directory.
+    pub fn new(enabled: bool) -> HashCache {
+        let file = match enabled {
+            true => get_hash_cache_file(),
+            false => None,
+        };
+
+        HashCache::with_file(file)
+    }
+
+    pub fn with_file(file: Option<PathBuf>) -> HashCache {
+        HashCache {
+            file,
+            entries: None,
+            updated: HashMap::new(),
+        }
+    }
+
+    /// Returns the cached hash of the file if it hasn't changed since,
+    /// otherwise computes it and remembers the result. Empty results
+    /// (unreadable files) are never cached.
+    pub fn get_or_compute(&mut self, algorithm: &str, path: &Path, compute: impl FnOnce(&Path) -> String) -> String {
+        if self.file.is_none() {
+            return compute(path);
+        }
+
+        // Hashing follows symlinks, and so does the cache key
+        let (dev, ino, stamp) = match fs::metadata(path).ok().and_then(|m| get_file_stamp(&m)) {
+            Some(key) => key,
+            None => return compute(path),
+        };
+
+        let key = (algorithm.to_string(), dev, ino);
+        if !self.updated.contains_key(&key) {
+            self.load();
+        }
+        if let Some(cached) = self.updated.get(&key).or_else(|| self.entries.as_ref().and_then(|e| e.get(&key)))
+            && cached.stamp == stamp {
+                return cached.hash.clone();
+            }
+
+        let hash = compute(path);
+        if !hash.is_empty() {
+            self.updated.insert(key, CachedHash { stamp, hash: hash.clone(), path: path.to_path_buf() });
+        }
+
+        hash
+    }
+
+    /// Writes the hashes computed during this run. The cache file is re-read
+    /// first, so entries saved meanwhile by another search are kept.
+    pub fn save(&mut self) {
+        if self.updated.is_empty() {
+            return;
+        }
+
+        let file = match &self.file {
+            Some(file) => file.clone(),
+            None => return,
+        };
+
+        let mut entries = read_entries(&file);
+        entries.extend(self.updated.drain());
+        let _ = write_entries(&file, &entries);
+        self.entries = Some(entries);
+    }
+
+    /// Drops entries of files that were deleted or changed since they were
+    /// hashed. Returns the number of removed and remaining entries.
+    pub fn prune(&mut self) -> std::io::Result<(usize, usize)> {
+        let file = match &self.file {
+            Some(file) => file.clone(),
+            None => return Ok((0, 0)),
+        };
+
+        let mut entries = read_entries(&file);
+        let total = entries.len();
+
+        entries.retain(|(_, dev, ino), cached| {
+            fs::metadata(&cached.path)
+                .ok()
+                .and_then(|m| get_file_stamp(&m))
+                .is_some_and(|(cur_dev, cur_ino, stamp)| cur_dev == *dev && cur_ino == *ino && stamp == cached.stamp)
+        });
+
+        let remaining = entries.len();
+        write_entries(&file, &entries)?;
+        self.entries = Some(entries);
+
+        Ok((total - remaining, remaining))
+    }
+
+    fn load(&mut self) {
+        if self.entries.is_none() {
+            self.entries = Some(self.file.as_deref().map(read_entries).unwrap_or_default());
+        }
+    }
+}
+
+/// Hashes are saved when the search is over, however it ended. The cache is
+/// shared by the searchers of subqueries, so this happens once, when the
+/// outermost searcher lets it go.
+impl Drop for HashCache {
+    fn drop(&mut self) {
+        self.save();
+    }
+}
+
+fn get_hash_cache_file() -> Option<PathBuf> {
+    crate::util::app_dirs::get_project_dir().map(|dir| dir.join(HASH_CACHE_FILE))
+}
+
+#[cfg(unix)]
+fn get_file_stamp(metadata: &Metadata) -> Option<(u64, u64, FileStamp)> {
+    use std::os::unix::fs::MetadataExt;
+
+    let stamp = FileStamp {
+        size: metadata.size(),
+        mtime: metadata.mtime(),
+        mtime_nsec: metadata.mtime_nsec(),
+    };
+
+    Some((metadata.dev(), metadata.ino(), stamp))
+}
+
+/// Stable Rust exposes no file identity on other platforms
+#[cfg(not(unix))]
+fn get_file_stamp(_metadata: &Metadata) -> Option<(u64, u64, FileStamp)> {
+    None
+}
+
+/// One entry per line, tab separated:
+/// algorithm, device, inode, size, mtime, mtime_nsec, hash, path.
+/// Malformed lines are skipped.
+fn read_entries(file: &Path) -> HashMap<CacheKey, CachedHash> {
+    let mut entries = HashMap::new();
+
+    let file = match fs::File::open(file) {
+        Ok(file) => file,
+        Err(_) => return entries,
+    };
+
+    for line in BufReader::new(file).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+
+        if let Some((key, cached)) = parse_entry(&line) {
+            entries.insert(key, cached);
+        }
+    }
+
+    entries
+}
+
+fn parse_entry(line: &str) -> Option<(CacheKey, CachedHash)> {
+    let parts: Vec<&str> = line.splitn(8, '\t').collect();
+    if parts.len() != 8 || parts[6].is_empty() {
+        return None;
+    }
+
+    let key = (parts[0].to_string(), parts[1].parse().ok()?, parts[2].parse().ok()?);
+    let stamp = FileStamp {
+        size: parts[3].parse().ok()?,
+        mtime: parts[4].parse().ok()?,
+        mtime_nsec: parts[5].parse().ok()?,
+    };
+
+    Some((key, CachedHash { stamp, hash: parts[6].to_string(), path: PathBuf::from(parts[7]) }))
+}
+
+/// Writes to a temporary file first and renames it over the cache, so an
+/// interrupted search never leaves a truncated cache behind. The temporary
+/// name is unique, so that searches running side by side don't write into the
+/// same file.
+fn write_entries(file: &Path, entries: &HashMap<CacheKey, CachedHash>) -> std::io::Result<()> {
+    if let Some(dir) = file.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let counter = TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed);
+    let tmp_file = file.with_extension(format!("{}-{}.tmp", std::process::id(), counter));
+    let result = write_lines(&tmp_file, entries).and_then(|_| fs::rename(&tmp_file, file));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_file);
+    }
+
+    result
+}
+
+fn write_lines(file: &Path, entries: &HashMap<CacheKey, CachedHash>) -> std::io::Result<()> {
+    let mut out = std::io::BufWriter::new(fs::File::create(file)?);
+    for ((algorithm, dev, ino), cached) in entries {
+        let path = cached.path.to_string_lossy();
+        // Paths that can't be read back from a line are not worth the trouble
+        if path.contains('\n') {
+            continue;
+        }
+        writeln!(
+            out,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            algorithm, dev, ino, cached.stamp.size, cached.stamp.mtime, cached.stamp.mtime_nsec, cached.hash, path
+        )?;
+    }
+
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_entry() {
+        let (key, cached) = parse_entry("sha1\t1\t2\t3\t4\t5\tabc\t/tmp/a\tb").unwrap();
+        assert_eq!(key, ("sha1".to_string(), 1, 2));
+        assert_eq!(cached.stamp, FileStamp { size: 3, mtime: 4, mtime_nsec: 5 });
+        assert_eq!(cached.hash, "abc");
+        assert_eq!(cached.path, PathBuf::from("/tmp/a\tb"));
+
+        assert!(parse_entry("sha1\t1\t2\t3\t4\t5\tabc").is_none());
+        assert!(parse_entry("sha1\tx\t2\t3\t4\t5\tabc\t/tmp/a").is_none());
+    }
+
+    #[test]
+    fn test_disabled_cache_always_computes() {
+        let tmp = std::env::temp_dir().join("fselect_test_hash_cache_disabled");
+        let _ = fs::remove_dir_all(&tmp);
+        fs::create_dir_all(&tmp).unwrap();
+        fs::write(tmp.join("a.txt"), "a").unwrap();
+        let entry = tmp.join("a.txt");
+
+        let mut cache = HashCache::with_file(None);
+        assert_eq!(cache.get_or_compute("sha1", &entry, |_| "first".to_string()), "first");
+        assert_eq!(cache.get_or_compute("sha1", &entry, |_| "second".to_string()), "second");
+        cache.save();
+
+        let _ = fs::remove_dir_all(&tmp);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_cached_hash_survives_until_file_changes() {
+        let tmp = std::env::temp_dir().join("fselect_test_hash_cache");
+        let _ = fs::remove_dir_all(&tmp);
+        fs::create_dir_all(&tmp).unwrap();
+        fs::write(tmp.join("a.txt"), "a").unwrap();
+        fs::write(tmp.join("gone.txt"), "gone").unwrap();
+        let cache_file = tmp.join("cache").join(HASH_CACHE_FILE);
+
+        let mut cache = HashCache::with_file(Some(cache_file.clone()));
+        let entry = tmp.join("a.txt");
+        assert_eq!(cache.get_or_compute("sha1", &entry, |_| "first".to_string()), "first");
+        let gone = tmp.join("gone.txt");
+        assert_eq!(cache.get_or_compute("sha1", &gone, |_| "gone".to_string()), "gone");
+        cache.save();
+
+        // A new search reads the hash back from disk, per algorithm
+        let mut cache = HashCache::with_file(Some(cache_file.clone()));
+        assert_eq!(cache.get_or_compute("sha1", &entry, |_| "second".to_string()), "first");
+        assert_eq!(cache.get_or_compute("sha256", &entry, |_| "other".to_string()), "other");
+
+        // A changed size invalidates the entry
+        fs::write(tmp.join("a.txt"), "changed").unwrap();
+        assert_eq!(cache.get_or_compute("sha1", &entry, |_| "third".to_string()), "third");
+        cache.save();
+
+        // The deleted file and the stale sha256 of the changed one go away
+        fs::remove_file(tmp.join("gone.txt")).unwrap();
+        let mut cache = HashCache::with_file(Some(cache_file.clone()));
+        assert_eq!(cache.prune().unwrap(), (2, 1));
+
+        let _ = fs::remove_dir_all(&tmp);
+    }
+}
@@ -0,0 +1,269 @@
+//! File content hashing behind the hash fields and the `hash()` function.
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use sha1::Digest;
+
+use crate::field::Field;
+use crate::util::progress::add_hashed_bytes;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum HashAlgorithm {
+    Sha1,
+    Sha256,
+    Sha512,
+    Sha3,
+    Md5,
+    Crc32,
+    Blake3,
+    Xxh3,
+}
+
+/// Part of the content to hash
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ByteRange {
+    All,
+    First(u64),
+    Last(u64),
+}
+
+impl ByteRange {
+    /// Positive numbers select the first bytes, negative ones the last bytes.
+    pub fn from_len(len: i64) -> ByteRange {
+        match len >= 0 {
+            true => ByteRange::First(len as u64),
+            false => ByteRange::Last(len.unsigned_abs()),
+        }
+    }
+}
+
+impl HashAlgorithm {
+    /// Accepts the names of the hash fields, case-insensitively.
+    pub fn from_name(name: &str) -> Option<HashAlgorithm> {
+        match name.to_lowercase().as_str() {
+            "sha1" => Some(HashAlgorithm::Sha1),
+            "sha2_256" | "sha256" => Some(HashAlgorithm::Sha256),
+            "sha2_512" | "sha512" => Some(HashAlgorithm::Sha512),
+            "sha3_512" | "sha3" => Some(HashAlgorithm::Sha3),
+            "md5" => Some(HashAlgorithm::Md5),
+            "crc32" => Some(HashAlgorithm::Crc32),
+            "blake3" => Some(HashAlgorithm::Blake3),
+            "xxh3" | "xxh3_64" => Some(HashAlgorithm::Xxh3),
+            _ => None,
+        }
+    }
+
+    pub fn from_field(field: &Field) -> Option<HashAlgorithm> {
+        match field {
+            Field::Sha1 => Some(HashAlgorithm::Sha1),
+            Field::Sha256 => Some(HashAlgorithm::Sha256),
+            Field::Sha512 => Some(HashAlgorithm::Sha512),
+            Field::Sha3 => Some(HashAlgorithm::Sha3),
+            Field::Md5 => Some(HashAlgorithm::Md5),
+            Field::Crc32 => Some(HashAlgorithm::Crc32),
+            Field::Blake3 => Some(HashAlgorithm::Blake3),
+            Field::Xxh3 => Some(HashAlgorithm::Xxh3),
+            _ => None,
+        }
+    }
+
+    /// Name of the algorithm in the hash cache
+    pub fn name(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha1 => "sha1",
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Sha512 => "sha512",
+            HashAlgorithm::Sha3 => "sha3",
+            HashAlgorithm::Md5 => "md5",
+            HashAlgorithm::Crc32 => "crc32",
+            HashAlgorithm::Blake3 => "blake3",
+            HashAlgorithm::Xxh3 => "xxh3",
+        }
+    }
+
+    fn hasher(&self) -> Box<dyn ContentHasher> {
+        match self {
+            HashAlgorithm::Sha1 => Box::new(sha1::Sha1::new()),
+            HashAlgorithm::Sha256 => Box::new(sha2::Sha256::new()),
+            HashAlgorithm::Sha512 => Box::new(sha2::Sha512::new()),
+            HashAlgorithm::Sha3 => Box::new(sha3::Sha3_512::new()),
+            HashAlgorithm::Md5 => Box::new(md5::Md5::new()),
+            HashAlgorithm::Crc32 => Box::new(crc32fast::Hasher::new()),
+            HashAlgorithm::Blake3 => Box::new(blake3::Hasher::new()),
+            HashAlgorithm::Xxh3 => Box::new(xxhash_rust::xxh3::Xxh3::new()),
+        }
+    }
+
+    /// Hashes everything the reader yields, returning the hex digest.
+    pub fn hash_reader(&self, mut reader: impl Read) -> Option<String> {
+        let mut hasher = self.hasher();
+        let mut buf = [0u8; 8192];
+        loop {
+            match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => {
+                    hasher.update(&buf[..n]);
+                    add_hashed_bytes(n);
+                }
+                Err(_) => return None,
+            }
+        }
+
+        Some(to_hex(&hasher.finish()))
+    }
+
+    /// Hashes the file, or an empty string if it can't be read.
+    pub fn hash_file(&self, path: &Path) -> String {
+        self.hash_file_range(path, ByteRange::All)
+    }
+
+    /// Hashes a part of the file, or an empty string if it can't be read.
+    pub fn hash_file_range(&self, path: &Path, range: ByteRange) -> String {
+        let mut file = match File::open(path) {
+            Ok(file) => file,
+            Err(_) => return String::new(),
+        };
+
+        let result = match range {
+            ByteRange::All => self.hash_reader(file),
+            ByteRange::First(len) => self.hash_reader(file.take(len)),
+            ByteRange::Last(len) => file
+                .metadata()
+                .ok()
+                .and_then(|m| file.seek(SeekFrom::Start(m.len().saturating_sub(len))).ok())
+                .and_then(|_| self.hash_reader(file)),
+        };
+
+        result.unwrap_or_default()
+    }
+
+    /// Hashes a part of a stream that can't seek, such as an archive entry
+    /// of the given size.
+    pub fn hash_stream_range(&self, mut reader: impl Read, size: u64, range: ByteRange) -> Option<String> {
+        match range {
+            ByteRange::All => self.hash_reader(reader),
+            ByteRange::First(len) => self.hash_reader(reader.take(len)),
+            ByteRange::Last(len) => {
+                let skip = size.saturating_sub(len);
+                std::io::copy(&mut (&mut reader).take(skip), &mut std::io::sink()).ok()?;
+                self.hash_reader(reader)
+            }
+        }
+    }
+}
+
+trait ContentHasher {
+    fn update(&mut self, data: &[u8]);
+    fn finish(self: Box<Self>) -> Vec<u8>;
+}
+
+macro_rules! digest_hasher {
+    ($($hasher:ty),*) => {
+        $(
+            impl ContentHasher for $hasher {
+                fn update(&mut self, data: &[u8]) {
+                    Digest::update(self, data);
+                }
+
+                fn finish(self: Box<Self>) -> Vec<u8> {
+                    self.finalize().to_vec()
+                }
+            }
+        )*
+    };
+}
+
+digest_hasher!(sha1::Sha1, sha2::Sha256, sha2::Sha512, sha3::Sha3_512, md5::Md5);
+
+impl ContentHasher for crc32fast::Hasher {
+    fn update(&mut self, data: &[u8]) {
+        crc32fast::Hasher::update(self, data);
+    }
+
+    fn finish(self: Box<Self>) -> Vec<u8> {
+        self.finalize().to_be_bytes().to_vec()
+    }
+}
+
+impl ContentHasher for blake3::Hasher {
+    fn update(&mut self, data: &[u8]) {
+        blake3::Hasher::update(self, data);
+    }
+
+    fn finish(self: Box<Self>) -> Vec<u8> {
+        self.finalize().as_bytes().to_vec()
+    }
+}
+
+impl ContentHasher for xxhash_rust::xxh3::Xxh3 {
+    fn update(&mut self, data: &[u8]) {
+        xxhash_rust::xxh3::Xxh3::update(self, data);
+    }
+
+    // Big-endian, the way xxhsum prints it
+    fn finish(self: Box<Self>) -> Vec<u8> {
+        self.digest().to_be_bytes().to_vec()
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    use std::fmt::Write;
+
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        let _ = write!(hex, "{:02x}", byte);
+    }
+    hex
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash_str(algorithm: HashAlgorithm, s: &str) -> String {
+        algorithm.hash_reader(s.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_known_digests() {
+        assert_eq!(hash_str(HashAlgorithm::Md5, "abc"), "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(hash_str(HashAlgorithm::Sha1, "abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert_eq!(hash_str(HashAlgorithm::Crc32, "123456789"), "cbf43926");
+        assert_eq!(
+            hash_str(HashAlgorithm::Blake3, ""),
+            "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"
+        );
+        assert_eq!(hash_str(HashAlgorithm::Xxh3, ""), "2d06800538d394c2");
+    }
+
+    #[test]
+    fn test_from_name() {
+        assert_eq!(HashAlgorithm::from_name("SHA2_256"), Some(HashAlgorithm::Sha256));
+        assert_eq!(HashAlgorithm::from_name("xxh3_64"), Some(HashAlgorithm::Xxh3));
+        assert_eq!(HashAlgorithm::from_name("whirlpool"), None);
+    }
+
+    #[test]
+    fn test_hash_ranges() {
+        let path = std::env::temp_dir().join("fselect_test_hash_ranges");
+        std::fs::write(&path, "headbodytail").unwrap();
+
+        let md5 = HashAlgorithm::Md5;
+        let first = md5.hash_file_range(&path, ByteRange::from_len(4));
+        let last = md5.hash_file_range(&path, ByteRange::from_len(-4));
+        let beyond = md5.hash_file_range(&path, ByteRange::Last(100));
+        let whole = md5.hash_file(&path);
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(first, hash_str(md5, "head"));
+        assert_eq!(last, hash_str(md5, "tail"));
+        assert_eq!(beyond, whole);
+        assert_eq!(whole, hash_str(md5, "headbodytail"));
+
+        let stream_last = md5.hash_stream_range("headbodytail".as_bytes(), 12, ByteRange::Last(4));
+        assert_eq!(stream_last, Some(hash_str(md5, "tail")));
+        assert!(md5.hash_file(Path::new("/nonexistent/fselect")).is_empty());
+    }
+}
@@ -0,0 +1,161 @@
+//! Perceptual image hashes backing the `phash` and `dhash` fields. Unlike
+//! content digests, images that look alike get hashes differing in only a
+//! few bits, even when resized or recompressed.
+
+use std::path::Path;
+
+use image::imageops::FilterType;
+use image::{DynamicImage, GrayImage, ImageReader};
+
+/// Side of the image the DCT of `phash` is computed for
+const PHASH_SIZE: usize = 32;
+/// Side of the lowest frequencies `phash` keeps
+const PHASH_BITS_SIZE: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ImageHashes {
+    pub phash: u64,
+    pub dhash: u64,
+}
+
+/// Decodes the image and computes both hashes, as decoding costs far more
+/// than hashing.
+pub fn get_image_hashes(path: &Path) -> Option<ImageHashes> {
+    let image = ImageReader::open(path).ok()?.with_guessed_format().ok()?.decode().ok()?;
+
+    Some(get_hashes(&image))
+}
+
+fn get_hashes(image: &DynamicImage) -> ImageHashes {
+    ImageHashes {
+        phash: get_phash(&shrink(image, PHASH_SIZE as u32, PHASH_SIZE as u32)),
+        dhash: get_dhash(&shrink(image, 9, 8)),
+    }
+}
+
+fn shrink(image: &DynamicImage, width: u32, height: u32) -> GrayImage {
+    image.resize_exact(width, height, FilterType::Triangle).to_luma8()
+}
+
+/// Difference hash: one bit per pair of horizontally adjacent pixels of a
+/// 9x8 thumbnail, set when the brightness grows to the right.
+fn get_dhash(thumbnail: &GrayImage) -> u64 {
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            let left = thumbnail.get_pixel(x, y)[0];
+            let right = thumbnail.get_pixel(x + 1, y)[0];
+            hash = (hash << 1) | (left < right) as u64;
+        }
+    }
+    hash
+}
+
+/// DCT hash: one bit per the lowest 8x8 frequencies of a 32x32 thumbnail,
+/// set when the coefficient is above their median.
+fn get_phash(thumbnail: &GrayImage) -> u64 {
+    let cosines: Vec<Vec<f64>> = (0..PHASH_BITS_SIZE)
+        .map(|u| {
+            (0..PHASH_SIZE)
+                .map(|x| ((2 * x + 1) as f64 * u as f64 * std::f64::consts::PI / (2 * PHASH_SIZE) as f64).cos())
+                .collect()
+        })
+        .collect();
+
+    // The 2D DCT is separable: transform the rows, then the columns
+    let mut rows = [[0f64; PHASH_BITS_SIZE]; PHASH_SIZE];
+    for (y, row) in rows.iter_mut().enumerate() {
+        for (u, value) in row.iter_mut().enumerate() {
+            *value = (0..PHASH_SIZE)
+                .map(|x| thumbnail.get_pixel(x as u32, y as u32)[0] as f64 * cosines[u][x])
+                .sum();
+        }
+    }
+
+    let mut coefficients = Vec::with_capacity(PHASH_BITS_SIZE * PHASH_BITS_SIZE);
+    for v_cosines in &cosines {
+        for u in 0..PHASH_BITS_SIZE {
+            coefficients.push(rows.iter().zip(v_cosines).map(|(row, cosine)| row[u] * cosine).sum::<f64>());
+        }
+    }
+
+    // The DC term is the average brightness and would skew the median
+    let mut sorted = coefficients[1..].to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let median = sorted[sorted.len() / 2];
+
+    coefficients.iter().fold(0u64, |hash, &coefficient| (hash << 1) | (coefficient > median) as u64)
+}
+
+pub fn format_image_hash(hash: u64) -> String {
+    format!("{:016x}", hash)
+}
+
+/// Number of differing bits of two hex strings of the same length.
+pub fn hamming_distance(a: &str, b: &str) -> Option<u32> {
+    if a.is_empty() || a.len() != b.len() {
+        return None;
+    }
+
+    a.chars().zip(b.chars()).try_fold(0, |distance, (a, b)| {
+        Some(distance + (a.to_digit(16)? ^ b.to_digit(16)?).count_ones())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    fn test_image(width: u32, height: u32, inverted: bool) -> DynamicImage {
+        let image = RgbImage::from_fn(width, height, |x, y| {
+            let value = ((x * 255 / width) ^ (y * 255 / height)) as u8;
+            let value = if inverted { 255 - value } else { value };
+            Rgb([value, value / 2, 255 - value])
+        });
+        DynamicImage::ImageRgb8(image)
+    }
+
+    fn distance(a: u64, b: u64) -> u32 {
+        (a ^ b).count_ones()
+    }
+
+    #[test]
+    fn test_resized_image_is_similar() {
+        let original = get_hashes(&test_image(400, 300, false));
+        let resized = get_hashes(&test_image(400, 300, false).resize_exact(160, 120, FilterType::Lanczos3));
+        let different = get_hashes(&test_image(400, 300, true));
+
+        assert!(distance(original.phash, resized.phash) <= 6);
+        assert!(distance(original.dhash, resized.dhash) <= 6);
+        assert!(distance(original.phash, different.phash) > 20);
+        assert!(distance(original.dhash, different.dhash) > 20);
+    }
+
+    #[test]
+    fn test_get_image_hashes_from_file() {
+        let tmp = std::env::temp_dir().join("fselect_test_image_hashes");
+        let _ = std::fs::remove_dir_all(&tmp);
+        std::fs::create_dir_all(&tmp).unwrap();
+        let image = test_image(64, 64, false);
+        image.save(tmp.join("image.png")).unwrap();
+        std::fs::write(tmp.join("broken.png"), "not an image").unwrap();
+
+        let from_file = get_image_hashes(&tmp.join("image.png"));
+        let broken = get_image_hashes(&tmp.join("broken.png"));
+        let _ = std::fs::remove_dir_all(&tmp);
+
+        assert_eq!(from_file, Some(get_hashes(&image)));
+        assert_eq!(broken, None);
+    }
+
+    #[test]
+    fn test_hamming_distance() {
+        assert_eq!(hamming_distance("00ff", "00ff"), Some(0));
+        assert_eq!(hamming_distance("00ff", "01fe"), Some(2));
+        assert_eq!(hamming_distance("ABCD", "abcd"), Some(0));
+        assert_eq!(hamming_distance("00ff", "0ff"), None);
+        assert_eq!(hamming_distance("zz", "00"), None);
+        assert_eq!(hamming_distance("", ""), None);
+    }
+}
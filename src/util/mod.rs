@@ -13,6 +13,10 @@ pub(crate) mod win_attrs;
 #[cfg(windows)]
 pub(crate) mod win_xattr;
 pub(crate) mod datetime;
+pub(crate) mod decompress;
+pub(crate) mod dir_entry;
+pub(crate) mod dir_size;
+pub(crate) mod duplicates;
 #[cfg(all(windows, feature = "everything"))]
 pub(crate) mod everything;
 #[cfg(all(unix, feature = "plocate"))]
@@ -24,7 +28,21 @@ pub(crate) mod error;
 pub(crate) mod git;
 mod glob;
 pub(crate) mod greek;
+pub(crate) mod hash_cache;
+pub(crate) mod checksums;
+pub(crate) mod fuzzy_hash;
+pub(crate) mod hashes;
+pub(crate) mod image_hash;
 pub(crate) mod japanese;
+pub(crate) mod progress;
+#[cfg(feature = "rar")]
+pub(crate) mod rar;
+pub(crate) mod spill;
+mod root_path;
+#[cfg(target_os = "linux")]
+pub(crate) mod sparse;
+#[cfg(target_os = "linux")]
+pub(crate) mod statx;
 mod top_n;
 pub(crate) mod variant;
 mod wbuf;
@@ -35,7 +53,6 @@ use std::fmt::Display;
 use std::fs;
 use std::fs::canonicalize;
 use std::fs::symlink_metadata;
-use std::fs::DirEntry;
 use std::fs::File;
 use std::fs::Metadata;
 use std::io::Read;
@@ -54,11 +71,15 @@ pub use self::datetime::format_time;
 pub use self::datetime::parse_datetime;
 pub use self::datetime::set_us_dates;
 pub use self::datetime::system_time_to_naive_local;
-pub use self::datetime::to_local_datetime;
 pub use self::glob::convert_glob_to_pattern;
 pub use self::glob::convert_like_to_pattern;
 pub use self::glob::is_glob;
-pub use self::top_n::TopN;
+pub use self::dir_entry::{DirEntry, FileType};
+pub use self::root_path::expand_root_path;
+pub use self::root_path::is_path_list_root;
+pub use self::root_path::read_path_list;
+pub use self::root_path::{PATH_LIST_ROOT_PREFIX, STDIN_ROOT};
+pub use self::top_n::{SpillKey, TopN};
 pub use self::variant::{Variant, VariantType};
 pub use self::wbuf::WritableBuffer;
 use crate::expr::Expr;
@@ -190,6 +211,16 @@ impl<T: Display + Ord> Ord for Criteria<T> {
     }
 }
 
+impl SpillKey for Criteria<String> {
+    fn values(&self) -> &[String] {
+        &self.values
+    }
+
+    fn with_values(&self, values: Vec<String>) -> Self {
+        Criteria::new(self.fields.clone(), values, self.orderings.clone())
+    }
+}
+
 /// Numeric sort key for ORDER BY comparisons: a plain integer or float, or a
 /// file size with a unit suffix ("1k", "5mb"). `None` for anything else.
 fn numeric_sort_key(s: &str) -> Option<f64> {
@@ -200,6 +231,47 @@ fn numeric_sort_key(s: &str) -> Option<f64> {
     parse_filesize(s).map(|size| size as f64)
 }
 
+/// Compares strings in natural order: runs of digits are compared by their
+/// numeric value, so `file2` goes before `file10`. Strings that are equal
+/// in this sense (e.g. `a01` and `a1`) fall back to plain string order.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a_chars = a.chars().peekable();
+    let mut b_chars = b.chars().peekable();
+
+    loop {
+        match (a_chars.peek(), b_chars.peek()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(ac), Some(bc)) if ac.is_ascii_digit() && bc.is_ascii_digit() => {
+                let mut a_num = String::new();
+                while let Some(c) = a_chars.next_if(|c| c.is_ascii_digit()) {
+                    a_num.push(c);
+                }
+                let mut b_num = String::new();
+                while let Some(c) = b_chars.next_if(|c| c.is_ascii_digit()) {
+                    b_num.push(c);
+                }
+
+                let a_num = a_num.trim_start_matches('0');
+                let b_num = b_num.trim_start_matches('0');
+                let ord = a_num.len().cmp(&b_num.len()).then_with(|| a_num.cmp(b_num));
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+            }
+            (Some(ac), Some(bc)) => {
+                let ord = ac.cmp(bc);
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+                a_chars.next();
+                b_chars.next();
+            }
+        }
+    }
+}
+
 #[cfg(windows)]
 pub fn calc_depth(s: &str) -> u32 {
     s.matches("\\").count() as u32
@@ -614,6 +686,28 @@ pub fn format_absolute_path(path_buf: &Path) -> String {
     path
 }
 
+/// Identifies the physical file behind a directory entry: device and inode
+/// numbers on Unix, the canonical path elsewhere.
+#[cfg(unix)]
+pub type FileIdentity = (u64, u64);
+#[cfg(not(unix))]
+pub type FileIdentity = PathBuf;
+
+#[cfg(unix)]
+pub fn get_file_identity(entry: &DirEntry, follow_symlinks: bool) -> Option<FileIdentity> {
+    use std::os::unix::fs::MetadataExt;
+
+    get_metadata(entry, follow_symlinks).map(|metadata| (metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+pub fn get_file_identity(entry: &DirEntry, follow_symlinks: bool) -> Option<FileIdentity> {
+    match follow_symlinks {
+        true => canonicalize(entry.path()).ok(),
+        false => entry.path().parent().and_then(|p| canonicalize(p).ok()).map(|p| p.join(entry.file_name())),
+    }
+}
+
 pub fn get_metadata(entry: &DirEntry, follow_symlinks: bool) -> Option<Metadata> {
     let metadata = match follow_symlinks {
         true => fs::metadata(entry.path()),
@@ -883,8 +977,12 @@ fn compute_content_stats(bytes: &[u8]) -> ContentStats {
     }
 }
 
-pub fn get_content_stats(entry: &DirEntry) -> Option<ContentStats> {
+/// Content stats of the file, or of its decompressed content with `decompress`.
+pub fn get_content_stats(entry: &DirEntry, decompress: bool) -> Option<ContentStats> {
     let file = File::open(entry.path()).ok()?;
+    if decompress {
+        return content_stats_from_reader(self::decompress::decompress(BufReader::new(file)).ok()?);
+    }
     content_stats_from_reader(file)
 }
 
@@ -1062,7 +1160,8 @@ fn read_up_to_4<R: Read>(reader: &mut R, buf: &mut [u8; 4]) -> Option<usize> {
     Some(filled)
 }
 
-fn content_stats_from_reader<R: Read>(mut reader: R) -> Option<ContentStats> {
+/// Content stats of whatever the reader yields, e.g. an entry of an archive.
+pub fn content_stats_from_reader<R: Read>(mut reader: R) -> Option<ContentStats> {
     let mut head = [0u8; 4];
     let head_len = read_up_to_4(&mut reader, &mut head)?;
     let head = &head[..head_len];
@@ -1406,45 +1505,6 @@ impl ContentSink for Utf32Sink {
     }
 }
 
-fn hash_file<D: sha1::Digest>(entry: &DirEntry) -> String {
-    if let Ok(mut file) = File::open(entry.path()) {
-        let mut hasher = D::new();
-        let mut buf = [0u8; 8192];
-        loop {
-            match file.read(&mut buf) {
-                Ok(0) => break,
-                Ok(n) => hasher.update(&buf[..n]),
-                Err(_) => return String::new(),
-            }
-        }
-        let hash = hasher.finalize();
-        let mut hex = String::with_capacity(hash.len() * 2);
-        for byte in hash.iter() {
-            use std::fmt::Write;
-            let _ = write!(hex, "{:02x}", byte);
-        }
-        return hex;
-    }
-
-    String::new()
-}
-
-pub fn get_sha1_file_hash(entry: &DirEntry) -> String {
-    hash_file::<sha1::Sha1>(entry)
-}
-
-pub fn get_sha256_file_hash(entry: &DirEntry) -> String {
-    hash_file::<sha2::Sha256>(entry)
-}
-
-pub fn get_sha512_file_hash(entry: &DirEntry) -> String {
-    hash_file::<sha2::Sha512>(entry)
-}
-
-pub fn get_sha3_512_file_hash(entry: &DirEntry) -> String {
-    hash_file::<sha3::Sha3_512>(entry)
-}
-
 pub fn is_dir_empty(entry: &DirEntry) -> Option<bool> {
     match fs::read_dir(entry.path()) {
         Ok(mut dir) => Some(dir.next().is_none()),
@@ -1457,6 +1517,21 @@ mod tests {
     use super::*;
     use crate::field::Field;
 
+    #[test]
+    fn test_natural_cmp() {
+        assert_eq!(natural_cmp("file2", "file10"), Ordering::Less);
+        assert_eq!(natural_cmp("file10", "file2"), Ordering::Greater);
+        assert_eq!(natural_cmp("a", "a1"), Ordering::Less);
+        assert_eq!(natural_cmp("x1y2", "x1y10"), Ordering::Less);
+        assert_eq!(natural_cmp("img007", "img7"), Ordering::Less);
+        assert_eq!(natural_cmp("abc", "abd"), Ordering::Less);
+        assert_eq!(natural_cmp("same", "same"), Ordering::Equal);
+
+        let mut names = vec!["v1.10", "v1.9", "v1.2", "readme", "v10.0"];
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(names, vec!["readme", "v1.2", "v1.9", "v1.10", "v10.0"]);
+    }
+
     #[test]
     fn topn_equal_criteria_keys_accumulate_values() {
         // The grouped-results path inserts every group under equal (possibly
@@ -1947,6 +2022,7 @@ mod tests {
             .unwrap()
             .filter_map(|e| e.ok())
             .find(|e| e.file_name() == target)
+            .map(DirEntry::from)
             .unwrap()
     }
 
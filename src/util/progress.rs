@@ -0,0 +1,174 @@
+//! Status line shown on stderr by `--progress` while a search runs.
+
+use std::cell::RefCell;
+use std::io::{stderr, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::rc::{Rc, Weak};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::util::format_filesize;
+
+/// How often the status line is redrawn
+const UPDATE_INTERVAL: Duration = Duration::from_millis(250);
+/// Longest tail of the current directory shown
+const MAX_DIR_WIDTH: usize = 60;
+
+/// Bytes read by all the hashing functions, which don't know about the searcher
+static HASHED_BYTES: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    /// Status line of the search running on this thread, redrawn while a
+    /// large file is being hashed
+    static CURRENT_STATUS: RefCell<Weak<RefCell<Status>>> = const { RefCell::new(Weak::new()) };
+}
+
+pub fn add_hashed_bytes(count: usize) {
+    HASHED_BYTES.fetch_add(count as u64, Ordering::Relaxed);
+
+    CURRENT_STATUS.with(|current| {
+        if let Some(status) = current.borrow().upgrade()
+            && let Ok(mut status) = status.try_borrow_mut() {
+                status.redraw();
+            }
+    });
+}
+
+pub struct Progress {
+    enabled: bool,
+    status: Rc<RefCell<Status>>,
+}
+
+struct Status {
+    dirs: u64,
+    entries: u64,
+    matches: u32,
+    current_dir: PathBuf,
+    hashed_bytes_at_start: u64,
+    last_update: Instant,
+    shown: bool,
+}
+
+impl Progress {
+    /// Progress is reported only to a terminal, so it never ends up mixed
+    /// into redirected output.
+    pub fn new(enabled: bool) -> Progress {
+        let status = Status {
+            dirs: 0,
+            entries: 0,
+            matches: 0,
+            current_dir: PathBuf::new(),
+            hashed_bytes_at_start: HASHED_BYTES.load(Ordering::Relaxed),
+            last_update: Instant::now(),
+            shown: false,
+        };
+
+        Progress {
+            enabled: enabled && stderr().is_terminal(),
+            status: Rc::new(RefCell::new(status)),
+        }
+    }
+
+    pub fn disabled() -> Progress {
+        Progress::new(false)
+    }
+
+    pub fn enter_dir(&mut self, dir: &Path) {
+        if self.enabled {
+            let mut status = self.status.borrow_mut();
+            status.dirs += 1;
+            status.current_dir = dir.to_path_buf();
+        }
+    }
+
+    /// Redraws the status line if it's time to. From then on, hashing redraws
+    /// it too, with the counts given here.
+    pub fn update(&mut self, entries: u64, matches: u32) {
+        if !self.enabled {
+            return;
+        }
+
+        CURRENT_STATUS.with(|current| *current.borrow_mut() = Rc::downgrade(&self.status));
+
+        let mut status = self.status.borrow_mut();
+        status.entries = entries;
+        status.matches = matches;
+        status.redraw();
+    }
+
+    /// Erases the status line, so that output to the same terminal starts
+    /// on a clean line.
+    pub fn clear(&mut self) {
+        let mut status = self.status.borrow_mut();
+        if status.shown {
+            let mut stderr = stderr().lock();
+            let _ = write!(stderr, "\r\x1b[2K");
+            let _ = stderr.flush();
+            status.shown = false;
+        }
+    }
+}
+
+impl Status {
+    /// Redraws the status line, at most once per `UPDATE_INTERVAL`.
+    fn redraw(&mut self) {
+        if self.last_update.elapsed() < UPDATE_INTERVAL {
+            return;
+        }
+        self.last_update = Instant::now();
+
+        let hashed_bytes = HASHED_BYTES.load(Ordering::Relaxed) - self.hashed_bytes_at_start;
+        let dir = self.current_dir.to_string_lossy();
+        let dir = match dir.char_indices().rev().nth(MAX_DIR_WIDTH - 1) {
+            Some((i, _)) if i > 0 => format!("...{}", &dir[i..]),
+            _ => dir.to_string(),
+        };
+
+        let mut stderr = stderr().lock();
+        let _ = write!(
+            stderr,
+            "\r\x1b[2K{} dirs, {} entries, {} matches, {} hashed, {}",
+            self.dirs,
+            self.entries,
+            self.matches,
+            format_filesize(hashed_bytes, "").unwrap_or_default(),
+            dir
+        );
+        let _ = stderr.flush();
+        self.shown = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disabled_progress_stays_quiet() {
+        let mut progress = Progress::disabled();
+        progress.enter_dir(Path::new("/tmp"));
+        progress.status.borrow_mut().last_update = Instant::now() - UPDATE_INTERVAL;
+        progress.update(10, 1);
+        add_hashed_bytes(1);
+
+        assert_eq!(progress.status.borrow().dirs, 0);
+        assert!(!progress.status.borrow().shown);
+    }
+
+    #[test]
+    fn test_hashing_redraws_status_line() {
+        let mut progress = Progress::disabled();
+        progress.enabled = true;
+        progress.update(10, 1);
+        assert!(!progress.status.borrow().shown);
+
+        // Bytes hashed after the interval passed redraw the line with the last counts
+        progress.status.borrow_mut().last_update = Instant::now() - UPDATE_INTERVAL;
+        add_hashed_bytes(1);
+        assert!(progress.status.borrow().shown);
+        assert_eq!(progress.status.borrow().entries, 10);
+
+        progress.clear();
+        assert!(!progress.status.borrow().shown);
+    }
+}
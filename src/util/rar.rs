@@ -0,0 +1,495 @@
+//! Listing of RAR archives. Only the headers are read: the entries can't be
+//! decompressed, and archives with encrypted headers list nothing.
+
+use std::io::{self, BufReader, ErrorKind, Read, Seek};
+use std::time::{Duration, UNIX_EPOCH};
+
+use chrono::{NaiveDate, NaiveDateTime};
+
+use crate::fileinfo::FileInfo;
+use crate::util::datetime::system_time_to_naive_local;
+
+const RAR4_SIGNATURE: &[u8] = b"Rar!\x1a\x07\x00";
+const RAR5_SIGNATURE: &[u8] = b"Rar!\x1a\x07\x01\x00";
+
+const RAR4_MAIN_HEADER: u8 = 0x73;
+const RAR4_FILE_HEADER: u8 = 0x74;
+const RAR4_END_HEADER: u8 = 0x7b;
+const RAR4_MAIN_ENCRYPTED: u16 = 0x0080;
+const RAR4_SPLIT_BEFORE: u16 = 0x0001;
+const RAR4_ENCRYPTED: u16 = 0x0004;
+const RAR4_DIRECTORY: u16 = 0x00e0;
+const RAR4_LARGE_FILE: u16 = 0x0100;
+const RAR4_LONG_BLOCK: u16 = 0x8000;
+
+const RAR5_FILE_HEADER: u64 = 2;
+const RAR5_ENCRYPTION_HEADER: u64 = 4;
+const RAR5_END_HEADER: u64 = 5;
+const RAR5_EXTRA_AREA: u64 = 0x0001;
+const RAR5_DATA_AREA: u64 = 0x0002;
+const RAR5_SPLIT_BEFORE: u64 = 0x0008;
+const RAR5_DIRECTORY: u64 = 0x0001;
+const RAR5_MTIME: u64 = 0x0002;
+const RAR5_CRC32: u64 = 0x0004;
+const RAR5_FILE_ENCRYPTION_RECORD: u64 = 1;
+/// The header size takes at most 3 bytes, so headers are never bigger than 2 MB
+const RAR5_MAX_HEADER_SIZE: u64 = 2 * 1024 * 1024;
+
+const HOST_OS_RAR4_UNIX: u8 = 3;
+const HOST_OS_RAR5_UNIX: u64 = 1;
+
+/// Compression methods from the fastest to the best compressing
+const METHODS: [&str; 6] = ["stored", "fastest", "fast", "normal", "good", "best"];
+
+/// Lists the entries of a RAR 4 or RAR 5 archive. Entries continued from
+/// a previous volume are skipped, so that every file is listed once.
+pub fn list_rar_entries<R: Read + Seek>(reader: R) -> io::Result<Vec<FileInfo>> {
+    let mut reader = BufReader::new(reader);
+
+    let mut signature = [0u8; 8];
+    reader.read_exact(&mut signature[..RAR4_SIGNATURE.len()])?;
+    if signature[..RAR4_SIGNATURE.len()] == *RAR4_SIGNATURE {
+        return list_rar4_entries(reader);
+    }
+
+    reader.read_exact(&mut signature[RAR4_SIGNATURE.len()..])?;
+    if signature == RAR5_SIGNATURE {
+        return list_rar5_entries(reader);
+    }
+
+    Err(io::Error::new(ErrorKind::InvalidData, "not a RAR archive"))
+}
+
+fn list_rar4_entries<R: Read + Seek>(mut reader: BufReader<R>) -> io::Result<Vec<FileInfo>> {
+    let mut entries = vec![];
+
+    loop {
+        // CRC, type, flags and the size of the whole header
+        let mut base = [0u8; 7];
+        if !read_or_eof(&mut reader, &mut base)? {
+            break;
+        }
+
+        let header_type = base[2];
+        let flags = u16::from_le_bytes([base[3], base[4]]);
+        let header_size = u16::from_le_bytes([base[5], base[6]]) as usize;
+        if header_size < base.len() {
+            return Err(invalid_header());
+        }
+
+        let mut header = vec![0u8; header_size - base.len()];
+        reader.read_exact(&mut header)?;
+
+        let mut data_size = if flags & RAR4_LONG_BLOCK != 0 { read_u32(&header, 0)? as u64 } else { 0 };
+
+        match header_type {
+            RAR4_MAIN_HEADER if flags & RAR4_MAIN_ENCRYPTED != 0 => break,
+            RAR4_END_HEADER => break,
+            RAR4_FILE_HEADER => {
+                let size_low = read_u32(&header, 4)? as u64;
+                let host_os = *header.get(8).ok_or_else(invalid_header)?;
+                let crc32 = read_u32(&header, 9)?;
+                let dos_time = read_u32(&header, 13)?;
+                let attributes = read_u32(&header, 21)?;
+                let method = header[18].wrapping_sub(b'0') as usize;
+                let name_size = u16::from_le_bytes([header[19], header[20]]) as usize;
+
+                let (size, name_start) = if flags & RAR4_LARGE_FILE != 0 {
+                    data_size |= (read_u32(&header, 25)? as u64) << 32;
+                    (size_low | (read_u32(&header, 29)? as u64) << 32, 33)
+                } else {
+                    (size_low, 25)
+                };
+
+                let name = header.get(name_start..name_start + name_size).ok_or_else(invalid_header)?;
+                // A Unicode name follows the plain one after a zero byte
+                let name = name.split(|&b| b == 0).next().unwrap_or_default();
+                let mut name = String::from_utf8_lossy(name).replace('\\', "/");
+
+                let is_dir = flags & RAR4_DIRECTORY == RAR4_DIRECTORY;
+                if is_dir && !name.ends_with('/') {
+                    name.push('/');
+                }
+
+                if flags & RAR4_SPLIT_BEFORE == 0 {
+                    entries.push(FileInfo {
+                        name,
+                        size,
+                        mode: (host_os == HOST_OS_RAR4_UNIX).then_some(attributes),
+                        modified: from_dos_datetime(dos_time),
+                        compressed_size: Some(data_size),
+                        compression_method: METHODS.get(method).map(|method| method.to_string()),
+                        encrypted: Some(flags & RAR4_ENCRYPTED != 0),
+                        crc32: Some(crc32),
+                        ..Default::default()
+                    });
+                }
+            }
+            _ => {}
+        }
+
+        skip(&mut reader, data_size)?;
+    }
+
+    Ok(entries)
+}
+
+fn list_rar5_entries<R: Read + Seek>(mut reader: BufReader<R>) -> io::Result<Vec<FileInfo>> {
+    let mut entries = vec![];
+
+    loop {
+        let mut crc = [0u8; 4];
+        if !read_or_eof(&mut reader, &mut crc)? {
+            break;
+        }
+
+        // The size is checked before anything is allocated for a damaged header
+        let header_size = read_vint_from(&mut reader)?;
+        if header_size > RAR5_MAX_HEADER_SIZE {
+            return Err(invalid_header());
+        }
+        let mut header = vec![0u8; header_size as usize];
+        reader.read_exact(&mut header)?;
+
+        let mut pos = 0;
+        let header_type = read_vint(&header, &mut pos)?;
+        let header_flags = read_vint(&header, &mut pos)?;
+        let extra_size = if header_flags & RAR5_EXTRA_AREA != 0 { read_vint(&header, &mut pos)? } else { 0 };
+        let data_size = if header_flags & RAR5_DATA_AREA != 0 { read_vint(&header, &mut pos)? } else { 0 };
+
+        match header_type {
+            RAR5_ENCRYPTION_HEADER | RAR5_END_HEADER => break,
+            RAR5_FILE_HEADER => {
+                let file_flags = read_vint(&header, &mut pos)?;
+                let size = read_vint(&header, &mut pos)?;
+                let attributes = read_vint(&header, &mut pos)?;
+                let mtime = if file_flags & RAR5_MTIME != 0 {
+                    let mtime = read_u32(&header, pos)?;
+                    pos += 4;
+                    Some(mtime)
+                } else {
+                    None
+                };
+                let crc32 = if file_flags & RAR5_CRC32 != 0 {
+                    let crc32 = read_u32(&header, pos)?;
+                    pos += 4;
+                    Some(crc32)
+                } else {
+                    None
+                };
+                let compression = read_vint(&header, &mut pos)?;
+                let host_os = read_vint(&header, &mut pos)?;
+                let name_size = usize::try_from(read_vint(&header, &mut pos)?).map_err(|_| invalid_header())?;
+
+                let name = pos
+                    .checked_add(name_size)
+                    .and_then(|end| header.get(pos..end))
+                    .ok_or_else(invalid_header)?;
+                let mut name = String::from_utf8_lossy(name).to_string();
+                if file_flags & RAR5_DIRECTORY != 0 && !name.ends_with('/') {
+                    name.push('/');
+                }
+
+                if header_flags & RAR5_SPLIT_BEFORE == 0 {
+                    entries.push(FileInfo {
+                        name,
+                        size,
+                        mode: (host_os == HOST_OS_RAR5_UNIX).then_some(attributes as u32),
+                        modified: mtime.and_then(|mtime| {
+                            system_time_to_naive_local(UNIX_EPOCH + Duration::from_secs(mtime as u64))
+                        }),
+                        compressed_size: Some(data_size),
+                        compression_method: METHODS.get((compression >> 7 & 0x07) as usize).map(|method| method.to_string()),
+                        encrypted: Some(has_encryption_record(&header, extra_size)?),
+                        crc32,
+                        ..Default::default()
+                    });
+                }
+            }
+            _ => {}
+        }
+
+        skip(&mut reader, data_size)?;
+    }
+
+    Ok(entries)
+}
+
+/// Encrypted entries carry an encryption record in the extra area, which
+/// closes the header.
+fn has_encryption_record(header: &[u8], extra_size: u64) -> io::Result<bool> {
+    let mut pos = header
+        .len()
+        .checked_sub(usize::try_from(extra_size).map_err(|_| invalid_header())?)
+        .ok_or_else(invalid_header)?;
+
+    while pos < header.len() {
+        // Sizes from a damaged header could take the position back or past the end
+        let record_end = usize::try_from(read_vint(header, &mut pos)?)
+            .ok()
+            .and_then(|record_size| pos.checked_add(record_size))
+            .filter(|&record_end| record_end <= header.len())
+            .ok_or_else(invalid_header)?;
+        if read_vint(header, &mut pos)? == RAR5_FILE_ENCRYPTION_RECORD {
+            return Ok(true);
+        }
+        pos = record_end;
+    }
+
+    Ok(false)
+}
+
+fn invalid_header() -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, "damaged RAR header")
+}
+
+/// Fills the buffer, or returns false if the archive ends right away.
+fn read_or_eof(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<bool> {
+    match reader.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+fn skip<R: Read + Seek>(reader: &mut BufReader<R>, count: u64) -> io::Result<()> {
+    let count = i64::try_from(count).map_err(|_| invalid_header())?;
+    reader.seek_relative(count)
+}
+
+fn read_u32(buf: &[u8], pos: usize) -> io::Result<u32> {
+    let bytes = buf.get(pos..pos + 4).ok_or_else(invalid_header)?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// RAR 5 numbers keep 7 bits in every byte, the high bit tells if more follow.
+fn read_vint(buf: &[u8], pos: &mut usize) -> io::Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *buf.get(*pos).ok_or_else(invalid_header)?;
+        *pos += 1;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+
+    Err(invalid_header())
+}
+
+fn read_vint_from(reader: &mut impl Read) -> io::Result<u64> {
+    // A vint is at most 10 bytes long
+    let mut buf = vec![];
+    for _ in 0..10 {
+        let mut byte = [0u8; 1];
+        reader.read_exact(&mut byte)?;
+        buf.push(byte[0]);
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+    }
+
+    read_vint(&buf, &mut 0)
+}
+
+/// RAR 4 keeps the modification time in local time, the way MS-DOS did.
+fn from_dos_datetime(dos_time: u32) -> Option<NaiveDateTime> {
+    let date = (dos_time >> 16) as u16;
+    let time = dos_time as u16;
+
+    NaiveDate::from_ymd_opt(1980 + (date >> 9) as i32, ((date >> 5) & 0x0f) as u32, (date & 0x1f) as u32)?
+        .and_hms_opt((time >> 11) as u32, ((time >> 5) & 0x3f) as u32, ((time & 0x1f) * 2) as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn write_vint(buf: &mut Vec<u8>, mut value: u64) {
+        while value >= 0x80 {
+            buf.push((value as u8 & 0x7f) | 0x80);
+            value >>= 7;
+        }
+        buf.push(value as u8);
+    }
+
+    fn rar5_block(header_type: u64, header_flags: u64, body: &[u8], extra: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut header = vec![];
+        write_vint(&mut header, header_type);
+        write_vint(&mut header, header_flags);
+        if header_flags & RAR5_EXTRA_AREA != 0 {
+            write_vint(&mut header, extra.len() as u64);
+        }
+        if header_flags & RAR5_DATA_AREA != 0 {
+            write_vint(&mut header, data.len() as u64);
+        }
+        header.extend_from_slice(body);
+        header.extend_from_slice(extra);
+
+        // The CRC isn't checked while listing
+        let mut block = vec![0u8; 4];
+        write_vint(&mut block, header.len() as u64);
+        block.extend(header);
+        block.extend_from_slice(data);
+        block
+    }
+
+    fn rar5_file(file_flags: u64, size: u64, attributes: u64, mtime: Option<u32>, host_os: u64, name: &str) -> Vec<u8> {
+        let mut body = vec![];
+        write_vint(&mut body, file_flags | if mtime.is_some() { RAR5_MTIME } else { 0 });
+        write_vint(&mut body, size);
+        write_vint(&mut body, attributes);
+        if let Some(mtime) = mtime {
+            body.extend_from_slice(&mtime.to_le_bytes());
+        }
+        write_vint(&mut body, 0);
+        write_vint(&mut body, host_os);
+        write_vint(&mut body, name.len() as u64);
+        body.extend_from_slice(name.as_bytes());
+        body
+    }
+
+    fn rar4_file(flags: u16, size: u32, host_os: u8, dos_time: u32, attributes: u32, name: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut body = vec![];
+        body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        body.extend_from_slice(&size.to_le_bytes());
+        body.push(host_os);
+        body.extend_from_slice(&[0; 4]);
+        body.extend_from_slice(&dos_time.to_le_bytes());
+        body.extend_from_slice(&[29, 0x30]);
+        body.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        body.extend_from_slice(&attributes.to_le_bytes());
+        body.extend_from_slice(name);
+
+        let mut block = vec![0, 0, RAR4_FILE_HEADER];
+        block.extend_from_slice(&(flags | RAR4_LONG_BLOCK).to_le_bytes());
+        block.extend_from_slice(&((body.len() + 7) as u16).to_le_bytes());
+        block.extend(body);
+        block.extend_from_slice(data);
+        block
+    }
+
+    fn list(archive: Vec<u8>) -> Vec<FileInfo> {
+        list_rar_entries(Cursor::new(archive)).unwrap()
+    }
+
+    #[test]
+    fn test_list_rar5_entries() {
+        let mut archive = RAR5_SIGNATURE.to_vec();
+        archive.extend(rar5_block(1, 0, &[0], &[], &[]));
+        archive.extend(rar5_block(
+            RAR5_FILE_HEADER,
+            RAR5_DATA_AREA,
+            &rar5_file(0, 11, 0o100644, Some(1_600_000_000), HOST_OS_RAR5_UNIX, "docs/readme.txt"),
+            &[],
+            b"packed data",
+        ));
+        archive.extend(rar5_block(RAR5_FILE_HEADER, 0, &rar5_file(RAR5_DIRECTORY, 0, 0x10, None, 0, "docs"), &[], &[]));
+        archive.extend(rar5_block(
+            RAR5_FILE_HEADER,
+            RAR5_DATA_AREA | RAR5_SPLIT_BEFORE,
+            &rar5_file(0, 3, 0, None, 0, "continued.bin"),
+            &[],
+            b"abc",
+        ));
+        // An encryption record holding nothing but its type
+        archive.extend(rar5_block(
+            RAR5_FILE_HEADER,
+            RAR5_EXTRA_AREA | RAR5_DATA_AREA,
+            &rar5_file(0, 100, 0x20, None, 0, "secret.bin"),
+            &[1, RAR5_FILE_ENCRYPTION_RECORD as u8],
+            b"encrypted",
+        ));
+        archive.extend(rar5_block(RAR5_END_HEADER, 0, &[0], &[], &[]));
+
+        let entries = list(archive);
+
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].name, "docs/readme.txt");
+        assert_eq!(entries[0].size, 11);
+        assert_eq!(entries[0].compressed_size, Some(11));
+        assert_eq!(entries[0].compression_method.as_deref(), Some("stored"));
+        assert_eq!(entries[0].encrypted, Some(false));
+        assert_eq!(entries[0].mode, Some(0o100644));
+        assert_eq!(
+            entries[0].modified,
+            system_time_to_naive_local(UNIX_EPOCH + Duration::from_secs(1_600_000_000))
+        );
+        assert_eq!(entries[1].name, "docs/");
+        assert_eq!(entries[1].mode, None);
+        assert_eq!(entries[1].modified, None);
+        assert_eq!(entries[2].name, "secret.bin");
+        assert_eq!(entries[2].compressed_size, Some(9));
+        assert_eq!(entries[2].encrypted, Some(true));
+    }
+
+    #[test]
+    fn test_list_rar4_entries() {
+        // 2021-03-04 05:06:08
+        let dos_time = ((41 << 9 | 3 << 5 | 4) << 16) | (5 << 11 | 6 << 5 | 4);
+
+        let mut archive = RAR4_SIGNATURE.to_vec();
+        archive.extend_from_slice(&[0, 0, RAR4_MAIN_HEADER, 0, 0, 13, 0, 0, 0, 0, 0, 0, 0]);
+        archive.extend(rar4_file(0, 5, HOST_OS_RAR4_UNIX, dos_time, 0o100600, b"dir\\notes.txt", b"12345"));
+        archive.extend(rar4_file(RAR4_DIRECTORY, 0, 2, dos_time, 0x10, b"dir", &[]));
+        archive.extend_from_slice(&[0, 0, RAR4_END_HEADER, 0, 0, 7, 0]);
+
+        let entries = list(archive);
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].name, "dir/notes.txt");
+        assert_eq!(entries[0].size, 5);
+        assert_eq!(entries[0].compressed_size, Some(5));
+        assert_eq!(entries[0].compression_method.as_deref(), Some("stored"));
+        assert_eq!(entries[0].encrypted, Some(false));
+        assert_eq!(entries[0].mode, Some(0o100600));
+        assert_eq!(
+            entries[0].modified,
+            NaiveDate::from_ymd_opt(2021, 3, 4).unwrap().and_hms_opt(5, 6, 8)
+        );
+        assert_eq!(entries[1].name, "dir/");
+        assert_eq!(entries[1].mode, None);
+    }
+
+    #[test]
+    fn test_oversized_rar5_header() {
+        let mut archive = RAR5_SIGNATURE.to_vec();
+        archive.extend_from_slice(&[0; 4]);
+        write_vint(&mut archive, 1 << 60);
+        archive.extend_from_slice(&[RAR5_FILE_HEADER as u8, 0]);
+
+        let result = list_rar_entries(Cursor::new(archive));
+        assert!(result.is_err_and(|err| err.kind() == ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn test_overflowing_rar5_sizes() {
+        // A record size that would wrap the position around
+        let mut extra = vec![];
+        write_vint(&mut extra, u64::MAX - 1);
+        extra.push(RAR5_FILE_ENCRYPTION_RECORD as u8);
+        let mut archive = RAR5_SIGNATURE.to_vec();
+        archive.extend(rar5_block(RAR5_FILE_HEADER, RAR5_EXTRA_AREA, &rar5_file(0, 1, 0, None, 0, "a.txt"), &extra, &[]));
+
+        let result = list_rar_entries(Cursor::new(archive));
+        assert!(result.is_err_and(|err| err.kind() == ErrorKind::InvalidData));
+
+        // A name size that would overflow the end of the name
+        let mut body = rar5_file(0, 1, 0, None, 0, "");
+        body.pop();
+        write_vint(&mut body, u64::MAX);
+        let mut archive = RAR5_SIGNATURE.to_vec();
+        archive.extend(rar5_block(RAR5_FILE_HEADER, 0, &body, &[], &[]));
+
+        let result = list_rar_entries(Cursor::new(archive));
+        assert!(result.is_err_and(|err| err.kind() == ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn test_not_a_rar_archive() {
+        let result = list_rar_entries(Cursor::new(b"PK\x03\x04 definitely not rar"));
+        assert!(result.is_err());
+    }
+}
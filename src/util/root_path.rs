@@ -0,0 +1,446 @@
+//! Search root path handling: shell-like expansion of `~`, environment
+//! variables, `{a,b}` braces and `*` / `?` / `**` globs, and reading
+//! of path lists for the `-` (stdin) and `@file` roots
+
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+
+#[cfg(feature = "interactive")]
+use directories::UserDirs;
+
+/// Root path that reads the list of files from the standard input.
+pub const STDIN_ROOT: &str = "-";
+
+/// Prefix of a root path that reads the list of files from a file.
+pub const PATH_LIST_ROOT_PREFIX: char = '@';
+
+/// Checks if the root is a list of files to examine rather than a directory to walk.
+/// Directories named with a leading `@`, like `@types` in `node_modules`, are walked as usual.
+pub fn is_path_list_root(path: &str) -> bool {
+    is_path_list_root_in(path, Path::new(""))
+}
+
+/// Same as `is_path_list_root`, with a relative path taken from `base_dir`.
+fn is_path_list_root_in(path: &str, base_dir: &Path) -> bool {
+    path == STDIN_ROOT || (path.starts_with(PATH_LIST_ROOT_PREFIX) && !base_dir.join(path).is_dir())
+}
+
+/// Reads a list of paths separated either by NUL characters (as produced by
+/// `find -print0` or `git ls-files -z`) or by newlines. NUL separation is
+/// assumed as soon as the input contains a NUL byte. Empty items are skipped.
+pub fn read_path_list<R: Read>(mut reader: R) -> io::Result<Vec<PathBuf>> {
+    let mut buf = vec![];
+    reader.read_to_end(&mut buf)?;
+
+    let separator = if buf.contains(&0) { b'\0' } else { b'\n' };
+
+    Ok(buf
+        .split(|&b| b == separator)
+        .map(|item| match separator {
+            b'\n' => item.strip_suffix(b"\r").unwrap_or(item),
+            _ => item,
+        })
+        .filter(|item| !item.is_empty())
+        .map(bytes_to_path)
+        .collect())
+}
+
+#[cfg(unix)]
+fn bytes_to_path(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+fn bytes_to_path(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).to_string())
+}
+
+/// Expands a root path into the list of paths it denotes.
+///
+/// `~` and `$VAR` / `${VAR}` are substituted first, then braces are expanded,
+/// and finally every alternative containing glob characters is matched
+/// against the file system. Only directories are taken as glob matches.
+/// A pattern matching nothing is kept verbatim, so that a mistyped root
+/// is still reported as a missing directory rather than silently dropped.
+pub fn expand_root_path(path: &str) -> Vec<String> {
+    let path = expand_env_vars(&expand_home_dir(path));
+
+    let mut result: Vec<String> = vec![];
+
+    for alternative in expand_braces(&path) {
+        let expanded = if has_glob_chars(&alternative) {
+            let matches = expand_glob(&alternative);
+            if matches.is_empty() {
+                vec![alternative]
+            } else {
+                matches
+            }
+        } else {
+            vec![alternative]
+        };
+
+        for item in expanded {
+            if !result.contains(&item) {
+                result.push(item);
+            }
+        }
+    }
+
+    result
+}
+
+#[cfg(feature = "interactive")]
+fn expand_home_dir(path: &str) -> String {
+    let rest = match path.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') || rest.starts_with(std::path::MAIN_SEPARATOR) => rest,
+        _ => return path.to_string(),
+    };
+
+    match UserDirs::new() {
+        Some(ud) => {
+            let rest = rest.trim_start_matches(['/', std::path::MAIN_SEPARATOR]);
+            let home = ud.home_dir().to_path_buf();
+            if rest.is_empty() {
+                home.to_string_lossy().to_string()
+            } else {
+                home.join(rest).to_string_lossy().to_string()
+            }
+        }
+        None => path.to_string(),
+    }
+}
+
+#[cfg(not(feature = "interactive"))]
+fn expand_home_dir(path: &str) -> String {
+    path.to_string()
+}
+
+/// Substitutes `$NAME` and `${NAME}` with the value of the environment
+/// variable. Unset variables are left untouched.
+fn expand_env_vars(path: &str) -> String {
+    let mut result = String::with_capacity(path.len());
+    let mut rest = path;
+
+    while let Some(pos) = rest.find('$') {
+        result.push_str(&rest[..pos]);
+        let after = &rest[pos + 1..];
+
+        let (name, consumed) = if let Some(braced) = after.strip_prefix('{') {
+            match braced.find('}') {
+                Some(end) => (&braced[..end], end + 2),
+                None => ("", 0),
+            }
+        } else {
+            let end = after
+                .char_indices()
+                .find(|&(i, c)| !(c == '_' || c.is_ascii_alphabetic() || (i > 0 && c.is_ascii_digit())))
+                .map(|(i, _)| i)
+                .unwrap_or(after.len());
+            (&after[..end], end)
+        };
+
+        match std::env::var(name) {
+            Ok(value) if !name.is_empty() => {
+                result.push_str(&value);
+                rest = &after[consumed..];
+            }
+            _ => {
+                result.push('$');
+                rest = after;
+            }
+        }
+    }
+
+    result.push_str(rest);
+    result
+}
+
+/// Expands `{a,b,c}` alternatives, including nested ones. Braces without
+/// a top-level comma are kept literally.
+fn expand_braces(path: &str) -> Vec<String> {
+    let mut search_from = 0;
+
+    while let Some(offset) = path[search_from..].find('{') {
+        let open = search_from + offset;
+        let mut depth = 0;
+        let mut commas = vec![];
+        let mut close = None;
+
+        for (i, c) in path[open..].char_indices() {
+            match c {
+                '{' => depth += 1,
+                '}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        close = Some(open + i);
+                        break;
+                    }
+                }
+                ',' if depth == 1 => commas.push(open + i),
+                _ => {}
+            }
+        }
+
+        let close = match close {
+            Some(close) => close,
+            None => break,
+        };
+
+        if commas.is_empty() {
+            search_from = open + 1;
+            continue;
+        }
+
+        let prefix = &path[..open];
+        let suffix = &path[close + 1..];
+
+        let mut bounds = vec![open];
+        bounds.extend(commas);
+        bounds.push(close);
+
+        return bounds
+            .windows(2)
+            .flat_map(|w| expand_braces(&format!("{}{}{}", prefix, &path[w[0] + 1..w[1]], suffix)))
+            .collect();
+    }
+
+    vec![path.to_string()]
+}
+
+fn has_glob_chars(s: &str) -> bool {
+    s.contains('*') || s.contains('?')
+}
+
+fn expand_glob(pattern: &str) -> Vec<String> {
+    let mut bases: Vec<PathBuf> = vec![PathBuf::new()];
+
+    for component in Path::new(pattern).components() {
+        let name = match component {
+            Component::Normal(name) => name.to_string_lossy().to_string(),
+            other => {
+                bases.iter_mut().for_each(|base| base.push(other.as_os_str()));
+                continue;
+            }
+        };
+
+        if name == "**" {
+            let mut next = vec![];
+            for base in &bases {
+                next.push(base.clone());
+                collect_subdirs(base, &mut next);
+            }
+            bases = next;
+        } else if has_glob_chars(&name) {
+            let mut next = vec![];
+            for base in &bases {
+                let dir = if base.as_os_str().is_empty() { Path::new(".") } else { base.as_path() };
+                let mut matched = list_subdirs(dir, true)
+                    .into_iter()
+                    .filter(|dir_name| matches_glob(&name, dir_name))
+                    .map(|dir_name| base.join(dir_name))
+                    .collect::<Vec<_>>();
+                matched.sort();
+                next.extend(matched);
+            }
+            bases = next;
+        } else {
+            bases.iter_mut().for_each(|base| base.push(&name));
+            bases.retain(|base| base.is_dir());
+        }
+
+        if bases.is_empty() {
+            break;
+        }
+    }
+
+    bases
+        .into_iter()
+        .filter(|base| base.is_dir())
+        .map(|base| base.to_string_lossy().to_string())
+        .collect()
+}
+
+fn list_subdirs(dir: &Path, follow_symlinks: bool) -> Vec<String> {
+    match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .flatten()
+            .filter(|entry| match entry.file_type() {
+                Ok(ft) if ft.is_symlink() => follow_symlinks && entry.path().is_dir(),
+                Ok(ft) => ft.is_dir(),
+                Err(_) => false,
+            })
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .collect(),
+        Err(_) => vec![],
+    }
+}
+
+/// Collects all non-hidden subdirectories of `base` recursively, without
+/// following symlinks.
+fn collect_subdirs(base: &Path, result: &mut Vec<PathBuf>) {
+    let dir = if base.as_os_str().is_empty() { Path::new(".") } else { base };
+    let mut names = list_subdirs(dir, false);
+    names.sort();
+
+    for name in names.into_iter().filter(|name| !name.starts_with('.')) {
+        let path = base.join(name);
+        result.push(path.clone());
+        collect_subdirs(&path, result);
+    }
+}
+
+/// Matches a single path component against a pattern with `*` and `?`.
+/// As in the shell, a leading dot must be matched explicitly.
+fn matches_glob(pattern: &str, name: &str) -> bool {
+    if name.starts_with('.') && !pattern.starts_with('.') {
+        return false;
+    }
+
+    #[cfg(windows)]
+    let (pattern, name) = (pattern.to_lowercase(), name.to_lowercase());
+
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = backtrack {
+            p = star_p + 1;
+            n = star_n + 1;
+            backtrack = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_braces() {
+        assert_eq!(expand_braces("/tmp/{a,b}"), vec!["/tmp/a", "/tmp/b"]);
+        assert_eq!(expand_braces("{x,y}/{1,2}"), vec!["x/1", "x/2", "y/1", "y/2"]);
+        assert_eq!(expand_braces("a{b,c{d,e}}f"), vec!["abf", "acdf", "acef"]);
+        assert_eq!(expand_braces("a{b}c"), vec!["a{b}c"]);
+        assert_eq!(expand_braces("a{b,c"), vec!["a{b,c"]);
+        assert_eq!(expand_braces("{}/{a,}"), vec!["{}/a", "{}/"]);
+    }
+
+    #[test]
+    fn test_expand_env_vars() {
+        // Cargo exports the manifest dir to test processes
+        let dir = env!("CARGO_MANIFEST_DIR");
+
+        assert_eq!(expand_env_vars("$CARGO_MANIFEST_DIR/src"), format!("{}/src", dir));
+        assert_eq!(expand_env_vars("${CARGO_MANIFEST_DIR}src"), format!("{}src", dir));
+        assert_eq!(expand_env_vars("/a/$FSELECT_TEST_UNSET_VAR/b"), "/a/$FSELECT_TEST_UNSET_VAR/b");
+        assert_eq!(expand_env_vars("cost$"), "cost$");
+        assert_eq!(expand_env_vars("${unterminated"), "${unterminated");
+    }
+
+    #[test]
+    fn test_matches_glob() {
+        assert!(matches_glob("*", "src"));
+        assert!(matches_glob("sr?", "src"));
+        assert!(matches_glob("*-old", "logs-old"));
+        assert!(matches_glob("a*b*c", "aXbYbZc"));
+        assert!(!matches_glob("a*b", "acd"));
+        assert!(!matches_glob("*", ".git"));
+        assert!(matches_glob(".*", ".git"));
+    }
+
+    #[test]
+    fn test_expand_glob_against_dir() {
+        let base = std::env::temp_dir().join("fselect_test_root_path_glob");
+        let _ = fs::remove_dir_all(&base);
+        fs::create_dir_all(base.join("proj1/logs")).unwrap();
+        fs::create_dir_all(base.join("proj2/logs")).unwrap();
+        fs::create_dir_all(base.join("proj3/deep/logs")).unwrap();
+        fs::create_dir_all(base.join("other")).unwrap();
+        fs::write(base.join("projfile"), b"not a dir").unwrap();
+
+        let b = base.to_string_lossy().to_string();
+
+        let single = expand_root_path(&format!("{}/proj*", b));
+        assert_eq!(
+            single,
+            vec![format!("{}/proj1", b), format!("{}/proj2", b), format!("{}/proj3", b)]
+        );
+
+        let logs = expand_root_path(&format!("{}/*/logs", b));
+        assert_eq!(logs, vec![format!("{}/proj1/logs", b), format!("{}/proj2/logs", b)]);
+
+        let deep = expand_root_path(&format!("{}/**/logs", b));
+        assert_eq!(
+            deep,
+            vec![
+                format!("{}/proj1/logs", b),
+                format!("{}/proj2/logs", b),
+                format!("{}/proj3/deep/logs", b),
+            ]
+        );
+
+        let braces = expand_root_path(&format!("{}/{{other,proj1}}", b));
+        assert_eq!(braces, vec![format!("{}/other", b), format!("{}/proj1", b)]);
+
+        let missing = expand_root_path(&format!("{}/nothing*", b));
+        assert_eq!(missing, vec![format!("{}/nothing*", b)]);
+
+        let _ = fs::remove_dir_all(&base);
+    }
+
+    #[test]
+    fn test_read_path_list_newline_separated() {
+        let input = "a.txt\nsub/b.txt\r\n\n/abs/c.txt\n";
+        let paths = read_path_list(input.as_bytes()).unwrap();
+        assert_eq!(
+            paths,
+            vec![PathBuf::from("a.txt"), PathBuf::from("sub/b.txt"), PathBuf::from("/abs/c.txt")]
+        );
+    }
+
+    #[test]
+    fn test_read_path_list_nul_separated() {
+        let input = b"with\nnewline.txt\0plain.txt\0\0";
+        let paths = read_path_list(&input[..]).unwrap();
+        assert_eq!(paths, vec![PathBuf::from("with\nnewline.txt"), PathBuf::from("plain.txt")]);
+    }
+
+    #[test]
+    fn test_is_path_list_root() {
+        assert!(is_path_list_root("-"));
+        assert!(is_path_list_root("@files.txt"));
+        assert!(!is_path_list_root("/tmp"));
+        assert!(!is_path_list_root("-dir"));
+
+        // A relative path, as a leading `@` is only seen in relative paths
+        let base_dir = std::env::temp_dir().join("fselect_test_is_path_list_root");
+        let _ = fs::remove_dir_all(&base_dir);
+        fs::create_dir_all(base_dir.join("@types")).unwrap();
+        let is_dir_list = is_path_list_root_in("@types", &base_dir);
+        let is_missing_list = is_path_list_root_in("@files.txt", &base_dir);
+        let _ = fs::remove_dir_all(&base_dir);
+        assert!(!is_dir_list);
+        assert!(is_missing_list);
+    }
+
+    #[test]
+    fn test_expand_plain_path_unchanged() {
+        assert_eq!(expand_root_path("/some/plain/path"), vec!["/some/plain/path"]);
+        assert_eq!(expand_root_path("."), vec!["."]);
+    }
+}
@@ -0,0 +1,127 @@
+//! Holes of sparse files through SEEK_HOLE/SEEK_DATA, and their extents through FIEMAP on Linux.
+
+use std::fs::File;
+use std::io;
+use std::os::unix::io::AsRawFd;
+
+/// FS_IOC_FIEMAP ioctl request number.
+/// Computed as _IOWR('f', 11, struct fiemap) = (3 << 30) | (sizeof(struct fiemap) << 16) | ('f' << 8) | 11
+const fn fs_ioc_fiemap() -> libc::c_ulong {
+    let dir: libc::c_ulong = 3; // _IOC_READ | _IOC_WRITE
+    let ty: libc::c_ulong = b'f' as libc::c_ulong;
+    let nr: libc::c_ulong = 11;
+    let size: libc::c_ulong = std::mem::size_of::<Fiemap>() as libc::c_ulong;
+    (dir << 30) | (size << 16) | (ty << 8) | nr
+}
+
+/// Header of `struct fiemap` from linux/fiemap.h. With `fm_extent_count` set
+/// to zero the kernel only counts the extents into `fm_mapped_extents`
+/// and doesn't need the trailing array of extent records.
+#[repr(C)]
+struct Fiemap {
+    fm_start: u64,
+    fm_length: u64,
+    fm_flags: u32,
+    fm_mapped_extents: u32,
+    fm_extent_count: u32,
+    fm_reserved: u32,
+}
+
+/// Sums the lengths of all holes in the file, walking it with
+/// `lseek(SEEK_DATA)` / `lseek(SEEK_HOLE)`. File systems that don't track
+/// holes report the whole file as data, so the result is zero for them.
+pub fn get_hole_bytes(file: &File, len: u64) -> Option<u64> {
+    let fd = file.as_raw_fd();
+    let len = len as libc::off_t;
+    let mut pos: libc::off_t = 0;
+    let mut holes: libc::off_t = 0;
+
+    while pos < len {
+        let data = unsafe { libc::lseek(fd, pos, libc::SEEK_DATA) };
+        if data < 0 {
+            // No more data past this offset: the rest of the file is a hole
+            return match io::Error::last_os_error().raw_os_error() {
+                Some(libc::ENXIO) => Some((holes + len - pos) as u64),
+                _ => None,
+            };
+        }
+
+        holes += data - pos;
+
+        let hole = unsafe { libc::lseek(fd, data, libc::SEEK_HOLE) };
+        if hole < 0 {
+            return None;
+        }
+
+        pos = hole;
+    }
+
+    Some(holes as u64)
+}
+
+/// Returns the number of extents the file data is stored in, as reported
+/// by the `FIEMAP` ioctl. `None` if the file system doesn't support it.
+pub fn get_extent_count(file: &File) -> Option<u64> {
+    let fd = file.as_raw_fd();
+    let mut fiemap = Fiemap {
+        fm_start: 0,
+        fm_length: u64::MAX,
+        // No FIEMAP_FLAG_SYNC: a search must not force the writeback of dirty
+        // pages. Extents of delayed allocations are counted as such anyway.
+        fm_flags: 0,
+        fm_mapped_extents: 0,
+        fm_extent_count: 0,
+        fm_reserved: 0,
+    };
+
+    let ret = unsafe { libc::ioctl(fd, fs_ioc_fiemap() as _, &mut fiemap) };
+    if ret == 0 {
+        Some(fiemap.fm_mapped_extents as u64)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Seek, SeekFrom, Write};
+
+    #[test]
+    fn test_fiemap_request_number() {
+        assert_eq!(fs_ioc_fiemap(), 0xC020660B);
+    }
+
+    #[test]
+    fn test_hole_bytes_of_dense_file() {
+        let path = std::env::temp_dir().join("fselect_test_sparse_dense");
+        std::fs::write(&path, vec![1u8; 8192]).unwrap();
+
+        let file = File::open(&path).unwrap();
+        let holes = get_hole_bytes(&file, 8192);
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(holes, Some(0));
+    }
+
+    #[test]
+    fn test_hole_bytes_of_sparse_file() {
+        let path = std::env::temp_dir().join("fselect_test_sparse_holes");
+        let len: u64 = 16 * 1024 * 1024;
+        {
+            let mut file = File::create(&path).unwrap();
+            file.write_all(&[1u8; 4096]).unwrap();
+            file.seek(SeekFrom::Start(len - 1)).unwrap();
+            file.write_all(&[1u8]).unwrap();
+        }
+
+        let file = File::open(&path).unwrap();
+        let holes = get_hole_bytes(&file, len);
+        let _ = std::fs::remove_file(&path);
+
+        // Either the file system tracks holes, or the whole file is data
+        let holes = holes.unwrap();
+        assert!(holes == 0 || holes >= len / 2, "unexpected hole bytes: {}", holes);
+        assert!(holes < len);
+    }
+}
@@ -0,0 +1,321 @@
+//! Temp files the sorted result buffer and the group accumulators spill to
+//! once they outgrow the `--max-memory` budget. Each spill writes one sorted
+//! run, and the runs are merged back when the results are output.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Lines, Write};
+use std::marker::PhantomData;
+use std::path::PathBuf;
+use std::sync::atomic::{self, AtomicUsize};
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use crate::util::error::SearchError;
+
+static RUN_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Rough number of bytes a value takes in memory.
+pub trait MemSize {
+    fn mem_size(&self) -> usize;
+}
+
+impl MemSize for String {
+    fn mem_size(&self) -> usize {
+        size_of::<String>() + self.capacity()
+    }
+}
+
+impl<T: MemSize> MemSize for Vec<T> {
+    fn mem_size(&self) -> usize {
+        size_of::<Vec<T>>() + self.iter().map(MemSize::mem_size).sum::<usize>()
+    }
+}
+
+impl<A: MemSize, B: MemSize> MemSize for (A, B) {
+    fn mem_size(&self) -> usize {
+        self.0.mem_size() + self.1.mem_size()
+    }
+}
+
+/// Most runs read side by side while merging, so that a search spilling
+/// many times never runs out of file descriptors.
+const MAX_OPEN_RUNS: usize = 64;
+
+/// Sorted sequence of records, read from a run or from memory
+pub type Records<'a, T> = Box<dyn Iterator<Item = io::Result<T>> + 'a>;
+
+/// Sorted records in a temp file, one JSON document per line. The file is
+/// removed when the run is dropped.
+#[derive(Debug)]
+pub struct SpillRun {
+    path: PathBuf,
+}
+
+impl SpillRun {
+    pub fn write<T: Serialize>(records: impl IntoIterator<Item = T>) -> io::Result<SpillRun> {
+        SpillRun::try_write(records.into_iter().map(Ok))
+    }
+
+    /// Writes the records, giving up at the first one that couldn't be read.
+    pub fn try_write<T: Serialize>(records: impl Iterator<Item = io::Result<T>>) -> io::Result<SpillRun> {
+        let counter = RUN_COUNTER.fetch_add(1, atomic::Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!("fselect-spill-{}-{}", std::process::id(), counter));
+        // Owning the path from the start removes the file if writing fails
+        let run = SpillRun { path };
+
+        let mut writer = BufWriter::new(File::create(&run.path)?);
+        for record in records {
+            serde_json::to_writer(&mut writer, &record?)?;
+            writer.write_all(b"\n")?;
+        }
+        writer.flush()?;
+
+        Ok(run)
+    }
+
+    /// Reads the records back in the order they were written. A run that
+    /// can't be read yields the error and ends.
+    pub fn records<T: DeserializeOwned>(&self) -> SpillRecords<T> {
+        let (lines, error) = match File::open(&self.path) {
+            Ok(file) => (Some(BufReader::new(file).lines()), None),
+            Err(err) => (None, Some(err)),
+        };
+
+        SpillRecords { lines, error, record: PhantomData }
+    }
+}
+
+impl Drop for SpillRun {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+pub struct SpillRecords<T> {
+    lines: Option<Lines<BufReader<File>>>,
+    error: Option<io::Error>,
+    record: PhantomData<T>,
+}
+
+impl<T: DeserializeOwned> Iterator for SpillRecords<T> {
+    type Item = io::Result<T>;
+
+    fn next(&mut self) -> Option<io::Result<T>> {
+        if let Some(err) = self.error.take() {
+            return Some(Err(err));
+        }
+
+        let line = self.lines.as_mut()?.next()?;
+        let record = line.and_then(|line| Ok(serde_json::from_str(&line)?));
+        if record.is_err() {
+            self.lines = None;
+        }
+
+        Some(record)
+    }
+}
+
+/// The error of a spilled run that couldn't be written or read back. The
+/// results would be incomplete, so the search fails.
+pub fn spill_error(err: io::Error) -> SearchError {
+    SearchError::fatal(err.to_string()).with_source("spill file")
+}
+
+/// Merges sorted sequences into one. Of equal items, those from earlier
+/// sequences come first, so merging runs in the order they were written
+/// keeps the sort stable. The merge ends with the first error of a sequence.
+pub fn merge_sorted<'a, T: 'a>(
+    mut sources: Vec<Records<'a, T>>,
+    cmp: impl Fn(&T, &T) -> Ordering + 'a,
+) -> impl Iterator<Item = io::Result<T>> + 'a {
+    // There are few runs, so a linear scan for the smallest head will do
+    let mut heads: Vec<Option<io::Result<T>>> = sources.iter_mut().map(|source| source.next()).collect();
+    let mut failed = false;
+
+    std::iter::from_fn(move || {
+        if failed {
+            return None;
+        }
+
+        let mut smallest: Option<usize> = None;
+        for (i, head) in heads.iter().enumerate() {
+            match head {
+                Some(Err(_)) => {
+                    smallest = Some(i);
+                    break;
+                }
+                Some(Ok(head))
+                    if smallest.is_none_or(|s| matches!(&heads[s], Some(Ok(other)) if cmp(head, other) == Ordering::Less)) =>
+                {
+                    smallest = Some(i);
+                }
+                _ => {}
+            }
+        }
+
+        let smallest = smallest?;
+        let head = std::mem::replace(&mut heads[smallest], sources[smallest].next());
+        failed = matches!(head, Some(Err(_)));
+        head
+    })
+}
+
+/// Records of the runs, one sequence per run.
+pub fn run_sources<'a, T: DeserializeOwned + 'a>(runs: &'a [SpillRun]) -> Vec<Records<'a, T>> {
+    runs.iter()
+        .map(|run| Box::new(run.records()) as Records<T>)
+        .collect()
+}
+
+/// Merges the runs, `MAX_OPEN_RUNS` at a time, into fewer and bigger ones
+/// until no more than `MAX_OPEN_RUNS` are left. `merge` writes a batch of
+/// runs out as one. Neighbouring runs are merged together, so the runs stay
+/// in the order they were written.
+pub fn reduce_runs(runs: &mut Vec<SpillRun>, merge: impl Fn(&[SpillRun]) -> io::Result<SpillRun>) -> io::Result<()> {
+    while runs.len() > MAX_OPEN_RUNS {
+        let mut merged = Vec::with_capacity(runs.len().div_ceil(MAX_OPEN_RUNS));
+        for batch in runs.chunks(MAX_OPEN_RUNS) {
+            merged.push(merge(batch)?);
+        }
+        *runs = merged;
+    }
+
+    Ok(())
+}
+
+/// Yields every group once, combining its accumulators from the spilled
+/// runs and from memory. Without runs the groups come in no particular order.
+pub fn merge_groups<'a, K, A>(
+    in_memory: HashMap<K, A>,
+    runs: &'a mut Vec<SpillRun>,
+    combine: impl Fn(&mut A, A) + Copy + 'a,
+) -> io::Result<Records<'a, (K, A)>>
+where
+    K: Ord + Serialize + DeserializeOwned + 'a,
+    A: Serialize + DeserializeOwned + 'a,
+{
+    if runs.is_empty() {
+        return Ok(Box::new(in_memory.into_iter().map(Ok)));
+    }
+
+    reduce_runs(runs, |batch| SpillRun::try_write(combine_groups::<K, A>(run_sources(batch), combine)))?;
+
+    let mut in_memory: Vec<(K, A)> = in_memory.into_iter().collect();
+    in_memory.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut sources = run_sources(runs);
+    sources.push(Box::new(in_memory.into_iter().map(Ok)));
+
+    Ok(combine_groups(sources, combine))
+}
+
+/// Merges sequences of groups sorted by key, combining the groups of the same key.
+fn combine_groups<'a, K: Ord + 'a, A: 'a>(
+    sources: Vec<Records<'a, (K, A)>>,
+    combine: impl Fn(&mut A, A) + 'a,
+) -> Records<'a, (K, A)> {
+    let mut merged = merge_sorted(sources, |a: &(K, A), b: &(K, A)| a.0.cmp(&b.0)).peekable();
+    Box::new(std::iter::from_fn(move || {
+        let (key, mut acc) = match merged.next()? {
+            Ok(group) => group,
+            Err(err) => return Some(Err(err)),
+        };
+        while let Some(Ok((_, other))) = merged.next_if(|next| matches!(next, Ok((next_key, _)) if *next_key == key)) {
+            combine(&mut acc, other);
+        }
+        Some(Ok((key, acc)))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spill_run_round_trip() {
+        let records = vec![(vec![String::from("a\nb")], 1), (vec![], 2)];
+        let run = SpillRun::write(&records).unwrap();
+        let path = run.path.clone();
+
+        let read: Vec<(Vec<String>, i32)> = run.records().collect::<io::Result<_>>().unwrap();
+        assert_eq!(read, records);
+
+        drop(run);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_merge_sorted_is_stable() {
+        let sources: Vec<Records<(i32, &str)>> = vec![
+            Box::new(vec![(1, "a"), (3, "a"), (5, "a")].into_iter().map(Ok)),
+            Box::new(vec![(1, "b"), (2, "b"), (6, "b")].into_iter().map(Ok)),
+            Box::new(vec![].into_iter().map(Ok)),
+            Box::new(vec![(3, "c")].into_iter().map(Ok)),
+        ];
+
+        let merged: Vec<(i32, &str)> = merge_sorted(sources, |a, b| a.0.cmp(&b.0)).collect::<io::Result<_>>().unwrap();
+        assert_eq!(merged, vec![(1, "a"), (1, "b"), (2, "b"), (3, "a"), (3, "c"), (5, "a"), (6, "b")]);
+    }
+
+    #[test]
+    fn test_merge_groups() {
+        let mut runs = vec![
+            SpillRun::write([("a", 1), ("c", 2)]).unwrap(),
+            SpillRun::write([("b", 4), ("c", 8)]).unwrap(),
+        ];
+        let in_memory = HashMap::from([(String::from("c"), 16), (String::from("d"), 32)]);
+
+        let merged: Vec<(String, i32)> = merge_groups(in_memory, &mut runs, |a, b| *a += b)
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(
+            merged,
+            vec![
+                (String::from("a"), 1),
+                (String::from("b"), 4),
+                (String::from("c"), 26),
+                (String::from("d"), 32)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_many_group_runs_merge_in_passes() {
+        let mut runs: Vec<SpillRun> = (0..150)
+            .map(|i| SpillRun::write([(format!("{:03}", i % 100), 1), (String::from("zzz"), i)]).unwrap())
+            .collect();
+
+        let merged: Vec<(String, i32)> = merge_groups(HashMap::new(), &mut runs, |a, b| *a += b)
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert!(runs.len() <= MAX_OPEN_RUNS);
+        assert_eq!(merged.len(), 101);
+        assert_eq!(merged[0], (String::from("000"), 2));
+        assert_eq!(merged[99], (String::from("099"), 1));
+        assert_eq!(merged[100], (String::from("zzz"), (0..150).sum()));
+    }
+
+    #[test]
+    fn test_unreadable_run_ends_with_error() {
+        let corrupt = SpillRun::write([("a", 1), ("c", 3)]).unwrap();
+        fs::write(&corrupt.path, "[\"a\",1]\nnot json\n[\"c\",3]\n").unwrap();
+        let missing = SpillRun::write([("b", 2)]).unwrap();
+        fs::remove_file(&missing.path).unwrap();
+
+        let mut records = corrupt.records::<(String, i32)>();
+        assert_eq!(records.next().unwrap().unwrap(), (String::from("a"), 1));
+        assert!(records.next().unwrap().is_err());
+        assert!(records.next().is_none());
+        assert!(missing.records::<(String, i32)>().next().unwrap().is_err());
+
+        let runs = [corrupt, missing];
+        let merged: Vec<io::Result<(String, i32)>> = merge_sorted(run_sources(&runs), |a: &(String, i32), b| a.0.cmp(&b.0)).collect();
+        assert_eq!(merged.len(), 1);
+        assert!(merged[0].is_err());
+    }
+}
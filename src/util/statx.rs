@@ -0,0 +1,246 @@
+//! Linux fast path for file metadata: `statx` relative to an open directory
+//! file descriptor, requesting only the attributes the query needs.
+
+use std::ffi::{CString, OsStr};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::RawFd;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::field::Field;
+
+pub const STATX_TYPE: u32 = 0x0001;
+pub const STATX_MODE: u32 = 0x0002;
+pub const STATX_NLINK: u32 = 0x0004;
+pub const STATX_UID: u32 = 0x0008;
+pub const STATX_GID: u32 = 0x0010;
+pub const STATX_MTIME: u32 = 0x0040;
+pub const STATX_INO: u32 = 0x0100;
+pub const STATX_SIZE: u32 = 0x0200;
+pub const STATX_BLOCKS: u32 = 0x0400;
+
+const AT_SYMLINK_NOFOLLOW: libc::c_int = 0x100;
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct StatxTimestamp {
+    tv_sec: i64,
+    tv_nsec: u32,
+    reserved: i32,
+}
+
+/// `struct statx` from linux/stat.h. Declared here rather than taken from
+/// `libc`, which only exposes it for some C libraries.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct RawStatx {
+    stx_mask: u32,
+    stx_blksize: u32,
+    stx_attributes: u64,
+    stx_nlink: u32,
+    stx_uid: u32,
+    stx_gid: u32,
+    stx_mode: u16,
+    spare0: u16,
+    stx_ino: u64,
+    stx_size: u64,
+    stx_blocks: u64,
+    stx_attributes_mask: u64,
+    stx_atime: StatxTimestamp,
+    stx_btime: StatxTimestamp,
+    stx_ctime: StatxTimestamp,
+    stx_mtime: StatxTimestamp,
+    stx_rdev_major: u32,
+    stx_rdev_minor: u32,
+    stx_dev_major: u32,
+    stx_dev_minor: u32,
+    spare2: [u64; 14],
+}
+
+/// Result of a `statx` call. Only the attributes listed in its mask are valid.
+#[derive(Clone, Copy)]
+pub struct Statx {
+    raw: RawStatx,
+}
+
+impl Statx {
+    /// Whether all the attributes in `mask` were filled in by the kernel.
+    pub fn has(&self, mask: u32) -> bool {
+        self.raw.stx_mask & mask == mask
+    }
+
+    pub fn size(&self) -> u64 {
+        self.raw.stx_size
+    }
+
+    pub fn blocks(&self) -> u64 {
+        self.raw.stx_blocks
+    }
+
+    pub fn ino(&self) -> u64 {
+        self.raw.stx_ino
+    }
+
+    pub fn nlink(&self) -> u64 {
+        self.raw.stx_nlink as u64
+    }
+
+    pub fn uid(&self) -> u32 {
+        self.raw.stx_uid
+    }
+
+    pub fn gid(&self) -> u32 {
+        self.raw.stx_gid
+    }
+
+    /// File type and permission bits, like `st_mode`.
+    pub fn mode(&self) -> u32 {
+        self.raw.stx_mode as u32
+    }
+
+    pub fn modified(&self) -> SystemTime {
+        let ts = self.raw.stx_mtime;
+        let secs = Duration::from_secs(ts.tv_sec.unsigned_abs());
+        let epoch = match ts.tv_sec >= 0 {
+            true => UNIX_EPOCH + secs,
+            false => UNIX_EPOCH - secs,
+        };
+        epoch + Duration::from_nanos(ts.tv_nsec as u64)
+    }
+}
+
+/// Error of the `statx` call: `Unsupported` means the kernel lacks the
+/// syscall and the caller should stop trying.
+pub enum StatxError {
+    Unsupported,
+    Failed,
+}
+
+/// Calls `statx` for `name` relative to the directory `dir_fd`, so the kernel
+/// doesn't resolve the full path again for every entry.
+pub fn statx_at(dir_fd: RawFd, name: &OsStr, follow_symlinks: bool, mask: u32) -> Result<Statx, StatxError> {
+    let name = CString::new(name.as_bytes()).map_err(|_| StatxError::Failed)?;
+    let flags = if follow_symlinks { 0 } else { AT_SYMLINK_NOFOLLOW };
+    let mut raw = RawStatx::default();
+
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_statx,
+            dir_fd as libc::c_long,
+            name.as_ptr(),
+            flags as libc::c_long,
+            mask as libc::c_long,
+            &mut raw as *mut RawStatx,
+        )
+    };
+
+    if ret == 0 {
+        return Ok(Statx { raw });
+    }
+
+    match io::Error::last_os_error().raw_os_error() {
+        Some(libc::ENOSYS) => Err(StatxError::Unsupported),
+        _ => Err(StatxError::Failed),
+    }
+}
+
+/// Attributes a field needs from `statx`: `Some(0)` for fields that need no
+/// metadata at all, `None` for fields served only by the full metadata.
+fn field_mask(field: &Field) -> Option<u32> {
+    match field {
+        Field::Name
+        | Field::Filename
+        | Field::Extension
+        | Field::Path
+        | Field::AbsPath
+        | Field::Directory
+        | Field::AbsDir
+        | Field::IsDir
+        | Field::IsFile
+        | Field::IsSymlink
+        | Field::ArchivePath
+        | Field::EntryPath
+        | Field::CompressedSize
+        | Field::CompressionRatio
+        | Field::CompressionMethod
+        | Field::IsEncrypted
+        | Field::ArchiveEntryCount
+        | Field::ArchiveUncompressedSize => Some(0),
+        Field::Size | Field::FormattedSize => Some(STATX_SIZE),
+        Field::AllocatedSize | Field::Blocks => Some(STATX_BLOCKS),
+        Field::Inode => Some(STATX_INO),
+        Field::Hardlinks => Some(STATX_NLINK),
+        Field::Uid => Some(STATX_UID),
+        Field::Gid => Some(STATX_GID),
+        Field::Mode => Some(STATX_TYPE | STATX_MODE),
+        Field::Modified => Some(STATX_MTIME),
+        _ => None,
+    }
+}
+
+/// Computes the `statx` mask for a set of fields. Zero disables the fast
+/// path: either no metadata is needed, or some field needs the full
+/// metadata anyway and a second stat call would only add to the cost.
+pub fn statx_mask_for_fields<'a>(fields: impl IntoIterator<Item = &'a Field>) -> u32 {
+    let mut mask = 0;
+
+    for field in fields {
+        match field_mask(field) {
+            Some(field_mask) => mask |= field_mask,
+            None => return 0,
+        }
+    }
+
+    mask
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::os::unix::fs::MetadataExt;
+    use std::os::unix::io::AsRawFd;
+
+    #[test]
+    fn test_raw_statx_layout() {
+        assert_eq!(std::mem::size_of::<RawStatx>(), 256);
+    }
+
+    #[test]
+    fn test_statx_mask_for_fields() {
+        assert_eq!(statx_mask_for_fields(&[Field::Name, Field::Size]), STATX_SIZE);
+        assert_eq!(
+            statx_mask_for_fields(&[Field::Size, Field::Modified, Field::Uid]),
+            STATX_SIZE | STATX_MTIME | STATX_UID
+        );
+        assert_eq!(statx_mask_for_fields(&[Field::Path]), 0);
+        assert_eq!(statx_mask_for_fields(&[Field::Size, Field::Created]), 0);
+    }
+
+    #[test]
+    fn test_statx_at_matches_metadata() {
+        let tmp = std::env::temp_dir().join("fselect_test_statx_at");
+        let _ = fs::remove_dir_all(&tmp);
+        fs::create_dir_all(&tmp).unwrap();
+        fs::write(tmp.join("file.txt"), "twelve bytes").unwrap();
+
+        let dir = fs::File::open(&tmp).unwrap();
+        let mask = STATX_SIZE | STATX_INO | STATX_MTIME | STATX_MODE | STATX_TYPE;
+        let result = statx_at(dir.as_raw_fd(), OsStr::new("file.txt"), false, mask);
+        let metadata = fs::metadata(tmp.join("file.txt")).unwrap();
+        let _ = fs::remove_dir_all(&tmp);
+
+        match result {
+            Ok(st) => {
+                assert!(st.has(STATX_SIZE | STATX_INO));
+                assert_eq!(st.size(), 12);
+                assert_eq!(st.ino(), metadata.ino());
+                assert_eq!(st.mode(), metadata.mode());
+                assert_eq!(st.modified(), metadata.modified().unwrap());
+            }
+            // Kernels older than 4.11 have no statx
+            Err(StatxError::Unsupported) => {}
+            Err(StatxError::Failed) => panic!("statx failed"),
+        }
+    }
+}
@@ -1,10 +1,31 @@
 use std::collections::BTreeMap;
+use std::io;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use crate::util::error::error_message;
+use crate::util::spill::{merge_sorted, reduce_runs, MemSize, Records, SpillRun};
+
+/// Key of a buffer that can spill to disk: its values are written along with
+/// the buffered value, and the key is rebuilt from them when reading back.
+pub trait SpillKey: Ord + Clone {
+    fn values(&self) -> &[String];
+    /// Key of the same kind, holding other values
+    fn with_values(&self, values: Vec<String>) -> Self;
+}
 
 #[derive(Debug)]
 pub struct TopN<K: Ord, V> {
     limit: Option<u32>,
     count: u32,
     echelons: BTreeMap<K, Vec<V>>,
+    /// Bytes the entries of a limitless buffer may take before they spill to disk
+    memory_limit: Option<usize>,
+    memory_used: usize,
+    runs: Vec<SpillRun>,
+    /// Any key seen, to rebuild the keys of spilled entries with
+    key_template: Option<K>,
 }
 
 impl<K: Ord, V> TopN<K, V> {
@@ -14,6 +35,10 @@ impl<K: Ord, V> TopN<K, V> {
             limit: Some(limit),
             count: 0,
             echelons: BTreeMap::new(),
+            memory_limit: None,
+            memory_used: 0,
+            runs: vec![],
+            key_template: None,
         }
     }
 
@@ -22,7 +47,21 @@ impl<K: Ord, V> TopN<K, V> {
             limit: None,
             count: 0,
             echelons: BTreeMap::new(),
+            memory_limit: None,
+            memory_used: 0,
+            runs: vec![],
+            key_template: None,
+        }
+    }
+
+    /// Makes a limitless buffer spill its entries to disk beyond the given
+    /// number of bytes, zero meaning no limit. A TopN with a limit holds only
+    /// that many entries anyway.
+    pub fn with_memory_limit(mut self, bytes: usize) -> TopN<K, V> {
+        if self.limit.is_none() && bytes > 0 {
+            self.memory_limit = Some(bytes);
         }
+        self
     }
 
     pub fn insert(&mut self, k: K, v: V) -> Option<V>
@@ -50,6 +89,11 @@ impl<K: Ord, V> TopN<K, V> {
         self.echelons.values().flatten()
     }
 
+    #[cfg(test)]
+    pub fn spilled_runs(&self) -> usize {
+        self.runs.len()
+    }
+
     #[cfg(test)]
     pub fn values(&self) -> Vec<V>
     where
@@ -59,6 +103,96 @@ impl<K: Ord, V> TopN<K, V> {
     }
 }
 
+impl<K, V> TopN<K, V>
+where
+    K: SpillKey,
+    V: MemSize + Serialize + DeserializeOwned + Clone,
+{
+    /// Inserts like `insert`, writing the entries out as a sorted run when
+    /// they take more memory than allowed.
+    pub fn insert_or_spill(&mut self, k: K, v: V) -> Option<V> {
+        let memory_limit = match self.memory_limit {
+            Some(memory_limit) => memory_limit,
+            None => return self.insert(k, v),
+        };
+
+        self.memory_used += size_of::<K>() + k.values().iter().map(String::mem_size).sum::<usize>() + v.mem_size();
+        if self.key_template.is_none() {
+            self.key_template = Some(k.clone());
+        }
+        let popped = self.insert(k, v);
+
+        if self.memory_used > memory_limit {
+            self.spill();
+        }
+
+        popped
+    }
+
+    fn spill(&mut self) {
+        let records = self
+            .echelons
+            .iter()
+            .flat_map(|(k, values)| values.iter().map(move |v| (k.values(), v)));
+
+        match SpillRun::write(records) {
+            Ok(run) => {
+                self.runs.push(run);
+                self.echelons.clear();
+                self.memory_used = 0;
+            }
+            Err(err) => {
+                // Carry on within memory rather than lose the results
+                error_message("spill file", &err.to_string());
+                self.memory_limit = None;
+            }
+        }
+    }
+
+    /// All the values in order, merging the spilled runs with the entries
+    /// still in memory. Runs that can't be read back end the values with an error.
+    pub fn sorted_values(&mut self) -> io::Result<Records<'_, V>> {
+        let key_template = match &self.key_template {
+            Some(key_template) if !self.runs.is_empty() => key_template.clone(),
+            _ => return Ok(Box::new(self.iter_values().cloned().map(Ok))),
+        };
+
+        reduce_runs(&mut self.runs, |batch| {
+            let sources = keyed_sources(batch, &key_template);
+            SpillRun::try_write(
+                merge_sorted(sources, |a: &(K, V), b: &(K, V)| a.0.cmp(&b.0))
+                    .map(|record| record.map(|(k, v)| (k.values().to_vec(), v))),
+            )
+        })?;
+
+        let mut sources = keyed_sources(&self.runs, &key_template);
+        sources.push(Box::new(
+            self.echelons
+                .iter()
+                .flat_map(|(k, values)| values.iter().map(move |v| Ok((k.clone(), v.clone())))),
+        ));
+
+        Ok(Box::new(merge_sorted(sources, |a: &(K, V), b: &(K, V)| a.0.cmp(&b.0)).map(|record| record.map(|(_, v)| v))))
+    }
+}
+
+/// Records of the runs with their keys rebuilt, one sequence per run.
+fn keyed_sources<'a, K, V>(runs: &'a [SpillRun], key_template: &K) -> Vec<Records<'a, (K, V)>>
+where
+    K: SpillKey + 'a,
+    V: DeserializeOwned + 'a,
+{
+    runs.iter()
+        .map(|run| {
+            let key_template = key_template.clone();
+            let records = run
+                .records::<(Vec<String>, V)>()
+                .map(move |record| record.map(|(values, v)| (key_template.with_values(values), v)));
+            Box::new(records) as Records<(K, V)>
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -131,4 +265,53 @@ mod tests {
         top_n.insert("a", 0);
         assert_eq!(top_n.values(), vec![1, 0, 2, 3]);
     }
+
+    #[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+    struct Key(Vec<String>);
+
+    impl SpillKey for Key {
+        fn values(&self) -> &[String] {
+            &self.0
+        }
+
+        fn with_values(&self, values: Vec<String>) -> Self {
+            Key(values)
+        }
+    }
+
+    #[test]
+    fn test_limitless_spills_and_merges_in_order() {
+        let key = |s: &str| Key(vec![s.to_string()]);
+        let mut top_n = TopN::limitless().with_memory_limit(200);
+        for (k, v) in [("m", "1"), ("c", "2"), ("x", "3"), ("c", "4"), ("a", "5"), ("m", "6"), ("b", "7")] {
+            top_n.insert_or_spill(key(k), v.to_string());
+        }
+
+        assert!(top_n.spilled_runs() > 0);
+        assert_eq!(top_n.sorted_values().unwrap().collect::<io::Result<Vec<_>>>().unwrap(), vec!["5", "7", "2", "4", "1", "6", "3"]);
+    }
+
+    #[test]
+    fn test_memory_limit_ignored_with_limit() {
+        let mut top_n = TopN::new(2).with_memory_limit(1);
+        top_n.insert_or_spill(Key(vec![String::from("b")]), String::from("2"));
+        top_n.insert_or_spill(Key(vec![String::from("a")]), String::from("1"));
+        top_n.insert_or_spill(Key(vec![String::from("c")]), String::from("3"));
+
+        assert_eq!(top_n.spilled_runs(), 0);
+        assert_eq!(top_n.sorted_values().unwrap().collect::<io::Result<Vec<_>>>().unwrap(), vec!["1", "2"]);
+    }
+
+    #[test]
+    fn test_many_runs_merge_in_passes() {
+        let mut top_n = TopN::limitless().with_memory_limit(1);
+        for i in 0..200 {
+            top_n.insert_or_spill(Key(vec![format!("{:03}", (i * 7) % 200)]), i.to_string());
+        }
+        assert_eq!(top_n.spilled_runs(), 200);
+
+        let expected: Vec<String> = (0..200).map(|k| ((k * 143) % 200).to_string()).collect();
+        assert_eq!(top_n.sorted_values().unwrap().collect::<io::Result<Vec<_>>>().unwrap(), expected);
+        assert!(top_n.spilled_runs() <= 64);
+    }
 }